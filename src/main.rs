@@ -3,27 +3,58 @@
 use lazy_static::lazy_static;
 use structopt::StructOpt;
 use strum_macros::EnumString;
-use std::path::Path;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use ssh_key::{Algorithm, EcdsaCurve, HashAlg, PublicKey};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::os::fd::AsFd;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::ffi::{OsStr, OsString};
+use std::str::FromStr;
+use std::path::{Path, PathBuf};
+use std::cell::RefCell;
 use std::ops::Deref;
-use std::io;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::thread;
 
 use path_absolutize::Absolutize;
-use std::process::{Command, ExitStatus};
-use execute::Execute;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 
 // ***************************************************************************
 //                             Constants
 // ***************************************************************************
-const TMSADM_INFO: &str = concat!("
+const TMSADM_INFO: &str = "
 The tmsadm program provides administrative access to the TMS Server's Sqlite 
 database from the command line. Access to this program should be limited to 
 those that can logon to the TMS Server machine.  Administrators can list or 
-delete records from several database tables. 
+delete records from several database tables, export records to files, run
+reports, import and validate pubkeys, and initialize a new database with the
+TMS tables.
 
 The sqlite3 program must be on the PATH for execution to succeed.
-----------------------------------------------------------------------------");
 
-const DEBUG: bool = false;
+The operation and resource can also be given as subcommands, so that
+'tmsadm list pubkey --host myhost' is the same as 'tmsadm --operation LIST
+--resource pubkey --host myhost', and 'tmsadm count client' counts clients.
+
+SQL statements are not echoed in the output unless --echo is given. Earlier
+versions echoed them by default; --echo-off is still accepted but does nothing.
+----------------------------------------------------------------------------";
+
+// Database path used when --dbpath isn't given.  Packagers can set the 
+// TMSADM_DEFAULT_DBPATH environment variable when building to change it.
+const DEFAULT_DBPATH: &str = match option_env!("TMSADM_DEFAULT_DBPATH") {
+    Some(p) => p,
+    None => "~/.tms/database/tms.db",
+};
 
 // Sqlite command line program that we call to access the database.
 // Usage: sqlite3 [OPTIONS] FILENAME [SQL]
@@ -31,6 +62,17 @@ const DEBUG: bool = false;
 //   if the file does not previously exist, which we short-circuit.
 const SQLITE3: &str = "sqlite3";
 
+// The first 16 bytes of every sqlite database file.
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+// SQLCipher's sqlite3 compatible command line program, used for encrypted 
+// databases.
+const SQLCIPHER: &str = "sqlcipher";
+
+// The oldest sqlite3 that tmsadm works with, which is the first with the 
+// -json output mode that tmsadm reads query results in.
+const MIN_SQLITE_VERSION: (u32, u32, u32) = (3, 33, 0);
+
 // SQL command prototypes.
 const LIST_PUBKEY:       &str = "SELECT * FROM pubkeys ";
 const LIST_CLIENT:       &str = "SELECT * FROM clients ";
@@ -39,14 +81,199 @@ const DELETE_PUBKEY:     &str = "DELETE FROM pubkeys ";
 const DELETE_CLIENT:     &str = "DELETE FROM clients ";
 const DELETE_DELEGATION: &str = "DELETE FROM delegations ";
 
+// Delegations that reference a client that doesn't exist.
+const LIST_ORPHAN_DELEGATION: &str = "SELECT d.* FROM delegations d LEFT JOIN clients c \
+    ON d.client_id = c.client_id AND d.tenant = c.tenant WHERE c.id IS NULL ";
+const DELETE_ORPHAN_DELEGATION: &str = "DELETE FROM delegations WHERE NOT EXISTS \
+    (SELECT 1 FROM clients c WHERE c.client_id = delegations.client_id AND c.tenant = delegations.tenant) ";
+
+// Schema created by INIT, which matches the TMS Server's tables.
+const CREATE_TABLES: &str = "\
+CREATE TABLE IF NOT EXISTS clients (
+    id INTEGER PRIMARY KEY NOT NULL,
+    tenant TEXT NOT NULL,
+    app_name TEXT NOT NULL,
+    app_version TEXT NOT NULL,
+    client_id TEXT NOT NULL,
+    client_secret TEXT NOT NULL,
+    tms_user_id TEXT,
+    enabled INTEGER NOT NULL DEFAULT 1,
+    created TEXT NOT NULL,
+    updated TEXT NOT NULL,
+    UNIQUE (client_id, tenant));
+CREATE TABLE IF NOT EXISTS pubkeys (
+    id INTEGER PRIMARY KEY NOT NULL,
+    tenant TEXT NOT NULL,
+    client_id TEXT NOT NULL,
+    tms_user_id TEXT NOT NULL,
+    host TEXT NOT NULL,
+    host_account TEXT NOT NULL,
+    public_key_fingerprint TEXT NOT NULL,
+    public_key TEXT NOT NULL,
+    key_type TEXT NOT NULL,
+    key_bits INTEGER NOT NULL,
+    max_uses INTEGER NOT NULL,
+    remaining_uses INTEGER NOT NULL,
+    initial_ttl_minutes INTEGER NOT NULL,
+    expires_at TEXT NOT NULL,
+    created TEXT NOT NULL,
+    updated TEXT NOT NULL);
+CREATE TABLE IF NOT EXISTS delegations (
+    id INTEGER PRIMARY KEY NOT NULL,
+    tenant TEXT NOT NULL,
+    client_id TEXT NOT NULL,
+    tms_user_id TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    created TEXT NOT NULL,
+    updated TEXT NOT NULL,
+    UNIQUE (tenant, client_id, tms_user_id));";
+
+// Tables that every TMS database contains.
+const TMS_TABLES: [&str; 3] = ["pubkeys", "clients", "delegations"];
+
+// Tables that record the schema version, in order of preference.  The TMS
+// Server's migrations are applied by sqlx.
+const SCHEMA_VERSION_TABLES: [&str; 3] = ["_sqlx_migrations", "schema_version", "migrations"];
+
+// Delegations whose expires_at time has passed, which --expire-cleanup deletes.
+const EXPIRED_CONDITION: &str = "julianday(expires_at) < julianday('now')";
+
+// Tables purged by --purge-user in dependency order.
+const PURGE_TABLES: [&str; 3] = ["delegations", "pubkeys", "clients"];
+
+// Permission names for the modes that change the database without an --operation.
+const PSEUDO_OPERATIONS: [&str; 3] = ["BATCH", "UNDO", "SET-PRAGMA"];
+
+// Columns used by the shortcut filters and --purge-user.
+const USER_COLUMN:   &str = "tms_user_id";
+const HOST_COLUMN:   &str = "host";
+const TENANT_COLUMN: &str = "tenant";
+const CLIENT_ID_COLUMN: &str = "client_id";
+const CREATED_COLUMN: &str = "created";
+
+// Column marked by --soft-delete.
+const DELETED_COLUMN: &str = "deleted_at";
+
+// Value shown in place of a column's value by the redact --col-transform.
+const REDACTED: &str = "[REDACTED]";
+
+// Exit code used when the user interrupts tmsadm with Ctrl-C.
+const EXIT_ABORTED: i32 = 130;
+
+// Exit code used by --fail-on-empty when a read operation returns no rows.
+const EXIT_EMPTY: i32 = 3;
+
+// Exit code used by --fail-on-rows when a read operation returns rows.
+const EXIT_ROWS: i32 = 4;
+
+// Exit code used when a confirmation prompt gets no answer within the 
+// --prompt-timeout.
+const EXIT_PROMPT_TIMEOUT: i32 = 5;
+
+// Exit code used by --healthcheck when the database can't be read.
+const EXIT_UNHEALTHY: i32 = 6;
+
+// Exit code used by --require-deletes when a DELETE operation deletes nothing.
+const EXIT_NO_DELETES: i32 = 7;
+
+// The PRAGMAs that --show-pragmas prints.
+const SHOW_PRAGMAS: [&str; 5] = ["page_size", "journal_mode", "cache_size", "auto_vacuum", "user_version"];
+
+// The PRAGMAs that --set-pragma can change, which are stored in the database
+// file.  Changes to the first two only take effect after a VACUUM.
+const SET_PRAGMAS: [&str; 4] = ["page_size", "auto_vacuum", "journal_mode", "user_version"];
+
+// Set for the commands that --batch runs, which are given the batch's lock 
+// file as stdin.
+const BATCH_LOCK_ENV: &str = "TMSADM_BATCH_LOCK_HELD";
+
 // ***************************************************************************
 //                             Static Variables
 // ***************************************************************************
+// Process ids of running sqlite3 children, terminated on interrupt.
+static CHILD_PIDS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+// Set while --all-pages fetches pages after the first, whose output omits the
+// echoed SQL and column headers already written for the first page.
+static CONTINUED_PAGE: AtomicBool = AtomicBool::new(false);
+
+// The tables known to have, or not have, the deleted_at column.
+static DELETED_COLUMN_TABLES: Mutex<Vec<(String, bool)>> = Mutex::new(Vec::new());
+
+// The index of the --resource being processed.
+static RESOURCE_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+// The number of rows returned by commands run for the user.
+static ROW_COUNT: AtomicU64 = AtomicU64::new(0);
+
+// The WHERE clause written with --edit-where, which is used along with the
+// --sqlwhere clauses.
+static EDITED_WHERE: Mutex<Option<String>> = Mutex::new(None);
+
+// The number of records changed by a mutation, once it's known.
+static AFFECTED: Mutex<Option<u64>> = Mutex::new(None);
+
+// The number of records changed by all of the operation's mutations.
+static AFFECTED_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+// The file that passes the --key to sqlcipher, once it's been written.
+static KEY_FILE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+// Set when a failed sqlite3 command is about to panic, so that --errors-json
+// can report a database error.
+static SQLITE_FAILED: AtomicBool = AtomicBool::new(false);
+
+// Runs the thread's sqlite3 commands.  Tests replace it with a CommandRunner
+// double so that they run without an sqlite3 binary.
+thread_local! {
+    static COMMAND_RUNNER: RefCell<&'static dyn CommandRunner> = const {RefCell::new(&ProcessRunner)};
+}
+
+// Whether --no-absolutize is on the command line, which is checked before the
+// arguments are parsed because parsing them resolves paths.
+lazy_static! {
+    static ref NO_ABSOLUTIZE: bool = std::env::args_os().any(|a| a == "--no-absolutize");
+}
+
+// When tmsadm started, for --summary-json.
+lazy_static! {
+    static ref START: Instant = Instant::now();
+}
+
 // Assign the command line arguments BEFORE RUNTIME_CTX is initialized in main.
 lazy_static! {
     pub static ref TMSADM_ARGS: TmsadmArgs = init_tmsadm_args();
 }
 
+// The --output file that read operations other than EXPORT write their 
+// results to instead of stdout, once it's been opened.
+lazy_static! {
+    static ref OUTPUT_FILE: Mutex<Option<File>> = Mutex::new(None);
+}
+
+// The version of the sqlite3 program that tmsadm runs, once it's been asked.
+lazy_static! {
+    static ref SQLITE_VERSION: String = read_sqlite_version(sqlite3_program());
+}
+
+// The configuration file's sanitize rules, which --sanitize applies.
+lazy_static! {
+    static ref SANITIZE_RULES: BTreeMap<String, SanitizeRule> = load_sanitize_rules();
+}
+
+// The --tee file that gets a copy of what's written to stdout, once it's
+// been opened.
+lazy_static! {
+    static ref TEE_FILE: Mutex<Option<File>> = Mutex::new(None);
+}
+
+// The table being written by the DUMP-ALL operation, which takes the place
+// of the resource's table when formatting output.  Each --jobs thread writes
+// its own table.
+thread_local! {
+    static DUMP_TABLE: RefCell<Option<String>> = const {RefCell::new(None)};
+}
+
 // ***************************************************************************
 //                                 Enums
 // ***************************************************************************
@@ -56,6 +283,52 @@ pub enum TmsOperation {
     LIST,
     #[strum(ascii_case_insensitive)]
     DELETE,
+    #[strum(ascii_case_insensitive)]
+    INIT,
+    #[strum(ascii_case_insensitive)]
+    IMPORT,
+    #[strum(ascii_case_insensitive)]
+    VALIDATE,
+    #[strum(ascii_case_insensitive)]
+    EXPORT,
+    #[strum(ascii_case_insensitive)]
+    REPORT,
+    #[strum(ascii_case_insensitive)]
+    UPDATE,
+    #[strum(ascii_case_insensitive)]
+    ROTATE,
+    #[strum(ascii_case_insensitive)]
+    DUMP,
+    #[strum(serialize = "DUMP-ALL", ascii_case_insensitive)]
+    DUMPALL,
+    #[strum(ascii_case_insensitive)]
+    OPTIMIZE,
+}
+
+impl TmsOperation {
+    /** The operation's name as given to --operation. */
+    pub fn name(&self) -> &'static str {
+        match self {
+            TmsOperation::LIST => "LIST",
+            TmsOperation::DELETE => "DELETE",
+            TmsOperation::INIT => "INIT",
+            TmsOperation::IMPORT => "IMPORT",
+            TmsOperation::VALIDATE => "VALIDATE",
+            TmsOperation::EXPORT => "EXPORT",
+            TmsOperation::REPORT => "REPORT",
+            TmsOperation::UPDATE => "UPDATE",
+            TmsOperation::ROTATE => "ROTATE",
+            TmsOperation::DUMP => "DUMP",
+            TmsOperation::DUMPALL => "DUMP-ALL",
+            TmsOperation::OPTIMIZE => "OPTIMIZE",
+        }
+    }
+
+    /** Operations that can change the database. */
+    pub fn is_mutation(&self) -> bool {
+        matches!(self, TmsOperation::DELETE | TmsOperation::INIT | TmsOperation::IMPORT 
+                       | TmsOperation::UPDATE | TmsOperation::ROTATE | TmsOperation::OPTIMIZE)
+    }
 }
 
 #[allow(non_camel_case_types)]
@@ -69,264 +342,6422 @@ pub enum TmsResource {
     delegation,
 }
 
-// ***************************************************************************
-//                               Main Processing
-// ***************************************************************************
-fn main() {
-    // Parse command line args and print based on compile-time debug flag.
-    let arg_info = format!("*** Command line arguments *** \n{:?}\n", *TMSADM_ARGS);
-    if DEBUG {println!("{}", arg_info);}
-
-    // Check that the database file exists, which avoids sqlite3 creating it.
-    check_db_file();
-
-    // Choose the command processor to execute.
-    if TMSADM_ARGS.operation == TmsOperation::LIST {
-        // LIST operations.
-        if TMSADM_ARGS.resource == TmsResource::pubkey {
-            process_list_pubkey();
-        } else if TMSADM_ARGS.resource == TmsResource::client {
-            process_list_client();
-        } else {
-            process_list_delegation();
+impl TmsResource {
+    /** The database table that holds this resource's records. */
+    pub fn table(&self) -> &'static str {
+        match self {
+            TmsResource::pubkey => "pubkeys",
+            TmsResource::client => "clients",
+            TmsResource::delegation => "delegations",
         }
-    } else {
-        // DELETE operations.
-        if TMSADM_ARGS.resource == TmsResource::pubkey {
-            process_delete_pubkey();
-        } else if TMSADM_ARGS.resource == TmsResource::client {
-            process_delete_client();
-        } else {
-            process_delete_delegation();
+    }
+
+    /** The order in which LIST and EXPORT return records unless told otherwise. */
+    pub fn default_order(&self) -> &'static str {
+        match self {
+            TmsResource::pubkey => "created DESC",
+            TmsResource::client => "app_name",
+            TmsResource::delegation => "created DESC",
         }
     }
 }
 
-// ---------------------------------------------------------------------------
-// process_list_pubkey:
-// ---------------------------------------------------------------------------
-fn process_list_pubkey() {
-    // Construct the command and run it.
-    let cmd = make_sqlite3_cmd(LIST_PUBKEY);
-    run_command(cmd, "LIST pubkeys");
+#[allow(non_camel_case_types)]
+#[derive(Debug, PartialEq, EnumString)]
+pub enum TmsReport {
+    #[strum(serialize = "delegations-per-client", ascii_case_insensitive)]
+    delegations_per_client,
 }
 
-// ---------------------------------------------------------------------------
-// process_list_client:
-// ---------------------------------------------------------------------------
-fn process_list_client() {
-    // Construct the command and run it.
-    let cmd = make_sqlite3_cmd(LIST_CLIENT);
-    run_command(cmd, "LIST clients");
+/** A transform that --col-transform applies to a column's values. */
+#[allow(non_camel_case_types)]
+#[derive(Debug, PartialEq)]
+pub enum ColTransform {
+    fingerprint,
+    localtime,
+    truncate(usize),
+    redact,
 }
 
-// ---------------------------------------------------------------------------
-// process_list_delegation:
-// ---------------------------------------------------------------------------
-fn process_list_delegation() {
-    // Construct the command and run it.
-    let cmd = make_sqlite3_cmd(LIST_DELEGATION);
-    run_command(cmd, "LIST delegations");
+/** A piece of a --template, either literal text or a column placeholder. */
+#[derive(Debug, PartialEq)]
+pub enum TemplatePart {
+    Text(String),
+    Column(String),
 }
 
-// ---------------------------------------------------------------------------
-// process_delete_pubkey:
-// ---------------------------------------------------------------------------
-fn process_delete_pubkey() {
-    if !TMSADM_ARGS.confirm_delete_off {
-        // First show what could be deleted.
-        process_list_pubkey();
-        if !confirm_delete() {
-            println!("Nothing deleted.");
-            return
-        }
-    }
+/** A token of a --sqlwhere clause, as split by sql_tokens. */
+#[derive(Debug, PartialEq)]
+pub enum SqlToken {
+    /// A string literal, unclosed if the clause ends inside it.
+    Literal {value: String, closed: bool},
+    /// A double quoted identifier, unclosed if the clause ends inside it.
+    Quoted {name: String, closed: bool},
+    Word(String),
+    Number(String),
+    Symbol(String),
+}
 
-    // Construct the command and run it.
-    let cmd = make_sqlite3_cmd(DELETE_PUBKEY);
-    run_command(cmd, "DELETE pubkeys");
+/** What --sanitize does to a column named in the configuration file. */
+#[allow(non_camel_case_types)]
+#[derive(Debug, PartialEq, Deserialize)]
+pub enum SanitizeRule {
+    drop,
+    hash,
 }
 
-// ---------------------------------------------------------------------------
-// process_delete_client:
-// ---------------------------------------------------------------------------
-fn process_delete_client() {
-    if !TMSADM_ARGS.confirm_delete_off {
-        // First show what could be deleted.
-        process_list_client();
-        if !confirm_delete() {
-            println!("Nothing deleted.");
-            return
-        }
+#[allow(non_camel_case_types)]
+#[derive(Debug, PartialEq, EnumString)]
+pub enum TmsFormat {
+    #[strum(ascii_case_insensitive)]
+    json,
+    #[strum(ascii_case_insensitive)]
+    ndjson,
+    #[strum(ascii_case_insensitive)]
+    authorized_keys,
+    #[strum(serialize = "sql-insert", ascii_case_insensitive)]
+    sql_insert,
+    #[strum(serialize = "json-typed", ascii_case_insensitive)]
+    json_typed,
+    #[strum(ascii_case_insensitive)]
+    yaml,
+    #[strum(ascii_case_insensitive)]
+    tsv,
+    #[strum(ascii_case_insensitive)]
+    template,
+    #[strum(ascii_case_insensitive)]
+    none,
+}
+
+impl TmsFormat {
+    /** Formats that tmsadm derives by post-processing sqlite3's json output. */
+    pub fn is_derived(&self) -> bool {
+        matches!(self, TmsFormat::ndjson | TmsFormat::authorized_keys | TmsFormat::json_typed 
+                       | TmsFormat::yaml | TmsFormat::template)
     }
+}
 
-    // Construct the command and run it.
-    let cmd = make_sqlite3_cmd(DELETE_CLIENT);
-    run_command(cmd, "DELETE clients");
+// ***************************************************************************
+//                               Main Processing
+// ***************************************************************************
+fn main() {
+    // Errors in the arguments themselves are also reported as json.
+    if errors_json_requested() {install_error_hook();}
 
-}
+    // Parse command line args and print them when debugging.
+    if TMSADM_ARGS.debug {
+        println!("*** Command line arguments *** \n{:?}\n", *TMSADM_ARGS);
+    }
+    let _summary = SummaryGuard::new();
 
-// ---------------------------------------------------------------------------
-// process_delete_delegation:
-// ---------------------------------------------------------------------------
-fn process_delete_delegation() {
-    if !TMSADM_ARGS.confirm_delete_off {
-        // First show what could be deleted.
-        process_list_delegation();
-        if !confirm_delete() {
-            println!("Nothing deleted.");
-            return
-        }
+    // Run this command once for each profile.
+    if TMSADM_ARGS.all_profiles {
+        process_all_profiles();
+        return;
     }
 
-    // Construct the command and run it.
-    let cmd = make_sqlite3_cmd(DELETE_DELEGATION);
-    run_command(cmd, "DELETE delegations");
-}
+    // Show the effective settings without touching the database.
+    if TMSADM_ARGS.show_config {
+        process_show_config();
+        return;
+    }
 
-// ---------------------------------------------------------------------------
-// make_sqlite3_cmd:
-// ---------------------------------------------------------------------------
-/** Create the command object that issues an OS call with this format:
- * 
- *   sqlite3 [OPTIONS] FILENAME [SQL]
- */
-fn make_sqlite3_cmd(sql_stmt: &str) -> Command {
-    // Construct the SQL command.
-    let mut sql = sql_stmt.to_string();
-    match &TMSADM_ARGS.sqlwhere {
-        Some(wh) => sql += wh,
-        None => {},
+    // Show where the database is without touching it.
+    if TMSADM_ARGS.print_path {
+        process_print_path();
+        return;
     }
-    if TMSADM_ARGS.limit > 0 {
-        sql += " LIMIT ";
-        sql += TMSADM_ARGS.limit.to_string().as_str();
+
+    // Validate the configuration file without touching the database.
+    if TMSADM_ARGS.check_config {
+        process_check_config();
+        return;
     }
 
-    // Build the command with user selected options.
-    let mut cmd = Command::new(SQLITE3);
-    if !&TMSADM_ARGS.json_off {cmd.arg("-json");}
-    if !&TMSADM_ARGS.header_off {cmd.arg("-header");}
-    if !&TMSADM_ARGS.echo_off {cmd.arg("-echo");}
-    cmd.arg(get_absolute_path(&TMSADM_ARGS.dbpath));
-    cmd.arg(sql);
-    cmd
-}
+    // Check an exported file without touching the database.
+    if let Some(path) = &TMSADM_ARGS.verify_export {
+        process_verify_export(path);
+        return;
+    }
 
-// ***************************************************************************
-//                               Config Structs
-// ***************************************************************************
-// ---------------------------------------------------------------------------
-// init_tms_args:
-// ---------------------------------------------------------------------------
-/** Get the command line arguments. */
-fn init_tmsadm_args() -> TmsadmArgs {
-    TmsadmArgs::from_args()
-}
+    // Probe the database without taking the lock, which a running instance holds.
+    if TMSADM_ARGS.healthcheck {
+        process_healthcheck();
+        return;
+    }
 
-// ---------------------------------------------------------------------------
-// TmsadmArgs:
-// ---------------------------------------------------------------------------
-#[derive(Debug, StructOpt)]
-#[structopt(name = "tmsadm", about = "Command line arguments for tmsadm program.", before_help = TMSADM_INFO)]
-pub struct TmsadmArgs {
-    /// Specify the operation to carry out.
-    /// 
-    #[structopt(short, long, possible_values=&["LIST","DELETE"])]
-    pub operation: TmsOperation,
+    // Refuse operations the configuration file doesn't permit this user.
+    if let Some(operation) = &TMSADM_ARGS.operation {
+        check_permission(operation.name());
+        check_require_sanitize(operation);
+    }
+    let modes = [(TMSADM_ARGS.batch.is_some(), "BATCH"), (TMSADM_ARGS.undo, "UNDO"), 
+                 (!TMSADM_ARGS.set_pragma.is_empty(), "SET-PRAGMA")];
+    for (_, name) in modes.iter().filter(|(set, _)| *set) {check_permission(name);}
 
-    /// Specify the resource type to which the operation will be applied.
-    /// 
-    #[structopt(short, long, possible_values=&["pubkey","client","delegation"])]
-    pub resource: TmsResource,
+    // Clean up if the user interrupts us.
+    install_interrupt_handler();
 
-    /// Path to TMS database file.
-    /// 
-    #[structopt(short, long, default_value="~/.tms/database/tms.db")]
-    pub dbpath: String,
+    // INIT creates the database directory, where the lock file goes, if needed.
+    if TMSADM_ARGS.operation == Some(TmsOperation::INIT) {create_db_dir();}
 
-    /// Set JSON formatting (default=false, implying json is on).
-    /// 
-    #[structopt(short, long)]
-    pub json_off: bool,
+    // Keep other tmsadm instances away until we exit.
+    let lock = acquire_lock();
 
-    /// Echo the SQL command in the output (default=false, implying echo on).
-    /// 
-    #[structopt(short, long)]
-    pub echo_off: bool,
+    // Batches run their commands under this instance's lock.
+    if let Some(path) = &TMSADM_ARGS.batch {
+        process_batch(path, lock.as_ref());
+        return;
+    }
 
-    /// Retrieve SQL column headings when using non-JSON format (default=false, implying headers on).
-    /// 
-    #[structopt(short, long)]
-    pub header_off: bool,
+    // Undo replaces the database file, whatever state it's in.
+    if TMSADM_ARGS.undo {
+        process_undo();
+        return;
+    }
 
-    /// Limit the number of records returned. The default is 0 (no limit).
-    /// 
-    #[structopt(short, long, default_value = "0")]
-    pub limit: i32,
+    // Check that sqlite3 is new enough before running it.
+    if TMSADM_ARGS.debug {println!("*** {} version *** \n{}\n", sqlite3_program(), *SQLITE_VERSION);}
+    check_sqlite_version();
 
-    /// Don't prompt user for confirmation on deletes (default=false, implying conformation on).
-    /// 
-    #[structopt(long)]
-    pub confirm_delete_off: bool,
+    // INIT creates the database file, so it never checks for it.
+    if TMSADM_ARGS.operation == Some(TmsOperation::INIT) {
+        process_init();
+        return;
+    }
 
-    /// Provide an SQL WHERE clause to be submitted as part of a SQL statement. The clause
-    /// must start with the word "WHERE" (case insensitive) and be written exactly as it would 
-    /// appear in an SQL statment. Example:
-    /// 
-    ///   "WHERE tms_user_id = 'bud' and host = 'example.com'"
-    /// 
-    /// Use the LIST operation to discover the columns that can be referenced for a chosen
-    /// resource. Discovery can use JSON or non-JSON formatting and "--limit 1" to minimize
-    /// output.
-    /// 
-    #[structopt(short, long)]
-    pub sqlwhere: Option<String>,
-}
+    // Check that the database file exists, which avoids sqlite3 creating it.
+    if !TMSADM_ARGS.no_db_check {check_db_file();}
+    if !TMSADM_ARGS.no_warnings && !TMSADM_ARGS.readonly {check_db_hot();}
 
-// ***************************************************************************
-//                               Utilities
-// ***************************************************************************
-// ---------------------------------------------------------------------------
-// check_db_file:
-// ---------------------------------------------------------------------------
-fn check_db_file() {
-    if !Path::new(&get_absolute_path(&TMSADM_ARGS.dbpath)).is_file() {
-        panic!("Database file does not exist: {}",get_absolute_path(&TMSADM_ARGS.dbpath));
+    // Check that the database is a TMS database before querying it.
+    if !TMSADM_ARGS.no_schema_check {check_db_schema();}
+
+    // Informational modes don't take an operation.
+    if TMSADM_ARGS.schema_version {
+        process_schema_version();
+        return;
     }
-}
+    if TMSADM_ARGS.show_pragmas {
+        process_show_pragmas();
+        return;
+    }
+    if TMSADM_ARGS.vacuum_estimate {
+        process_vacuum_estimate();
+        return;
+    }
+    if TMSADM_ARGS.dump_schema {
+        process_dump_schema();
+        return;
+    }
+    if TMSADM_ARGS.build_where {
+        process_build_where(get_resource());
+        return;
+    }
+    if let Some(path) = &TMSADM_ARGS.expect_counts {
+        process_expect_counts(path);
+        return;
+    }
+    if !TMSADM_ARGS.set_pragma.is_empty() {
+        process_set_pragmas();
+        return;
+    }
+    let operation = get_operation();
 
-// ---------------------------------------------------------------------------
-// confirm_delete:
-// ---------------------------------------------------------------------------
-/** Prompt user to confirm deletion and read user input from stdin.
- */
-fn confirm_delete() -> bool {
-    // Prompt user for confirmation.
-    println!("\nConfirm deletion of the above listed records (y/n):");
+    // Write another --sqlwhere clause with the user's editor.
+    if TMSADM_ARGS.edit_where {edit_where(get_resource());}
 
-    // Collect response.
-    let mut input = String::new();
-    match io::stdin().read_line(&mut input) {
-        Ok(_) => {
-            if input.to_lowercase().starts_with('y') {true}
-                else {false}
-        },
-        Err(e) => {
-            println!("Nothing deleted.");
-            panic!("error: {}", e)
-        },
+    // Hold the --sqlwhere clauses to the allowlist before any query uses them.
+    if TMSADM_ARGS.strict_sql {check_strict_sqlwhere();}
+
+    if TMSADM_ARGS.require_deletes && *operation != TmsOperation::DELETE {
+        panic!("The --require-deletes option only applies to the DELETE operation.");
     }
-}
 
-// ---------------------------------------------------------------------------
-// get_absolute_path:
-// ---------------------------------------------------------------------------
-/** Replace tilde (~) and environment variable values in a path name and
- * then construct the absolute path name.  The difference between 
- * absolutize and standard canonicalize methods is that absolutize does not 
- * care about whether the file exists and what the file really is.
- * 
+    // Modes that span all tables don't take a resource.
+    if let Some(user) = &TMSADM_ARGS.purge_user {
+        if *operation != TmsOperation::DELETE {
+            panic!("The --purge-user option requires the DELETE operation.");
+        }
+        process_purge_user(user);
+        check_records_deleted();
+        return;
+    }
+    if TMSADM_ARGS.expire_cleanup {
+        if *operation != TmsOperation::DELETE || current_resource() != Some(&TmsResource::delegation) {
+            panic!("The --expire-cleanup option requires the DELETE operation on the delegation resource.");
+        }
+        process_expire_cleanup();
+        check_records_deleted();
+        return;
+    }
+    if !TMSADM_ARGS.dedupe.is_empty() {
+        if *operation != TmsOperation::DELETE {
+            panic!("The --dedupe option requires the DELETE operation.");
+        }
+        process_dedupe(get_resource(), &TMSADM_ARGS.dedupe);
+        check_records_deleted();
+        return;
+    }
+    if let Some(cutoff) = &TMSADM_ARGS.purge_soft_deleted_before {
+        if *operation != TmsOperation::DELETE {
+            panic!("The --purge-soft-deleted-before option requires the DELETE operation.");
+        }
+        process_purge_soft_deleted(cutoff);
+        check_records_deleted();
+        return;
+    }
+    if let Some(policy) = &TMSADM_ARGS.apply_retention {
+        if *operation != TmsOperation::DELETE {
+            panic!("The --apply-retention option requires the DELETE operation.");
+        }
+        process_apply_retention(policy);
+        check_records_deleted();
+        return;
+    }
+    if TMSADM_ARGS.output.is_some() && *operation != TmsOperation::EXPORT && *operation != TmsOperation::DUMP {
+        if *operation != TmsOperation::LIST && *operation != TmsOperation::REPORT {
+            panic!("The --output option only applies to the LIST, REPORT, EXPORT and DUMP operations.");
+        }
+        open_output_file();
+    }
+    if TMSADM_ARGS.tee.is_some() {
+        if !matches!(operation, TmsOperation::LIST | TmsOperation::REPORT | TmsOperation::DUMP) {
+            panic!("The --tee option only applies to the LIST, REPORT and DUMP operations.");
+        }
+        open_tee_file();
+    }
+    if TMSADM_ARGS.compact_json && (TMSADM_ARGS.format != TmsFormat::json || TMSADM_ARGS.json_off) {
+        panic!("The --compact-json option only applies to the json format.");
+    }
+    if TMSADM_ARGS.db_timezone.is_some() && !TMSADM_ARGS.localtime 
+        && !TMSADM_ARGS.col_transform.iter().any(|(_, t)| *t == ColTransform::localtime) {
+        panic!("The --db-timezone option requires --localtime or a localtime --col-transform.");
+    }
+    if TMSADM_ARGS.timestamp_output && TMSADM_ARGS.format == TmsFormat::ndjson {
+        panic!("The --timestamp-output option doesn't apply to the ndjson format.");
+    }
+    if TMSADM_ARGS.checksum && !matches!(operation, TmsOperation::EXPORT | TmsOperation::DUMP | TmsOperation::DUMPALL) {
+        panic!("The --checksum option only applies to the EXPORT, DUMP and DUMP-ALL operations.");
+    }
+    if *operation == TmsOperation::DUMP {
+        process_dump();
+        return;
+    }
+    if TMSADM_ARGS.jobs.is_some() && *operation != TmsOperation::DUMPALL {
+        panic!("The --jobs option only applies to the DUMP-ALL operation.");
+    }
+    if *operation == TmsOperation::DUMPALL {
+        process_dump_all();
+        return;
+    }
+    if *operation == TmsOperation::OPTIMIZE {
+        process_optimize();
+        return;
+    }
+    if *operation == TmsOperation::REPORT {
+        if let Some(TmsReport::delegations_per_client) = &TMSADM_ARGS.report {
+            process_report_delegations_per_client();
+        } else if let Some(column) = &TMSADM_ARGS.group_by {
+            process_report_group_by(get_resource(), column);
+        } else if let Some(column) = &TMSADM_ARGS.count_by_day {
+            process_report_count_by_day(get_resource(), column);
+        } else if !TMSADM_ARGS.duplicates.is_empty() {
+            process_report_duplicates(get_resource(), &TMSADM_ARGS.duplicates);
+        } else {
+            panic!("The REPORT operation requires the --report, --group-by, --count-by-day or --duplicates option.");
+        }
+        check_rows_returned();
+        return;
+    }
+
+    // Run the operation on each resource in turn, separating their listings.
+    if TMSADM_ARGS.resource.len() > 1 {
+        if *operation != TmsOperation::LIST && *operation != TmsOperation::DELETE {
+            panic!("Only the LIST and DELETE operations accept more than one resource.");
+        }
+        for (i, resource) in TMSADM_ARGS.resource.iter().enumerate() {
+            RESOURCE_INDEX.store(i, Ordering::Relaxed);
+            if *operation == TmsOperation::LIST {
+                with_output(|out| writeln!(out, "== {} ==", resource.table()));
+                CONTINUED_PAGE.store(false, Ordering::Relaxed);
+            }
+            process_resource(operation, resource);
+        }
+    } else {
+        process_resource(operation, get_resource());
+    }
+    if *operation == TmsOperation::DELETE {check_records_deleted();}
+}
+
+// ---------------------------------------------------------------------------
+// process_resource:
+// ---------------------------------------------------------------------------
+/** Validate the options for the operation on the resource and choose the
+ * command processor to execute.
+ */
+fn process_resource(operation: &TmsOperation, resource: &'static TmsResource) {
+    if !sqlwhere_clauses().is_empty() {lint_sqlwhere(resource.table());}
+    if *operation == TmsOperation::IMPORT {
+        if *resource != TmsResource::pubkey {
+            panic!("The IMPORT operation only applies to the pubkey resource.");
+        }
+        process_import_pubkey();
+        return;
+    }
+    if *operation == TmsOperation::UPDATE {
+        if *resource != TmsResource::client {
+            panic!("The UPDATE operation only applies to the client resource.");
+        }
+        if let Some(tenant) = &TMSADM_ARGS.copy_to_tenant {
+            process_copy_client(tenant);
+            return;
+        }
+        if let Some(names) = &TMSADM_ARGS.rename {
+            process_rename_client(&names[0], &names[1]);
+            return;
+        }
+        if !TMSADM_ARGS.enable && !TMSADM_ARGS.disable {
+            panic!("The UPDATE operation requires the --enable, --disable, --copy-to-tenant or --rename option.");
+        }
+        process_update_client(TMSADM_ARGS.enable);
+        return;
+    }
+    if *operation == TmsOperation::ROTATE {
+        if *resource != TmsResource::pubkey {
+            panic!("The ROTATE operation only applies to the pubkey resource.");
+        }
+        process_rotate_pubkey();
+        return;
+    }
+    if *operation == TmsOperation::VALIDATE {
+        if *resource != TmsResource::pubkey {
+            panic!("The VALIDATE operation only applies to the pubkey resource.");
+        }
+        process_validate_pubkey();
+        return;
+    }
+    if TMSADM_ARGS.orphans && *resource != TmsResource::delegation {
+        panic!("The --orphans option only applies to the delegation resource.");
+    }
+    if TMSADM_ARGS.format == TmsFormat::authorized_keys && *resource != TmsResource::pubkey {
+        panic!("The authorized_keys format only applies to the pubkey resource.");
+    }
+    if TMSADM_ARGS.format == TmsFormat::json_typed {
+        if *operation != TmsOperation::LIST && *operation != TmsOperation::EXPORT {
+            panic!("The json-typed format only applies to the LIST and EXPORT operations.");
+        }
+        if TMSADM_ARGS.count_only || TMSADM_ARGS.all_pages || TMSADM_ARGS.distinct.is_some() 
+            || TMSADM_ARGS.count_distinct.is_some() || TMSADM_ARGS.diff_against.is_some() {
+            panic!("The json-typed format cannot be combined with --count-only, --all-pages, --distinct, --count-distinct or --diff-against.");
+        }
+    }
+    if TMSADM_ARGS.format == TmsFormat::template {
+        if *operation != TmsOperation::LIST && *operation != TmsOperation::EXPORT {
+            panic!("The template format only applies to the LIST and EXPORT operations.");
+        }
+        if TMSADM_ARGS.count_only || TMSADM_ARGS.all_pages || TMSADM_ARGS.distinct.is_some() 
+            || TMSADM_ARGS.count_distinct.is_some() || TMSADM_ARGS.diff_against.is_some() {
+            panic!("The template format cannot be combined with --count-only, --all-pages, --distinct, --count-distinct or --diff-against.");
+        }
+        match &TMSADM_ARGS.template {
+            Some(template) => check_template_columns(resource, template),
+            None => panic!("The template format requires the --template option."),
+        }
+    }
+    if TMSADM_ARGS.since_id.is_some() && *operation != TmsOperation::LIST && *operation != TmsOperation::EXPORT {
+        panic!("The --since-id option only applies to the LIST and EXPORT operations.");
+    }
+    if TMSADM_ARGS.join_clients && (*operation != TmsOperation::LIST || *resource != TmsResource::delegation 
+                                    || TMSADM_ARGS.format == TmsFormat::json_typed) {
+        panic!("The --join-clients option only applies to the LIST operation on the delegation resource, and not to the json-typed format.");
+    }
+    if TMSADM_ARGS.tail && *operation != TmsOperation::LIST {
+        panic!("The --tail option only applies to the LIST operation.");
+    }
+    if (TMSADM_ARGS.first || TMSADM_ARGS.last) && *operation != TmsOperation::LIST {
+        panic!("The --first and --last options only apply to the LIST operation.");
+    }
+    if TMSADM_ARGS.explain_confirm && *operation != TmsOperation::DELETE {
+        panic!("The --explain-confirm option only applies to the DELETE operation.");
+    }
+    if TMSADM_ARGS.limit.is_some() && *operation == TmsOperation::DELETE {
+        panic!("The --limit option doesn't apply to the DELETE operation; use --preview-limit to limit the \
+                records shown before a delete is confirmed.");
+    }
+    if TMSADM_ARGS.verify && *operation != TmsOperation::DELETE {
+        panic!("The --verify option only applies to the DELETE operation.");
+    }
+    if TMSADM_ARGS.sort.is_some() && *operation != TmsOperation::LIST && *operation != TmsOperation::EXPORT {
+        panic!("The --sort option only applies to the LIST and EXPORT operations.");
+    }
+    if TMSADM_ARGS.all_pages {
+        if *operation != TmsOperation::LIST && *operation != TmsOperation::EXPORT {
+            panic!("The --all-pages option only applies to the LIST and EXPORT operations.");
+        }
+        if TMSADM_ARGS.limit.is_none() {
+            panic!("The --all-pages option requires a --limit page size.");
+        }
+        if TMSADM_ARGS.sort.as_ref().is_some_and(|c| c != "id") {
+            panic!("The --all-pages option lists records in id order, so it can only --sort by id.");
+        }
+        if TMSADM_ARGS.orphans {
+            panic!("The --all-pages option doesn't apply to --orphans.");
+        }
+    }
+    if TMSADM_ARGS.fingerprint {
+        if *operation != TmsOperation::LIST || *resource != TmsResource::pubkey {
+            panic!("The --fingerprint option only applies to the LIST operation on the pubkey resource.");
+        }
+        if TMSADM_ARGS.format != TmsFormat::json && TMSADM_ARGS.format != TmsFormat::ndjson {
+            panic!("The --fingerprint option only applies to the json and ndjson formats.");
+        }
+        if TMSADM_ARGS.json_off {
+            panic!("The --fingerprint option doesn't apply with --json-off.");
+        }
+    }
+    if !TMSADM_ARGS.col_transform.is_empty() {
+        if *operation != TmsOperation::LIST && *operation != TmsOperation::EXPORT {
+            panic!("The --col-transform option only applies to the LIST and EXPORT operations.");
+        }
+        if TMSADM_ARGS.format != TmsFormat::json && TMSADM_ARGS.format != TmsFormat::ndjson {
+            panic!("The --col-transform option only applies to the json and ndjson formats.");
+        }
+        if TMSADM_ARGS.json_off {
+            panic!("The --col-transform option doesn't apply with --json-off.");
+        }
+        let columns: Vec<String> = TMSADM_ARGS.col_transform.iter().map(|(c, _)| c.clone()).collect();
+        check_columns(resource.table(), &columns);
+    }
+    if TMSADM_ARGS.with_rowid {
+        if *operation != TmsOperation::LIST && *operation != TmsOperation::EXPORT {
+            panic!("The --with-rowid option only applies to the LIST and EXPORT operations.");
+        }
+        if TMSADM_ARGS.format == TmsFormat::json_typed || TMSADM_ARGS.format == TmsFormat::sql_insert {
+            panic!("The --with-rowid option doesn't apply to the json-typed and sql-insert formats.");
+        }
+        check_rowid(resource.table());
+    }
+    if TMSADM_ARGS.sanitize {
+        if *operation != TmsOperation::EXPORT && *operation != TmsOperation::LIST {
+            panic!("The --sanitize option only applies to the LIST and EXPORT operations.");
+        }
+        if TMSADM_ARGS.format != TmsFormat::json && TMSADM_ARGS.format != TmsFormat::ndjson {
+            panic!("The --sanitize option only applies to the json and ndjson formats.");
+        }
+        if TMSADM_ARGS.json_off {
+            panic!("The --sanitize option doesn't apply with --json-off.");
+        }
+        lazy_static::initialize(&SANITIZE_RULES);
+    }
+    if let Some(id) = &TMSADM_ARGS.client_id {
+        if *resource != TmsResource::delegation 
+            || (*operation != TmsOperation::LIST && *operation != TmsOperation::DELETE) {
+            panic!("The --client-id option only applies to the LIST and DELETE operations on the delegation resource.");
+        }
+        if !TMSADM_ARGS.no_warnings {check_client_exists(id);}
+    }
+    if TMSADM_ARGS.cascade && (*operation != TmsOperation::DELETE || *resource != TmsResource::client) {
+        panic!("The --cascade option only applies to the DELETE operation on the client resource.");
+    }
+    if TMSADM_ARGS.deleted_archive.is_some() && *operation != TmsOperation::DELETE {
+        panic!("The --deleted-archive option only applies to the DELETE operation.");
+    }
+    if let Some(max) = TMSADM_ARGS.max_rows {
+        let records = TMSADM_ARGS.distinct.is_none() && TMSADM_ARGS.count_distinct.is_none() 
+            && TMSADM_ARGS.diff_against.is_none() && !TMSADM_ARGS.count_only;
+        if records && (*operation == TmsOperation::LIST || *operation == TmsOperation::EXPORT) {
+            check_max_rows(resource, max);
+        }
+    }
+    if *operation == TmsOperation::EXPORT {
+        process_export(resource);
+    } else if *operation == TmsOperation::LIST {
+        // LIST operations.
+        if TMSADM_ARGS.count_only {
+            let count = query_count(&list_sql(resource));
+            ROW_COUNT.store(count, Ordering::Relaxed);
+            with_output(|out| match TMSADM_ARGS.raw_scalar {
+                true => write!(out, "{}", count),
+                false => writeln!(out, "{}", count),
+            });
+        } else if let Some(other) = &TMSADM_ARGS.diff_against {
+            process_list_diff(resource, other);
+        } else if let Some(column) = &TMSADM_ARGS.distinct {
+            process_list_distinct(resource, column);
+        } else if let Some(column) = &TMSADM_ARGS.count_distinct {
+            process_count_distinct(resource, column);
+        } else if TMSADM_ARGS.tail {
+            process_tail(resource);
+        } else if TMSADM_ARGS.all_pages {
+            with_output(|out| {
+                run_pages(resource, &format!("LIST {}", resource.table()), out);
+                Ok(())
+            });
+        } else if *resource == TmsResource::pubkey {
+            process_list_pubkey();
+        } else if *resource == TmsResource::client {
+            process_list_client();
+        } else {
+            process_list_delegation();
+        }
+        check_rows_returned();
+    } else {
+        // DELETE operations.
+        if *resource == TmsResource::pubkey {
+            process_delete_pubkey();
+        } else if *resource == TmsResource::client {
+            process_delete_client();
+        } else {
+            process_delete_delegation();
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// process_tail:
+// ---------------------------------------------------------------------------
+/** Print the resource's selected records as they're added, like tail -f, 
+ * until interrupted.  Every --tail-interval seconds, records with an id 
+ * above the highest one seen are listed in id order.  The first records 
+ * listed are those after the --since-id, or, without it, those added after
+ * tailing starts.  Column headers and echoed SQL are only written once.
+ * 
+ * Each poll runs a new sqlite3, which opens whatever file the database path
+ * names at the time, so a database file that the TMS server replaces, such
+ * as on a restart, is picked up with a notice.  Polls are skipped while the
+ * file is missing, since sqlite3 would otherwise create an empty database.
+ */
+fn process_tail(resource: &TmsResource) {
+    let table = resource.table();
+    let mut seen = match TMSADM_ARGS.since_id {
+        Some(id) => id,
+        None => run_capture(&format!("SELECT COALESCE(MAX(id), 0) FROM {}", table), "FIND last id")
+            .trim().parse().unwrap_or_default(),
+    };
+    let interval = Duration::from_secs(TMSADM_ARGS.tail_interval);
+    let mut identity = db_file_identity();
+    loop {
+        let current = db_file_identity();
+        if current != identity {
+            match current {
+                Some(_) => eprintln!("Notice: the database file {} was replaced, reopening it.", db_file_path()),
+                None => eprintln!("Notice: the database file {} is missing, waiting for it.", db_file_path()),
+            }
+            identity = current;
+        }
+        if identity.is_none() {
+            thread::sleep(interval);
+            continue;
+        }
+        let sql = format!("SELECT MAX(id) FROM {} {}", table, make_where_clause_and(&format!("id > {}", seen)));
+        if let Ok(last) = run_capture(&sql, "FIND new records").trim().parse::<i64>() {
+            let list = format!("{}{} ORDER BY id", list_sql(resource), 
+                               make_where_clause_and(&format!("id > {} AND id <= {}", seen, last)));
+            run_command(make_sqlite3_raw_cmd(&list), &format!("TAIL {}", table), true);
+            CONTINUED_PAGE.store(true, Ordering::Relaxed);
+            seen = last;
+        }
+        thread::sleep(interval);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// db_file_identity:
+// ---------------------------------------------------------------------------
+/** Return what identifies the file at the database path, which changes when
+ * the file is replaced rather than written to, or None if there's no file.
+ */
+#[cfg(unix)]
+fn db_file_identity() -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(db_file_path()).ok().map(|m| (m.dev(), m.ino()))
+}
+
+#[cfg(not(unix))]
+fn db_file_identity() -> Option<(u64, u64)> {
+    let created = fs::metadata(db_file_path()).ok()?.created().ok()?;
+    created.duration_since(UNIX_EPOCH).ok().map(|d| (d.as_secs(), d.subsec_nanos() as u64))
+}
+
+// ---------------------------------------------------------------------------
+// check_max_rows:
+// ---------------------------------------------------------------------------
+/** Refuse to list or export more than the maximum number of records, which 
+ * protects against a mistyped filter that selects a whole table.
+ */
+fn check_max_rows(resource: &TmsResource, max: u64) {
+    let count = query_count(&list_sql(resource));
+    if count > max {
+        panic!("The selection has {} rows, more than --max-rows {}. Narrow the filters or use --limit.", 
+               group_thousands(count), group_thousands(max));
+    }
+}
+
+// ---------------------------------------------------------------------------
+// process_list_pubkey:
+// ---------------------------------------------------------------------------
+fn process_list_pubkey() {
+    // Construct the command and run it.
+    let cmd = make_sqlite3_cmd(&list_sql(&TmsResource::pubkey));
+    run_command(cmd, "LIST pubkeys", true);
+}
+
+// ---------------------------------------------------------------------------
+// process_list_client:
+// ---------------------------------------------------------------------------
+fn process_list_client() {
+    // Construct the command and run it.
+    let cmd = make_sqlite3_cmd(&list_sql(&TmsResource::client));
+    run_command(cmd, "LIST clients", true);
+}
+
+// ---------------------------------------------------------------------------
+// process_list_delegation:
+// ---------------------------------------------------------------------------
+fn process_list_delegation() {
+    // Construct the command and run it.
+    let cmd = if TMSADM_ARGS.join_clients {
+        make_sqlite3_raw_cmd(&join_clients_sql())
+    } else {
+        make_sqlite3_cmd(&list_sql(&TmsResource::delegation))
+    };
+    run_command(cmd, "LIST delegations", true);
+}
+
+// ---------------------------------------------------------------------------
+// join_clients_sql:
+// ---------------------------------------------------------------------------
+/** Return the complete statement that lists the selected delegations with
+ * their client's application name, version and enabled flag, which are null
+ * for orphaned delegations.  The delegations are selected and limited before
+ * the join so that the record filters' unqualified columns aren't ambiguous,
+ * and the joined rows are wrapped so that the same holds for the ordering.
+ */
+fn join_clients_sql() -> String {
+    let delegations = format!("{}{}{}", list_sql(&TmsResource::delegation), make_where_clause(), 
+                              make_limit_clause());
+    format!("SELECT * FROM (SELECT d.*, c.app_name AS client_app_name, c.app_version AS client_app_version, \
+             c.enabled AS client_enabled FROM ({}) d LEFT JOIN clients c \
+             ON d.client_id = c.client_id AND d.tenant = c.tenant){}", delegations, make_order_clause())
+}
+
+// ---------------------------------------------------------------------------
+// process_list_diff:
+// ---------------------------------------------------------------------------
+/** List the resource's records whose ids appear in only one of the database
+ * and the other database, which is attached for the query.  Each record has
+ * a leading diff column that is "added" for records only in the database and
+ * "removed" for records only in the other database.  The WHERE clause and 
+ * limit apply to the combined records.
+ */
+fn process_list_diff(resource: &TmsResource, other: &str) {
+    let other = get_absolute_path(other);
+    if !Path::new(&other).is_file() {
+        panic!("Database file does not exist: {}", other);
+    }
+    let t = resource.table();
+    let diff = format!("SELECT 'added' AS diff, * FROM main.{t} WHERE id NOT IN (SELECT id FROM diff_other.{t}) \
+        UNION ALL SELECT 'removed' AS diff, * FROM diff_other.{t} WHERE id NOT IN (SELECT id FROM main.{t})");
+    let sql = format!("ATTACH DATABASE {} AS diff_other; {}", sql_quote(&other), 
+                      make_sql(&format!("SELECT * FROM ({}) ", diff)));
+    let cmd = make_sqlite3_raw_cmd(&sql);
+    run_command(cmd, &format!("DIFF {}", t), true);
+}
+
+// ---------------------------------------------------------------------------
+// process_list_distinct:
+// ---------------------------------------------------------------------------
+/** List the distinct values of a column of the resource's selected records. */
+fn process_list_distinct(resource: &TmsResource, column: &str) {
+    check_columns(resource.table(), &[column.to_string()]);
+    let sql = make_sql(&format!("SELECT DISTINCT {} FROM {} ", column, resource.table()));
+    let cmd = make_sqlite3_raw_cmd(&sql);
+    run_command(cmd, &format!("LIST distinct {}", column), true);
+}
+
+// ---------------------------------------------------------------------------
+// process_count_distinct:
+// ---------------------------------------------------------------------------
+/** Print the number of distinct values of a column of the resource's selected
+ * records.
+ */
+fn process_count_distinct(resource: &TmsResource, column: &str) {
+    check_columns(resource.table(), &[column.to_string()]);
+    let sql = format!("SELECT COUNT(DISTINCT {}) FROM (SELECT * FROM {} {}{})", column, 
+                      resource.table(), make_where_clause(), make_limit_clause());
+    let count: u64 = run_capture(&sql, &format!("COUNT distinct {}", column)).trim().parse().unwrap_or_default();
+    ROW_COUNT.store(count, Ordering::Relaxed);
+    with_output(|out| match TMSADM_ARGS.raw_scalar {
+        true => write!(out, "{}", count),
+        false => writeln!(out, "{}", count),
+    });
+}
+
+// ---------------------------------------------------------------------------
+// list_delegation_sql:
+// ---------------------------------------------------------------------------
+/** Return the SELECT prototype for delegations, which depends on whether 
+ * only orphans are selected.
+ */
+fn list_delegation_sql() -> &'static str {
+    if TMSADM_ARGS.orphans {LIST_ORPHAN_DELEGATION} else {LIST_DELEGATION}
+}
+
+// ---------------------------------------------------------------------------
+// process_delete_pubkey:
+// ---------------------------------------------------------------------------
+fn process_delete_pubkey() {
+    let sql = delete_prototype(DELETE_PUBKEY, TmsResource::pubkey.table());
+    let confirmation = match delete_confirmed(LIST_PUBKEY, &sql) {
+        Some(c) => c,
+        None => return,
+    };
+
+    // Run the delete.
+    let sql = make_sql(&sql);
+    run_delete(&sql, LIST_PUBKEY, "DELETE pubkeys");
+    write_audit_entry("pubkeys", &sql, confirmation);
+    if TMSADM_ARGS.verify {verify_deleted(LIST_PUBKEY);}
+}
+
+// ---------------------------------------------------------------------------
+// process_delete_client:
+// ---------------------------------------------------------------------------
+fn process_delete_client() {
+    let sql = delete_prototype(DELETE_CLIENT, TmsResource::client.table());
+    if !TMSADM_ARGS.soft_delete {warn_orphaned_delegations();}
+    let confirmation = match delete_confirmed(LIST_CLIENT, &sql) {
+        Some(c) => c,
+        None => return,
+    };
+
+    // Run the delete.
+    let sql = make_sql(&sql);
+    if TMSADM_ARGS.cascade {
+        let delegations = format!("DELETE FROM delegations WHERE {}", cascade_condition());
+        run_cascade_delete(&sql);
+        write_audit_entry("delegations", &delegations, confirmation);
+    } else {
+        run_delete(&sql, LIST_CLIENT, "DELETE clients");
+    }
+    write_audit_entry("clients", &sql, confirmation);
+    if TMSADM_ARGS.verify {verify_deleted(LIST_CLIENT);}
+}
+
+// ---------------------------------------------------------------------------
+// cascade_condition:
+// ---------------------------------------------------------------------------
+/** Return the condition that selects the delegations of the selected clients,
+ * which are those with the same client_id and tenant.
+ */
+fn cascade_condition() -> String {
+    format!("EXISTS (SELECT 1 FROM ({}) c \
+             WHERE c.client_id = delegations.client_id AND c.tenant = delegations.tenant)", make_sql(LIST_CLIENT))
+}
+
+// ---------------------------------------------------------------------------
+// warn_orphaned_delegations:
+// ---------------------------------------------------------------------------
+/** Before the selected clients are deleted, warn about the delegations that
+ * reference them, which the delete would leave without a client unless 
+ * --cascade removes them too.
+ */
+fn warn_orphaned_delegations() {
+    let sql = format!("SELECT COUNT(*) FROM delegations d WHERE EXISTS (SELECT 1 FROM ({}) c \
+                       WHERE c.client_id = d.client_id AND c.tenant = d.tenant)", make_sql(LIST_CLIENT));
+    let count = run_capture(&sql, "COUNT client delegations").trim().parse::<u64>().unwrap_or_default();
+    if count == 0 {return;}
+    if TMSADM_ARGS.cascade {
+        println!("The {} delegation(s) of these clients will also be deleted (--cascade).", count);
+    } else {
+        eprintln!("Warning: this will orphan {} delegation(s); use --purge-user or --cascade to remove them too.", count);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// run_cascade_delete:
+// ---------------------------------------------------------------------------
+/** Delete the selected clients' delegations and then the clients in one 
+ * transaction, recording the number of records deleted from both tables.
+ * With --deleted-archive, the records of both tables are archived first.
+ */
+fn run_cascade_delete(clients_sql: &str) {
+    let delegations_sql = archived_delete_sql("delegations", &cascade_condition());
+    let clients_sql = match &TMSADM_ARGS.deleted_archive {
+        Some(_) => archived_delete_sql("clients", &format!("id IN (SELECT id FROM ({}))", make_sql(LIST_CLIENT))),
+        None => clients_sql.to_string(),
+    };
+    if TMSADM_ARGS.echo && TMSADM_ARGS.format == TmsFormat::json {
+        println!("{}\n{}", delegations_sql, clients_sql);
+    }
+    let sql = format!("BEGIN; {}; SELECT changes(); {}; SELECT changes(); COMMIT;", delegations_sql, clients_sql);
+    let out = run_deletes(&sql, "DELETE clients and delegations");
+    let counts: Vec<u64> = out.lines().filter_map(|l| l.trim().parse().ok()).collect();
+    let (delegations, clients) = match counts[..] {
+        [d, c] => (d, c),
+        _ => panic!("DELETE clients and delegations: unexpected counts returned by {}: {}", sqlite3_program(), out.trim()),
+    };
+    println!("Deleted {} client(s) and {} of their delegation(s).", clients, delegations);
+    record_affected(clients + delegations);
+}
+
+// ---------------------------------------------------------------------------
+// process_delete_delegation:
+// ---------------------------------------------------------------------------
+fn process_delete_delegation() {
+    let sql = if TMSADM_ARGS.orphans {DELETE_ORPHAN_DELEGATION.to_string()} 
+        else {delete_prototype(DELETE_DELEGATION, TmsResource::delegation.table())};
+    let confirmation = match delete_confirmed(list_delegation_sql(), &sql) {
+        Some(c) => c,
+        None => return,
+    };
+
+    // Run the delete.
+    let sql = make_sql(&sql);
+    run_delete(&sql, list_delegation_sql(), "DELETE delegations");
+    write_audit_entry("delegations", &sql, confirmation);
+    if TMSADM_ARGS.verify {verify_deleted(list_delegation_sql());}
+}
+
+// ---------------------------------------------------------------------------
+// delete_prototype:
+// ---------------------------------------------------------------------------
+/** Return the statement prototype that deletes the table's selected records.
+ * With --soft-delete, records are marked by setting their deleted_at column
+ * instead, which the table must have.
+ */
+fn delete_prototype(delete_sql: &str, table: &str) -> String {
+    if !TMSADM_ARGS.soft_delete {return delete_sql.to_string();}
+    if !has_deleted_column(table) {
+        panic!("The --soft-delete option requires a {} column, which the {} table doesn't have.", 
+               DELETED_COLUMN, table);
+    }
+    format!("UPDATE {} SET {} = datetime('now') ", table, DELETED_COLUMN)
+}
+
+// ---------------------------------------------------------------------------
+// delete_confirmed:
+// ---------------------------------------------------------------------------
+/** Decide whether a delete can proceed.  A dry run only reports how many 
+ * records would be deleted and the DELETE statement that would be run, so it
+ * never proceeds.  Otherwise the database must be writable, and unless 
+ * confirmation is turned off, a sample of the records that would be deleted
+ * is shown along with their total and the user is prompted.  When a 
+ * confirmation threshold is set, the affected records are first counted using
+ * the list prototype and the prompt is skipped if the count doesn't exceed 
+ * the threshold.
+ * 
+ * When the delete can proceed, how it was confirmed is returned for the 
+ * audit log: "prompted", "confirm-file", "skipped" or "below-threshold".
+ */
+fn delete_confirmed(list_sql: &str, delete_sql: &str) -> Option<&'static str> {
+    if TMSADM_ARGS.dry_run {
+        println!("Would delete {} rows with: {}", query_count(list_sql), make_sql(delete_sql));
+        println!("Dry run, nothing deleted.");
+        return None;
+    }
+    check_db_writable();
+    if TMSADM_ARGS.ids_stdin {println!("Read {} id(s) from stdin.", TMSADM_ARGS.ids.len());}
+    if TMSADM_ARGS.confirm_delete_off {return Some("skipped");}
+    if let Some(threshold) = TMSADM_ARGS.confirm_threshold {
+        let count = query_count(list_sql);
+        if count <= threshold {
+            println!("Deleting {} record(s) without confirmation (threshold is {}).", count, threshold);
+            return Some("below-threshold");
+        }
+    }
+
+    // First show what could be deleted.
+    confirm_deletion(|| {
+        preview_records(list_sql);
+        if TMSADM_ARGS.explain_confirm {explain_delete(list_sql);}
+        confirm_records("deletion")
+    })
+}
+
+// ---------------------------------------------------------------------------
+// confirm_deletion:
+// ---------------------------------------------------------------------------
+/** Ask for a delete to be confirmed with the prompt function unless 
+ * --confirm-delete-off is set.  None is returned, after reporting that 
+ * nothing was deleted, when the user declines.  Otherwise how the delete was
+ * confirmed is returned for the audit log: "skipped", "confirm-file" or 
+ * "prompted".
+ */
+fn confirm_deletion(prompt: impl FnOnce() -> bool) -> Option<&'static str> {
+    if TMSADM_ARGS.confirm_delete_off {return Some("skipped");}
+    if !prompt() {
+        println!("Nothing deleted.");
+        return None;
+    }
+    Some(if TMSADM_ARGS.confirm_from.is_some() {"confirm-file"} else {"prompted"})
+}
+
+// ---------------------------------------------------------------------------
+// dry_run_unsupported:
+// ---------------------------------------------------------------------------
+/** With --dry-run, report that the task has no dry run and return true so
+ * that the caller returns without changing anything.  This keeps a dry run
+ * of a --batch file from running any of its changes.
+ */
+fn dry_run_unsupported(task: &str) -> bool {
+    if !TMSADM_ARGS.dry_run {return false;}
+    println!("Would run {}, which has no dry run, so its changes can't be shown.", task);
+    println!("Dry run, nothing changed.");
+    true
+}
+
+// ---------------------------------------------------------------------------
+// explain_delete:
+// ---------------------------------------------------------------------------
+/** Describe the delete about to be confirmed in a sentence that names the 
+ * table, the conditions that select the records and how many there are.
+ */
+fn explain_delete(list_sql: &str) {
+    let table = get_resource().table();
+    let mut conds = Vec::new();
+    if TMSADM_ARGS.orphans {conds.push("the client doesn't exist".to_string());}
+    let wh = make_where_clause();
+    if !wh.is_empty() {conds.push(strip_where(&wh).to_string());}
+    let selection = if conds.is_empty() {"with no conditions, which is every row".to_string()} 
+        else {format!("where {}", conds.join(" and "))};
+    let count = group_thousands(query_count(list_sql));
+    if TMSADM_ARGS.soft_delete {
+        println!("About to mark rows of table '{}' {} as deleted, affecting {} rows.", table, selection, count);
+    } else {
+        println!("About to DELETE from table '{}' {}, affecting {} rows. \
+                  This cannot be undone unless a backup exists.", table, selection, count);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// run_delete:
+// ---------------------------------------------------------------------------
+/** Run a completed delete statement, record the number of records it changed
+ * and return that number.  With --echo, the statement is shown first as 
+ * sqlite3 would.
+ * 
+ * With --deleted-archive, the records the list prototype selects are first
+ * appended to the archive, and only if that succeeds are those records, by 
+ * id, deleted in a transaction that also requires them to still be selected.
+ */
+fn run_delete(sql: &str, list_sql: &str, task: &str) -> u64 {
+    let sql = match &TMSADM_ARGS.deleted_archive {
+        Some(_) => archived_delete_sql(get_resource().table(), 
+                                       &format!("id IN (SELECT id FROM ({}))", make_sql(list_sql))),
+        None => sql.to_string(),
+    };
+    if TMSADM_ARGS.echo && TMSADM_ARGS.format == TmsFormat::json {println!("{}", sql);}
+    let changed = if TMSADM_ARGS.deleted_archive.is_some() {
+        // The id list can be too long for a command line argument.
+        run_script(&format!("BEGIN IMMEDIATE;\n{};\nSELECT changes();\nCOMMIT;\n", sql), task)
+    } else {
+        run_capture(&format!("{}; SELECT changes();", sql), task)
+    };
+    let changed = changed.trim().parse().unwrap_or_default();
+    record_affected(changed);
+    changed
+}
+
+// ---------------------------------------------------------------------------
+// archive_records:
+// ---------------------------------------------------------------------------
+/** Append each record that the query selects to the --deleted-archive file as
+ * a json line with the time it was archived and its table, and return the 
+ * records' ids.  The file is synced before returning, and since the delete
+ * hasn't happened yet, any failure to write it is fatal.
+ */
+fn archive_records(path: &str, sql: &str, table: &str) -> Vec<String> {
+    let path = get_absolute_path(path);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let mut lines = String::new();
+    let mut ids = Vec::new();
+    query_rows(sql, "ARCHIVE records", |row| {
+        match row.get("id") {
+            Some(Value::Number(id)) => ids.push(id.to_string()),
+            _ => panic!("Unable to archive a {} record without an integer id.", table),
+        }
+        let entry = serde_json::json!({"archived_at": now, "table": table, "record": row});
+        lines += &format!("{}\n", entry);
+    });
+    let written = fs::OpenOptions::new().create(true).append(true).open(&path)
+        .and_then(|mut f| f.write_all(lines.as_bytes()).and_then(|_| f.sync_all()));
+    if let Err(e) = written {
+        panic!("Unable to write deleted records archive {}, nothing deleted: {}", path, e);
+    }
+    println!("Archived {} record(s) to {}.", ids.len(), path);
+    ids
+}
+
+// ---------------------------------------------------------------------------
+// archived_delete_sql:
+// ---------------------------------------------------------------------------
+/** Return the statement that deletes the table's records that match the 
+ * condition.  With --deleted-archive, the matching records are archived 
+ * first, and the statement only deletes those of them that still match.
+ */
+fn archived_delete_sql(table: &str, condition: &str) -> String {
+    let sql = format!("DELETE FROM {} WHERE {}", table, condition);
+    match &TMSADM_ARGS.deleted_archive {
+        Some(path) => {
+            let ids = archive_records(path, &format!("SELECT * FROM {} WHERE {}", table, condition), table);
+            format!("DELETE FROM {} WHERE ({}) AND id IN ({})", table, condition, ids.join(", "))
+        },
+        None => sql,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// run_deletes:
+// ---------------------------------------------------------------------------
+/** Run SQL that deletes records from one or more tables and return its 
+ * output.  With --deleted-archive, the statements list the archived ids, 
+ * which can be too long for a command line argument, so they run as a script.
+ */
+fn run_deletes(sql: &str, task: &str) -> String {
+    match TMSADM_ARGS.deleted_archive {
+        Some(_) => run_script(&format!("{}\n", sql), task),
+        None => run_capture(sql, task),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// verify_deleted:
+// ---------------------------------------------------------------------------
+/** Count the records the delete's WHERE clause still selects, which should
+ * be none unless, for example, a trigger re-inserted them.
+ */
+fn verify_deleted(list_sql: &str) {
+    let remaining = query_count(list_sql);
+    if remaining > 0 {
+        panic!("Verification failed: {} matching row(s) remain after the delete.", remaining);
+    }
+    println!("verified: 0 rows remain");
+}
+
+// ---------------------------------------------------------------------------
+// preview_records:
+// ---------------------------------------------------------------------------
+/** Show the first --preview-rows of the records selected by the list 
+ * prototype, followed by how many more there are, so that a large delete 
+ * doesn't flood the terminal.
+ */
+fn preview_records(list_sql: &str) {
+    preview_selected(&make_sql(list_sql), query_count(list_sql));
+}
+
+// ---------------------------------------------------------------------------
+// preview_selected:
+// ---------------------------------------------------------------------------
+/** Show the first --preview-rows of the records returned by the complete
+ * select statement, whose total number of records is given.
+ */
+fn preview_selected(select_sql: &str, total: u64) {
+    let sample = format!("SELECT * FROM ({}) LIMIT {}", select_sql, TMSADM_ARGS.preview_rows);
+    run_command(make_sqlite3_raw_cmd(&sample), "PREVIEW records", true);
+    if total > TMSADM_ARGS.preview_rows {
+        println!("\u{2026}and {} more ({} in total)", group_thousands(total - TMSADM_ARGS.preview_rows), 
+                 group_thousands(total));
+    }
+}
+
+// ---------------------------------------------------------------------------
+// group_thousands:
+// ---------------------------------------------------------------------------
+/** Format the number with commas between groups of three digits. */
+fn group_thousands(n: u64) -> String {
+    let digits = n.to_string();
+    let mut s = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {s.push(',');}
+        s.push(c);
+    }
+    s
+}
+
+// ---------------------------------------------------------------------------
+// write_audit_entry:
+// ---------------------------------------------------------------------------
+/** Append a json line describing a completed delete to the --audit-log file,
+ * if one is set.  The entry records how the delete was confirmed so that 
+ * deletes run with confirmation turned off can be told apart.  The delete has
+ * already happened, so a failure to write the entry is reported rather than
+ * treated as fatal.
+ */
+fn write_audit_entry(table: &str, sql: &str, confirmation: &str) {
+    let path = match &TMSADM_ARGS.audit_log {
+        Some(p) => get_absolute_path(p),
+        None => return,
+    };
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let entry = serde_json::json!({
+        "time": time,
+        "user": std::env::var("USER").unwrap_or_default(),
+        "operation": "DELETE",
+        "table": table,
+        "dbpath": db_file_path(),
+        "sql": sql,
+        "confirmation": confirmation,
+    });
+    let written = fs::OpenOptions::new().create(true).append(true).open(&path)
+        .and_then(|mut f| writeln!(f, "{}", entry));
+    if let Err(e) = written {
+        eprintln!("Unable to write audit log {}: {}", path, e);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// process_init:
+// ---------------------------------------------------------------------------
+/** Create a database with the TMS tables.  An existing file is only used if
+ * --force is set, in which case any missing tables are created and existing
+ * tables are left untouched.
+ */
+fn process_init() {
+    if dry_run_unsupported("INIT") {return;}
+    let dbpath = db_file_path();
+    if Path::new(&dbpath).exists() && !TMSADM_ARGS.force {
+        panic!("Database file already exists: {} (use --force to create any missing tables)", dbpath);
+    }
+
+    // Create the tables in a single transaction.
+    let sql = "BEGIN;\n".to_string() + CREATE_TABLES + "\nCOMMIT;";
+    let cmd = make_sqlite3_raw_cmd(&sql);
+    run_command(cmd, "INIT database", false);
+    println!("Initialized TMS database: {}", dbpath);
+}
+
+// ---------------------------------------------------------------------------
+// create_db_dir:
+// ---------------------------------------------------------------------------
+/** Create the database file's directory, and any missing parents, so that a
+ * new database can be initialized without creating them by hand.
+ */
+fn create_db_dir() {
+    let dbpath = db_file_path();
+    let dir = match Path::new(&dbpath).parent() {
+        Some(d) if !d.as_os_str().is_empty() && !d.exists() => d,
+        _ => return,
+    };
+    if let Err(e) = fs::create_dir_all(dir) {
+        panic!("Unable to create database directory {}: {}", dir.display(), e);
+    }
+    println!("Created database directory: {}", dir.display());
+}
+
+// ---------------------------------------------------------------------------
+// process_export:
+// ---------------------------------------------------------------------------
+/** Write the records that LIST would show to the --output file in the chosen
+ * format.  With --gzip the output is compressed as it's written and ".gz" is
+ * appended to the file name.  An existing file is only overwritten if --force
+ * is set.
+ */
+fn process_export(resource: &TmsResource) {
+    let path = match &TMSADM_ARGS.output {
+        Some(p) => get_absolute_path(p),
+        None => panic!("The EXPORT operation requires the --output option."),
+    };
+
+    // Stream the records into the file.
+    let task = format!("EXPORT {}", resource.table());
+    let path = write_output_file(path, |out| {
+        if TMSADM_ARGS.all_pages {
+            run_pages(resource, &task, out);
+        } else {
+            run_command_to(make_sqlite3_cmd(&list_sql(resource)), &task, true, out);
+        }
+    });
+    println!("Exported {} to {}.", resource.table(), path);
+    if TMSADM_ARGS.checksum {write_checksum(Path::new(&path));}
+}
+
+// ---------------------------------------------------------------------------
+// process_dump:
+// ---------------------------------------------------------------------------
+/** Write sqlite3's .dump of the --table tables, or else the resource's table,
+ * to the --output file or stdout.  Unlike EXPORT, the dump has the SQL that
+ * recreates each table along with its indexes and triggers.
+ */
+fn process_dump() {
+    let tables = match (&TMSADM_ARGS.table, current_resource()) {
+        (t, _) if !t.is_empty() => t.clone(),
+        (_, Some(r)) => vec![r.table().to_string()],
+        _ => panic!("The DUMP operation requires the --resource or --table option."),
+    };
+    for table in &tables {
+        if column_types(table).is_empty() {
+            panic!("Table {} does not exist in {}.", table, db_file_path());
+        }
+    }
+    let task = format!("DUMP {}", tables.join(" "));
+    let dump = |out: &mut dyn Write| {
+        let mut cmd = new_sqlite3_cmd();
+        cmd.arg(db_path());
+        cmd.arg(format!(".dump {}", tables.join(" ")));
+        if TMSADM_ARGS.debug {
+            println!("*** {} command *** \n{}\n", task, format_command(&cmd));
+        }
+        let result = run_sqlite3(command_runner(), &mut cmd, &task, None, &mut |reader| {
+            io::copy(reader, out).map(|_| ())
+        });
+        if let Err(e) = result {
+            panic!("{}: unable to write the dump: {}", task, e);
+        }
+    };
+    match &TMSADM_ARGS.output {
+        Some(p) => {
+            let path = write_output_file(get_absolute_path(p), dump);
+            println!("Dumped {} to {}.", tables.join(", "), path);
+            if TMSADM_ARGS.checksum {write_checksum(Path::new(&path));}
+        },
+        None if TMSADM_ARGS.gzip => panic!("The --gzip option requires the --output option."),
+        None => with_output(|out| {dump(out); Ok(())}),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// write_output_file:
+// ---------------------------------------------------------------------------
+/** Create the output file, which must not already exist unless --force is 
+ * set, and write to it, compressing with gzip and appending ".gz" to its 
+ * name when --gzip is set.  The path of the file written is returned.
+ */
+fn write_output_file(mut path: String, write: impl FnOnce(&mut dyn Write)) -> String {
+    if TMSADM_ARGS.gzip {path += ".gz";}
+    if Path::new(&path).exists() && !TMSADM_ARGS.force {
+        panic!("Output file already exists: {} (use --force to overwrite)", path);
+    }
+    let file = match File::create(&path) {
+        Ok(f) => f,
+        Err(e) => panic!("Unable to create output file {}: {}", path, e),
+    };
+    let finished = if TMSADM_ARGS.gzip {
+        let mut enc = GzEncoder::new(BufWriter::new(file), Compression::default());
+        write(&mut enc);
+        enc.finish().and_then(|mut w| w.flush())
+    } else {
+        let mut w = BufWriter::new(file);
+        write(&mut w);
+        w.flush()
+    };
+    if let Err(e) = finished {
+        panic!("Unable to write output file {}: {}", path, e);
+    }
+    path
+}
+
+// ---------------------------------------------------------------------------
+// write_checksum:
+// ---------------------------------------------------------------------------
+/** Write the SHA-256 of the output file to a .sha256 file beside it, in the
+ * format that sha256sum -c also accepts.
+ */
+fn write_checksum(path: &Path) {
+    let sidecar = checksum_path(path);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let line = format!("{}  {}\n", file_sha256(path), name);
+    if let Err(e) = fs::write(&sidecar, line) {
+        panic!("Unable to write checksum file {}: {}", sidecar.display(), e);
+    }
+    println!("Wrote checksum to {}.", sidecar.display());
+}
+
+// ---------------------------------------------------------------------------
+// process_verify_export:
+// ---------------------------------------------------------------------------
+/** Compare the file's SHA-256 with the one recorded by --checksum, exiting 
+ * with a non-zero code if they differ.
+ */
+fn process_verify_export(path: &str) {
+    let path = get_absolute_path(path);
+    let sidecar = checksum_path(Path::new(&path));
+    let recorded = match fs::read_to_string(&sidecar) {
+        Ok(text) => text.split_whitespace().next().unwrap_or_default().to_ascii_lowercase(),
+        Err(e) => panic!("Unable to read checksum file {}: {}", sidecar.display(), e),
+    };
+    let actual = file_sha256(Path::new(&path));
+    if actual == recorded {
+        println!("{}: OK", path);
+    } else {
+        println!("{}: checksum mismatch (expected {}, found {})", path, recorded, actual);
+        exit(1);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// checksum_path:
+// ---------------------------------------------------------------------------
+/** Return the path of the file's checksum file. */
+fn checksum_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".sha256");
+    PathBuf::from(name)
+}
+
+// ---------------------------------------------------------------------------
+// file_sha256:
+// ---------------------------------------------------------------------------
+/** Return the file's SHA-256 as lowercase hex. */
+fn file_sha256(path: &Path) -> String {
+    let mut file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) => panic!("Unable to read {}: {}", path.display(), e),
+    };
+    let mut hasher = Sha256::new();
+    if let Err(e) = io::copy(&mut file, &mut hasher) {
+        panic!("Unable to read {}: {}", path.display(), e);
+    }
+    hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// ---------------------------------------------------------------------------
+// check_rows_returned:
+// ---------------------------------------------------------------------------
+/** Exit with EXIT_EMPTY if --fail-on-empty is set and the read operation just
+ * completed returned no rows, or with EXIT_ROWS if --fail-on-rows is set and
+ * it returned rows.
+ */
+fn check_rows_returned() {
+    let rows = ROW_COUNT.load(Ordering::Relaxed) > 0;
+    if TMSADM_ARGS.fail_on_empty && !rows {
+        exit(EXIT_EMPTY);
+    }
+    if TMSADM_ARGS.fail_on_rows && rows {
+        exit(EXIT_ROWS);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// process_optimize:
+// ---------------------------------------------------------------------------
+/** Refresh the query planner's statistics with PRAGMA optimize, preceded by
+ * a full ANALYZE when --analyze is set.
+ */
+fn process_optimize() {
+    if dry_run_unsupported("OPTIMIZE") {return;}
+    check_db_writable();
+    let sql = if TMSADM_ARGS.analyze {"ANALYZE; PRAGMA optimize;"} else {"PRAGMA optimize;"};
+    run_capture(sql, "OPTIMIZE database");
+    println!("{} database: {}", if TMSADM_ARGS.analyze {"Analyzed and optimized"} else {"Optimized"}, 
+             db_file_path());
+}
+
+// ---------------------------------------------------------------------------
+// process_dump_all:
+// ---------------------------------------------------------------------------
+/** Write every record of the TMS tables, and of any --table tables, to its
+ * own file in the --output-dir directory in the chosen format.  Each file is
+ * named after its table with an extension for the format.  The directory is
+ * created if needed and existing files are only overwritten if --force is
+ * set.  With --stable, each table's records are written in rowid order, 
+ * which for the TMS tables is id order.  With --jobs, up to that many tables
+ * are written at once, each by its own read-only sqlite3 process, and a 
+ * table that fails doesn't stop the others; the tables that failed are
+ * listed at the end and the exit code is 1.
+ */
+fn process_dump_all() {
+    let dir = match &TMSADM_ARGS.output_dir {
+        Some(d) => get_absolute_path(d),
+        None => panic!("The DUMP-ALL operation requires the --output-dir option."),
+    };
+    if matches!(TMSADM_ARGS.format, TmsFormat::authorized_keys | TmsFormat::template | TmsFormat::none) 
+        || TMSADM_ARGS.fingerprint || !TMSADM_ARGS.columns_exclude.is_empty() {
+        panic!("The DUMP-ALL operation doesn't support the authorized_keys, template and none formats, --fingerprint or --columns-exclude.");
+    }
+    let ext = match TMSADM_ARGS.format {
+        TmsFormat::json if TMSADM_ARGS.json_off => "txt",
+        TmsFormat::ndjson => "ndjson",
+        TmsFormat::yaml => "yaml",
+        TmsFormat::sql_insert => "sql",
+        TmsFormat::tsv => "tsv",
+        _ => "json",
+    };
+    if let Err(e) = fs::create_dir_all(&dir) {
+        panic!("Unable to create output directory {}: {}", dir, e);
+    }
+
+    // Write each table to its file.
+    let mut tables: Vec<String> = TMS_TABLES.iter().map(|t| t.to_string()).collect();
+    for table in &TMSADM_ARGS.table {
+        if !tables.contains(table) {tables.push(table.clone());}
+    }
+    let mut paths = Vec::new();
+    for table in tables {
+        if column_types(&table).is_empty() {
+            panic!("Table {} does not exist in {}.", table, db_file_path());
+        }
+        let path = Path::new(&dir).join(format!("{}.{}", table, ext));
+        if path.exists() && !TMSADM_ARGS.force {
+            panic!("Output file already exists: {} (use --force to overwrite)", path.display());
+        }
+        paths.push((table, path));
+    }
+    let jobs = match TMSADM_ARGS.jobs {
+        Some(n) => n,
+        None => {
+            for (table, path) in &paths {dump_table(table, path);}
+            return;
+        },
+    };
+
+    // Hand the tables out to the jobs, collecting each one's failure.
+    let next = AtomicUsize::new(0);
+    let failed: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(paths.len()) {
+            scope.spawn(|| {
+                while let Some((table, path)) = paths.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let result = std::panic::catch_unwind(|| dump_table(table, path));
+                    if let Err(payload) = result {
+                        let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+                            .or_else(|| payload.downcast_ref::<String>().cloned())
+                            .unwrap_or_else(|| "unknown error".to_string());
+                        failed.lock().unwrap_or_else(|e| e.into_inner()).push((table.clone(), message));
+                    }
+                }
+            });
+        }
+    });
+    let mut failed = failed.into_inner().unwrap_or_else(|e| e.into_inner());
+    println!("Wrote {} of {} tables.", paths.len() - failed.len(), paths.len());
+    if !failed.is_empty() {
+        failed.sort();
+        for (table, message) in &failed {
+            println!("Failed to write {}: {}", table, message.trim());
+        }
+        exit(1);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// dump_table:
+// ---------------------------------------------------------------------------
+/** Write every record of the table to the DUMP-ALL output file, and its 
+ * checksum with --checksum. 
+ */
+fn dump_table(table: &str, path: &Path) {
+    let mut w = match File::create(path) {
+        Ok(f) => BufWriter::new(f),
+        Err(e) => panic!("Unable to create output file {}: {}", path.display(), e),
+    };
+    let sql = format!("SELECT * FROM {}{}", table, if TMSADM_ARGS.stable {" ORDER BY rowid"} else {""});
+    DUMP_TABLE.with(|t| *t.borrow_mut() = Some(table.to_string()));
+    run_command_to(make_sqlite3_raw_cmd(&sql), &format!("DUMP {}", table), true, &mut w);
+    DUMP_TABLE.with(|t| *t.borrow_mut() = None);
+    if let Err(e) = w.flush() {
+        panic!("Unable to write output file {}: {}", path.display(), e);
+    }
+    println!("Wrote {} to {}.", table, path.display());
+    if TMSADM_ARGS.checksum {write_checksum(path);}
+}
+
+// ---------------------------------------------------------------------------
+// output_table:
+// ---------------------------------------------------------------------------
+/** Return the table whose records are being written, which is the table being
+ * dumped by DUMP-ALL or otherwise the resource's table, if there is one.
+ */
+fn output_table() -> Option<String> {
+    match DUMP_TABLE.with(|t| t.borrow().clone()) {
+        Some(t) => Some(t),
+        None => current_resource().map(|r| r.table().to_string()),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// run_pages:
+// ---------------------------------------------------------------------------
+/** Write all of the resource's selected records to the writer, fetching them
+ * in pages of --limit records until a page comes back short, so that memory
+ * use is bounded by the page size.  Pages are taken in id order, descending
+ * with --sort id --desc, and each page starts after the last id of the one before, so
+ * no page rescans earlier records and records aren't repeated or skipped if
+ * the table changes between pages.  The last id of each page is found first
+ * from the table's ids alone.  Echoed SQL and column headers are only written
+ * for the first page, and the json format's pages are merged into one array.
+ */
+fn run_pages(resource: &TmsResource, task: &str, out: &mut dyn Write) {
+    let limit = TMSADM_ARGS.limit.unwrap_or_default() as u64;
+    let json = TMSADM_ARGS.format == TmsFormat::json && !TMSADM_ARGS.json_off;
+    let table = resource.table();
+    let (after, bound, last, order) = match TMSADM_ARGS.desc {
+        true => ("<", ">=", "MIN", " DESC"),
+        false => (">", "<=", "MAX", ""),
+    };
+    let mut rows = 0;
+    let mut start: Option<i64> = None;
+    loop {
+        // Find the id that ends the page, which only reads the ids.
+        let after = start.map(|id| format!("id {after} {id}"));
+        let wh = match &after {
+            Some(cond) => make_where_clause_and(cond),
+            None => make_where_clause(),
+        };
+        let ids = format!("SELECT id FROM {table} {wh} ORDER BY id{order} LIMIT {limit}");
+        let end = run_capture(&format!("SELECT {last}(id) FROM ({ids})"), "FIND page end");
+        let end: i64 = match end.trim() {
+            "" => break,
+            n => match n.parse() {
+                Ok(n) => n,
+                Err(_) => panic!("FIND page end: unexpected id returned by {}: {}", sqlite3_program(), n),
+            },
+        };
+        let page = match &after {
+            Some(cond) => format!("{cond} AND id {bound} {end}"),
+            None => format!("id {bound} {end}"),
+        };
+        let sql = format!("{} {} ORDER BY id{order}", list_sql(resource).trim_end(), make_where_clause_and(&page));
+        CONTINUED_PAGE.store(start.is_some(), Ordering::Relaxed);
+        let before = ROW_COUNT.load(Ordering::Relaxed);
+        let cmd = make_sqlite3_raw_cmd(&sql);
+        let written = if json {
+            // Rewrite the page's row punctuation to continue a single array.
+            let mut page = Vec::new();
+            run_command_to(cmd, task, true, &mut page);
+            String::from_utf8_lossy(&page).lines().try_for_each(|line| {
+                match json_row(line) {
+                    Some(row) => {
+                        rows += 1;
+                        write!(out, "{}{}", if rows == 1 {"["} else {json_separator()}, row)
+                    },
+                    None if line.trim() == "[]" => Ok(()),
+                    None => writeln!(out, "{}", line),
+                }
+            })
+        } else {
+            run_command_to(cmd, task, true, out);
+            Ok(())
+        };
+        if let Err(e) = written.and_then(|_| out.flush()) {
+            if e.kind() == io::ErrorKind::BrokenPipe {return;}
+            panic!("{}: {}", task, e);
+        }
+        if ROW_COUNT.load(Ordering::Relaxed) - before < limit {break;}
+        start = Some(end);
+    }
+    CONTINUED_PAGE.store(false, Ordering::Relaxed);
+    if json {
+        let closed = if rows == 0 {out.write_all(b"[]\n")} else {out.write_all(b"]\n")};
+        if let Err(e) = closed.and_then(|_| out.flush()) {
+            if e.kind() == io::ErrorKind::BrokenPipe {return;}
+            panic!("{}: {}", task, e);
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// list_sql:
+// ---------------------------------------------------------------------------
+/** Return the SELECT prototype that LIST uses for the resource.  With
+ * --columns-exclude, --localtime or a localtime --col-transform, the 
+ * prototype's * is replaced by the table's other columns, with timestamps 
+ * converted to local time.  With --with-rowid, the rowid comes first.
+ */
+fn list_sql(resource: &TmsResource) -> String {
+    let sql = match resource {
+        TmsResource::pubkey => LIST_PUBKEY,
+        TmsResource::client => LIST_CLIENT,
+        TmsResource::delegation => list_delegation_sql(),
+    };
+    let transformed = TMSADM_ARGS.col_transform.iter().any(|(_, t)| *t == ColTransform::localtime);
+    let rewrite = !TMSADM_ARGS.columns_exclude.is_empty() || TMSADM_ARGS.localtime || transformed;
+    if !rewrite && !TMSADM_ARGS.with_rowid {return sql.to_string();}
+
+    // Every prototype starts with SELECT * or, when joined, SELECT d.*.
+    let table = resource.table();
+    let (star, prefix) = if sql.starts_with("SELECT d.* ") {("SELECT d.*", "d.")} else {("SELECT *", "")};
+    let mut columns = Vec::new();
+    if TMSADM_ARGS.with_rowid {
+        // Without the alias, sqlite3 names the rowid after an INTEGER PRIMARY KEY column.
+        columns.push(format!("{}rowid AS rowid", prefix));
+    }
+    if rewrite {
+        columns.extend(included_columns(table).iter().map(|(c, _)| {
+            let column = format!("{}\"{}\"", prefix, c);
+            let localtime = (TMSADM_ARGS.localtime && is_time_column(c)) 
+                || col_transform(c) == Some(&ColTransform::localtime);
+            if localtime {localtime_sql(&column, c)} else {column}
+        }));
+    } else {
+        columns.push(star["SELECT ".len()..].to_string());
+    }
+    format!("SELECT {}{}", columns.join(", "), &sql[star.len()..])
+}
+
+// ---------------------------------------------------------------------------
+// check_client_exists:
+// ---------------------------------------------------------------------------
+/** Warn when no client has the --client-id, within the --tenant if one is 
+ * given, since the delegations selected by it would otherwise just come up
+ * empty.  The warning doesn't stop the operation.
+ */
+fn check_client_exists(id: &str) {
+    let mut sql = format!("SELECT COUNT(*) FROM clients WHERE {} = {}", CLIENT_ID_COLUMN, sql_quote(id));
+    if let Some(tenant) = &TMSADM_ARGS.tenant {
+        sql += &format!(" AND {} = {}", TENANT_COLUMN, sql_quote(tenant));
+    }
+    if run_capture(&sql, "COUNT clients").trim() == "0" {
+        match &TMSADM_ARGS.tenant {
+            Some(tenant) => eprintln!("Warning: no client with id {} in tenant {}.", id, tenant),
+            None => eprintln!("Warning: no client with id {}.", id),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// check_rowid:
+// ---------------------------------------------------------------------------
+/** Refuse --with-rowid for a table that has no rowid, because it was created
+ * WITHOUT ROWID, or whose rowid is hidden by a column named rowid.
+ */
+fn check_rowid(table: &str) {
+    if column_types(table).iter().any(|(c, _)| c.eq_ignore_ascii_case("rowid")) {
+        panic!("The --with-rowid option can't be used with the {} table, which has a column named rowid.", table);
+    }
+    let sql = format!("SELECT sql FROM sqlite_master WHERE type = 'table' AND name = {}", sql_quote(table));
+    let create = run_capture(&sql, "READ table definition").to_ascii_uppercase();
+    if create.split_whitespace().collect::<Vec<_>>().windows(2).any(|w| w == ["WITHOUT", "ROWID"]) {
+        panic!("The --with-rowid option can't be used with the {} table, which is a WITHOUT ROWID table.", table);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// col_transform:
+// ---------------------------------------------------------------------------
+/** Return the --col-transform given for the column, if any. */
+fn col_transform(column: &str) -> Option<&'static ColTransform> {
+    TMSADM_ARGS.col_transform.iter().find(|(c, _)| c == column).map(|(_, t)| t)
+}
+
+// ---------------------------------------------------------------------------
+// is_time_column:
+// ---------------------------------------------------------------------------
+/** Return true if --localtime converts the column, which is one of the 
+ * --time-columns if any are given, otherwise created, updated or a column
+ * whose name ends in _at.
+ */
+fn is_time_column(column: &str) -> bool {
+    if !TMSADM_ARGS.time_columns.is_empty() {
+        return TMSADM_ARGS.time_columns.iter().any(|c| c == column);
+    }
+    column == "created" || column == "updated" || column.ends_with("_at")
+}
+
+// ---------------------------------------------------------------------------
+// localtime_sql:
+// ---------------------------------------------------------------------------
+/** Return a select list expression that shows the timestamp column in the 
+ * local timezone as YYYY-MM-DD HH:MM:SS, keeping its name.  Integer values
+ * are taken as unix epoch seconds and text values as ISO-8601 times, which
+ * are in the --db-timezone, UTC by default, unless they have an offset.  
+ * Values that aren't timestamps are shown unchanged.
+ */
+fn localtime_sql(column: &str, name: &str) -> String {
+    let text = match db_timezone_modifiers() {
+        None => column.to_string(),
+        Some(mods) => format!("CASE WHEN {column} GLOB '*[Zz]' OR {column} GLOB '*[+-][0-9][0-9]:[0-9][0-9]' \
+                               THEN {column} ELSE datetime({column}, {mods}) END"),
+    };
+    format!("COALESCE(CASE typeof({column}) \
+             WHEN 'integer' THEN strftime('%Y-%m-%d %H:%M:%S', {column}, 'unixepoch', 'localtime') \
+             ELSE strftime('%Y-%m-%d %H:%M:%S', {text}, 'localtime') END, {column}) AS \"{name}\"")
+}
+
+// ---------------------------------------------------------------------------
+// db_timezone_modifiers:
+// ---------------------------------------------------------------------------
+/** Return the sqlite date modifiers that convert a time in the --db-timezone
+ * to UTC, or None when stored times are already UTC.
+ */
+fn db_timezone_modifiers() -> Option<String> {
+    match TMSADM_ARGS.db_timezone.as_deref() {
+        None | Some("utc") => None,
+        Some("local") => Some("'utc'".to_string()),
+        Some(offset) => {
+            // The offset is +HH:MM or -HH:MM, which is subtracted to get UTC.
+            let sign = if offset.starts_with('+') {"-"} else {"+"};
+            Some(format!("'{sign}{} hours', '{sign}{} minutes'", &offset[1..3], &offset[4..6]))
+        },
+    }
+}
+
+// ---------------------------------------------------------------------------
+// included_columns:
+// ---------------------------------------------------------------------------
+/** Return the table's columns and their types, without the --columns-exclude
+ * columns, which must all exist.  At least one column must remain.
+ */
+fn included_columns(table: &str) -> Vec<(String, String)> {
+    let columns = column_types(table);
+    for exclude in &TMSADM_ARGS.columns_exclude {
+        if !columns.iter().any(|(c, _)| c == exclude) {
+            panic!("The {} table has no column named {} to exclude.", table, exclude);
+        }
+    }
+    let columns: Vec<_> = columns.into_iter()
+        .filter(|(c, _)| !TMSADM_ARGS.columns_exclude.contains(c)).collect();
+    if columns.is_empty() {
+        panic!("The --columns-exclude option excludes every column of the {} table.", table);
+    }
+    columns
+}
+
+// ---------------------------------------------------------------------------
+// process_import_pubkey:
+// ---------------------------------------------------------------------------
+/** Insert the pubkeys read from the --input file, or from stdin when the 
+ * input is "-".  The input is either a json array of objects in the same 
+ * format as LIST's json output or, like ndjson output, one json object per
+ * line.  All rows are inserted in a single transaction.  Rows that would 
+ * duplicate a unique key of an existing record are skipped and reported 
+ * rather than failing the import, but any other constraint failure fails it.
+ * Malformed lines of newline-delimited input, including those with unknown
+ * columns or missing a required column, are also skipped and reported by line number, unless 
+ * --strict is set.
+ */
+fn process_import_pubkey() {
+    if dry_run_unsupported("IMPORT") {return;}
+    let (path, text) = match &TMSADM_ARGS.input {
+        Some(p) if p == "-" => {
+            let mut text = String::new();
+            if let Err(e) = io::stdin().read_to_string(&mut text) {
+                panic!("Unable to read import data from stdin: {}", e);
+            }
+            ("stdin".to_string(), text)
+        },
+        Some(p) => {
+            let path = get_absolute_path(p);
+            match fs::read_to_string(&path) {
+                Ok(t) => (path, t),
+                Err(e) => panic!("Unable to read import file {}: {}", path, e),
+            }
+        },
+        None => panic!("The IMPORT operation requires the --input option."),
+    };
+
+    // Build an insert for each row, labelled by its row or line number.
+    let table = TmsResource::pubkey.table();
+    let columns: Vec<String> = column_types(table).into_iter().map(|(name, _)| name).collect();
+    let required = required_columns(table);
+    let mut inserts: Vec<(String, String, Option<Value>)> = Vec::new();
+    let mut malformed = Vec::new();
+    if text.trim_start().starts_with('[') {
+        let rows: Vec<Map<String, Value>> = match serde_json::from_str(&text) {
+            Ok(r) => r,
+            Err(e) => panic!("Import file {} is not a json array of objects: {}", path, e),
+        };
+        for (i, row) in rows.iter().enumerate() {
+            match make_insert_sql(table, row, &columns, &required) {
+                Ok(sql) => inserts.push((format!("row {}", i + 1), sql, row.get("id").cloned())),
+                Err(e) => panic!("Row {}: {}", i + 1, e),
+            }
+        }
+    } else {
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {continue;}
+            let insert = serde_json::from_str::<Map<String, Value>>(line).map_err(|e| e.to_string())
+                .and_then(|row| make_insert_sql(table, &row, &columns, &required).map(|sql| (sql, row.get("id").cloned())));
+            match insert {
+                Ok((sql, id)) => inserts.push((format!("line {}", i + 1), sql, id)),
+                Err(e) if TMSADM_ARGS.strict => panic!("Line {} of {}: {}", i + 1, path, e),
+                Err(e) => malformed.push(format!("line {}: {}", i + 1, e)),
+            }
+        }
+    }
+
+    // Insert each row and report whether it changed the table.
+    let mut script = "BEGIN;\n".to_string();
+    for (_, sql, _) in &inserts {
+        script += sql;
+        script += "SELECT changes();\n";
+    }
+    script += "COMMIT;\n";
+    let out = run_script(&script, "IMPORT pubkeys");
+
+    // Report the results.
+    let mut inserted = 0;
+    let mut skipped = Vec::new();
+    for ((label, _, id), changes) in inserts.iter().zip(out.lines()) {
+        if changes.trim() == "0" {
+            match id {
+                Some(id) => skipped.push(format!("{} (id {})", label, id)),
+                None => skipped.push(label.clone()),
+            }
+        } else {
+            inserted += 1;
+        }
+    }
+    record_affected(inserted);
+    println!("Imported {} of {} pubkeys from {}.", inserted, inserts.len() + malformed.len(), path);
+    if !skipped.is_empty() {
+        println!("Skipped {} duplicate pubkeys: {}", skipped.len(), skipped.join(", "));
+    }
+    if !malformed.is_empty() {
+        println!("Skipped {} malformed lines:", malformed.len());
+        for m in &malformed {println!("  {}", m);}
+    }
+}
+
+// ---------------------------------------------------------------------------
+// process_update_client:
+// ---------------------------------------------------------------------------
+/** Enable or disable the selected clients, which is a reversible alternative
+ * to deleting them.  Unless confirmation is turned off, a sample of the 
+ * clients that would change is shown along with their total and the user is
+ * prompted.  Clients already in the requested state aren't shown.  The 
+ * number of clients changed is shown.  A dry run only shows the number of 
+ * clients that would change and the UPDATE statement.
+ */
+fn process_update_client(enabled: bool) {
+    let action = if enabled {"enabling"} else {"disabling"};
+
+    // The selection is a subquery so that a limit applies.  Clients already
+    // in the requested state are left untouched and not counted.
+    let enabled = enabled as i32;
+    let selected = format!("enabled != {enabled} AND id IN (SELECT id FROM clients {})", make_sql("").trim());
+    let sql = format!("UPDATE clients SET enabled = {enabled}, updated = strftime('%Y-%m-%dT%H:%M:%SZ', 'now') \
+                       WHERE {selected}");
+    let count = run_capture(&format!("SELECT COUNT(*) FROM clients WHERE {selected}"), "COUNT clients");
+    if TMSADM_ARGS.dry_run {
+        println!("Would update {} client(s) with: {}", count.trim(), sql);
+        println!("Dry run, nothing changed.");
+        return;
+    }
+    if !TMSADM_ARGS.confirm_delete_off {
+        let total = count.trim().parse().unwrap_or_default();
+        preview_selected(&format!("SELECT * FROM clients WHERE {selected} ORDER BY id"), total);
+        println!("{} client(s) would change.", group_thousands(total));
+        if !confirm_records(action) {
+            println!("Nothing changed.");
+            return;
+        }
+    }
+
+    let changed = run_capture(&format!("{}; SELECT changes();", sql), "UPDATE clients");
+    record_affected(changed.trim().parse().unwrap_or_default());
+    println!("{} {} client(s).", if enabled == 1 {"Enabled"} else {"Disabled"}, changed.trim());
+}
+
+// ---------------------------------------------------------------------------
+// process_copy_client:
+// ---------------------------------------------------------------------------
+/** Copy the selected clients to another tenant in a single transaction and
+ * report the new record ids.  The copy is refused before anything is written
+ * if any of the selected client_ids already exist in the target tenant, or 
+ * if more than one selected client has the same client_id, since 
+ * (client_id, tenant) is unique.
+ */
+fn process_copy_client(tenant: &str) {
+    if dry_run_unsupported("UPDATE --copy-to-tenant") {return;}
+    let quoted = sql_quote(tenant);
+    let selected = format!("SELECT id FROM clients {}", make_sql("").trim());
+
+    // Check for selected clients that share a client_id, which would collide
+    // with each other in the target tenant.
+    let sql = format!("SELECT client_id FROM clients WHERE id IN ({selected}) GROUP BY client_id \
+                       HAVING COUNT(*) > 1 ORDER BY client_id");
+    let mut shared = Vec::new();
+    query_rows(&sql, "CHECK selected client_ids", |row| 
+        shared.push(row.get("client_id").and_then(Value::as_str).unwrap_or_default().to_string()));
+    if !shared.is_empty() {
+        panic!("Unable to copy clients to tenant {}, more than one selected client has these client_ids: {}", 
+               tenant, shared.join(", "));
+    }
+
+    // Check for clients that would collide in the target tenant.
+    let sql = format!("SELECT client_id FROM clients WHERE tenant = {quoted} AND client_id IN \
+                       (SELECT client_id FROM clients WHERE id IN ({selected})) ORDER BY client_id");
+    let mut dups = Vec::new();
+    query_rows(&sql, "CHECK client duplicates", |row| 
+        dups.push(row.get("client_id").and_then(Value::as_str).unwrap_or_default().to_string()));
+    if !dups.is_empty() {
+        panic!("Unable to copy clients to tenant {}, these client_ids already exist there: {}", 
+               tenant, dups.join(", "));
+    }
+
+    check_db_writable();
+    if !TMSADM_ARGS.confirm_delete_off {
+        preview_records(&list_sql(&TmsResource::client));
+        if !confirm_records("copying") {
+            println!("Nothing copied.");
+            return;
+        }
+    }
+
+    // Since no duplicates exist, the new records are the only ones in the
+    // target tenant with the selected client_ids.
+    let now = "strftime('%Y-%m-%dT%H:%M:%SZ', 'now')";
+    let script = format!("BEGIN;
+CREATE TEMP TABLE copy_ids AS SELECT client_id FROM clients WHERE id IN ({selected});
+INSERT INTO clients (tenant, app_name, app_version, client_id, client_secret, tms_user_id, enabled, created, updated) 
+SELECT {quoted}, app_name, app_version, client_id, client_secret, tms_user_id, enabled, {now}, {now} 
+    FROM clients WHERE id IN ({selected}) ORDER BY id;
+SELECT id FROM clients WHERE tenant = {quoted} AND client_id IN (SELECT client_id FROM copy_ids) ORDER BY id;
+COMMIT;
+");
+    let output = run_script(&script, "COPY clients");
+    let ids: Vec<&str> = output.split_whitespace().collect();
+    record_affected(ids.len() as u64);
+    if ids.is_empty() {
+        println!("No clients selected, nothing copied.");
+    } else {
+        println!("Copied {} client(s) to tenant {}, new ids: {}", ids.len(), tenant, ids.join(", "));
+    }
+}
+
+// ---------------------------------------------------------------------------
+// process_rename_client:
+// ---------------------------------------------------------------------------
+/** Change a client's client_id in each selected tenant, along with the
+ * client_id of its delegations and pubkeys, in a single transaction.  The
+ * rename is refused before anything is written if the new client_id already
+ * exists in any of the tenants.
+ */
+fn process_rename_client(old: &str, new: &str) {
+    if dry_run_unsupported("UPDATE --rename") {return;}
+    if old == new {
+        panic!("The --rename option requires different old and new client_ids.");
+    }
+    let scope = make_where_clause_and(&format!("client_id = {}", sql_quote(old)));
+    let tenants = format!("SELECT tenant FROM clients {}", scope);
+
+    // Find the clients being renamed and any that would collide.
+    let count = run_capture(&format!("SELECT COUNT(*) FROM clients {}", scope), "COUNT clients");
+    let count = count.trim();
+    if count == "0" {
+        println!("No client with client_id {} was selected, nothing renamed.", old);
+        return;
+    }
+    let sql = format!("SELECT tenant FROM clients WHERE client_id = {} AND tenant IN ({tenants}) ORDER BY tenant",
+                      sql_quote(new));
+    let mut conflicts = Vec::new();
+    query_rows(&sql, "CHECK client conflicts", |row| 
+        conflicts.push(row.get("tenant").and_then(Value::as_str).unwrap_or_default().to_string()));
+    if !conflicts.is_empty() {
+        panic!("Unable to rename client {} to {}, the new client_id already exists in tenant(s): {}", 
+               old, new, conflicts.join(", "));
+    }
+    if !TMSADM_ARGS.confirm_delete_off 
+        && !confirm(&format!("Rename client {} to {} in {} tenant(s)", old, new, count)) {
+        println!("Nothing renamed.");
+        return;
+    }
+
+    // The clients are renamed last since the tenant subquery selects them.
+    let now = "strftime('%Y-%m-%dT%H:%M:%SZ', 'now')";
+    let (old_q, new_q) = (sql_quote(old), sql_quote(new));
+    let script = format!("BEGIN;
+UPDATE delegations SET client_id = {new_q}, updated = {now} WHERE client_id = {old_q} AND tenant IN ({tenants});
+UPDATE pubkeys SET client_id = {new_q}, updated = {now} WHERE client_id = {old_q} AND tenant IN ({tenants});
+UPDATE clients SET client_id = {new_q}, updated = {now} {scope};
+SELECT changes();
+COMMIT;
+");
+    let renamed = run_script(&script, "RENAME client");
+    record_affected(renamed.trim().parse().unwrap_or_default());
+    println!("Renamed client {} to {} in {} tenant(s).", old, new, renamed.trim());
+}
+
+// ---------------------------------------------------------------------------
+// process_rotate_pubkey:
+// ---------------------------------------------------------------------------
+/** Replace a user's public key for a host in a single transaction.  The new
+ * key comes from --public-key or the first line of the --input file, and the
+ * selection must match exactly one pubkey record.  The new record copies the
+ * old record's other columns, with its use count reset, and the old record
+ * is deleted.  The old and new key fingerprints are shown.
+ */
+fn process_rotate_pubkey() {
+    if dry_run_unsupported("ROTATE") {return;}
+    if TMSADM_ARGS.user.is_none() || TMSADM_ARGS.host.is_none() {
+        panic!("The ROTATE operation requires the --user and --host options.");
+    }
+    let text = match (&TMSADM_ARGS.public_key, &TMSADM_ARGS.input) {
+        (Some(k), _) => k.clone(),
+        (None, Some(p)) => {
+            let path = get_absolute_path(p);
+            match fs::read_to_string(&path) {
+                Ok(t) => t.lines().find(|l| !l.trim().is_empty()).unwrap_or_default().to_string(),
+                Err(e) => panic!("Unable to read public key file {}: {}", path, e),
+            }
+        },
+        (None, None) => panic!("The ROTATE operation requires the --public-key or --input option."),
+    };
+    let key = match PublicKey::from_openssh(text.trim()) {
+        Ok(k) => k,
+        Err(e) => panic!("The new public key is not in valid OpenSSH format: {}", e),
+    };
+    let new_fingerprint = key.fingerprint(HashAlg::Sha256).to_string();
+
+    // Find the record being replaced.
+    let sql = make_sql("SELECT id, public_key, public_key_fingerprint FROM pubkeys ");
+    let mut old = Vec::new();
+    query_rows(&sql, "FIND pubkey", |row| old.push(row));
+    if old.len() != 1 {
+        panic!("The ROTATE operation must select exactly one pubkey record, but {} were selected.", old.len());
+    }
+    let old = &old[0];
+    let old_id = old.get("id").and_then(Value::as_i64).unwrap_or_default();
+    let old_fingerprint = match PublicKey::from_openssh(old.get("public_key").and_then(Value::as_str).unwrap_or_default()) {
+        Ok(k) => k.fingerprint(HashAlg::Sha256).to_string(),
+        Err(_) => old.get("public_key_fingerprint").and_then(Value::as_str).unwrap_or_default().to_string(),
+    };
+    if !TMSADM_ARGS.confirm_delete_off {
+        process_list_pubkey();
+        if !confirm_records("rotation") {
+            println!("Nothing changed.");
+            return;
+        }
+    }
+
+    // Insert the new key and delete the old one together.
+    let now = "strftime('%Y-%m-%dT%H:%M:%SZ', 'now')";
+    let script = format!("BEGIN;
+INSERT INTO pubkeys (tenant, client_id, tms_user_id, host, host_account, public_key_fingerprint, 
+    public_key, key_type, key_bits, max_uses, remaining_uses, initial_ttl_minutes, expires_at, created, updated) 
+SELECT tenant, client_id, tms_user_id, host, host_account, {}, {}, {}, {}, max_uses, max_uses, 
+    initial_ttl_minutes, expires_at, {now}, {now} FROM pubkeys WHERE id = {old_id};
+SELECT last_insert_rowid();
+DELETE FROM pubkeys WHERE id = {old_id};
+COMMIT;
+", sql_quote(&new_fingerprint), sql_quote(&key.to_openssh().unwrap_or(text)), 
+       sql_quote(key.algorithm().as_str()), key_bits(&key));
+    let new_id = run_script(&script, "ROTATE pubkey");
+    record_affected(1);
+    println!("Rotated pubkey record {} to record {}.", old_id, new_id.trim());
+    println!("  Old fingerprint: {}", old_fingerprint);
+    println!("  New fingerprint: {}", new_fingerprint);
+}
+
+// ---------------------------------------------------------------------------
+// key_bits:
+// ---------------------------------------------------------------------------
+/** Return the size of the public key in bits, or 0 if it isn't known. */
+fn key_bits(key: &PublicKey) -> usize {
+    match key.algorithm() {
+        Algorithm::Ed25519 | Algorithm::SkEd25519 | Algorithm::SkEcdsaSha2NistP256 => 256,
+        Algorithm::Ecdsa {curve: EcdsaCurve::NistP256} => 256,
+        Algorithm::Ecdsa {curve: EcdsaCurve::NistP384} => 384,
+        Algorithm::Ecdsa {curve: EcdsaCurve::NistP521} => 521,
+        Algorithm::Rsa {..} => key.key_data().rsa()
+            .and_then(|k| k.n.as_positive_bytes())
+            .map_or(0, |n| n.len() * 8 - n[0].leading_zeros() as usize),
+        _ => 0,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// process_validate_pubkey:
+// ---------------------------------------------------------------------------
+/** Check that each selected pubkey record holds a public key in valid OpenSSH
+ * format and report the ids of those that don't.  The database isn't 
+ * modified.  The process exits with status 1 if any key is malformed.
+ */
+fn process_validate_pubkey() {
+    let sql = make_sql("SELECT id, public_key FROM pubkeys ");
+    let mut checked = 0;
+    let mut malformed = 0;
+    query_rows(&sql, "VALIDATE pubkeys", |row| {
+        checked += 1;
+        let key = row.get("public_key").and_then(Value::as_str).unwrap_or_default();
+        if let Err(e) = PublicKey::from_openssh(key) {
+            malformed += 1;
+            println!("Malformed public key in pubkey record {}: {}", 
+                     row.get("id").unwrap_or(&Value::Null), e);
+        }
+    });
+    println!("Validated {} pubkeys, {} malformed.", checked, malformed);
+    if malformed > 0 {exit(1);}
+}
+
+// ---------------------------------------------------------------------------
+// make_insert_sql:
+// ---------------------------------------------------------------------------
+/** Return an INSERT statement for the json object.  Rows that conflict with
+ * a unique key of an existing record are ignored, while other constraint 
+ * failures still fail the statement.  Column names must be plain identifiers
+ * naming the table's columns, values must be json scalars and the required 
+ * columns must have non-null values, otherwise an error message is returned.
+ */
+fn make_insert_sql(table: &str, row: &Map<String, Value>, columns: &[String], required: &[String]) 
+    -> Result<String, String> {
+    let mut cols = Vec::new();
+    let mut vals = Vec::new();
+    for (col, val) in row {
+        if !is_identifier(col) {
+            return Err(format!("invalid column name: {}", col));
+        }
+        if !columns.contains(col) {
+            return Err(format!("table {} has no column named {}", table, col));
+        }
+        let literal = match val {
+            Value::Null => "NULL".to_string(),
+            Value::Bool(b) => if *b {"1".to_string()} else {"0".to_string()},
+            Value::Number(n) => n.to_string(),
+            Value::String(s) => sql_quote(s),
+            _ => return Err(format!("column {} must have a scalar value", col)),
+        };
+        cols.push(col.as_str());
+        vals.push(literal);
+    }
+    if let Some(col) = required.iter().find(|c| row.get(*c).is_none_or(Value::is_null)) {
+        return Err(format!("column {} is required", col));
+    }
+    Ok(format!("INSERT INTO {} ({}) VALUES ({}) ON CONFLICT DO NOTHING;\n", table, cols.join(", "), vals.join(", ")))
+}
+
+// ---------------------------------------------------------------------------
+// process_expire_cleanup:
+// ---------------------------------------------------------------------------
+/** Delete the selected delegations that have expired.  A preview first shows
+ * how many expired today, within the last week and before that, then the 
+ * delete is confirmed, archived and run as any other delegation delete is, 
+ * since the WHERE clause includes the expiry condition with --expire-cleanup.
+ */
+fn process_expire_cleanup() {
+    let wh = make_where_clause();
+
+    // Preview the expired delegations by age.
+    let preview = format!("SELECT CASE WHEN date(expires_at) = date('now') THEN 'today' \
+        WHEN julianday(expires_at) >= julianday('now', '-7 days') THEN 'this week' ELSE 'older' END AS expired, \
+        COUNT(*) AS count FROM delegations {} GROUP BY expired ORDER BY MAX(julianday(expires_at)) DESC", wh);
+    run_command(make_sqlite3_raw_cmd(&preview), "PREVIEW expired delegations", true);
+    let count = run_capture(&format!("SELECT COUNT(*) FROM delegations {}", wh), "COUNT expired delegations");
+    if count.trim() == "0" {
+        println!("No expired delegations, nothing deleted.");
+        return;
+    }
+    let sql = delete_prototype(DELETE_DELEGATION, TmsResource::delegation.table());
+    let confirmation = match delete_confirmed(LIST_DELEGATION, &sql) {
+        Some(c) => c,
+        None => return,
+    };
+
+    // Run the delete.
+    let sql = make_sql(&sql);
+    let deleted = run_delete(&sql, LIST_DELEGATION, "DELETE expired delegations");
+    write_audit_entry("delegations", &sql, confirmation);
+    println!("Deleted {} expired delegations.", deleted);
+    if TMSADM_ARGS.verify {verify_deleted(LIST_DELEGATION);}
+}
+
+// ---------------------------------------------------------------------------
+// process_dedupe:
+// ---------------------------------------------------------------------------
+/** Delete the resource's selected records that share the values of the 
+ * columns with another selected record, keeping the record with the highest
+ * id, which is the newest, in each group.  The records that would be removed 
+ * are previewed first and, after confirmation, deleted in a transaction.
+ */
+fn process_dedupe(resource: &TmsResource, columns: &[String]) {
+    let t = resource.table();
+    check_columns(t, columns);
+    let keep = format!("id NOT IN (SELECT MAX(id) FROM {} {} GROUP BY {})", t, make_where_clause(), 
+                       columns.join(", "));
+    let wh = make_where_clause_and(&keep);
+    let count = run_capture(&format!("SELECT COUNT(*) FROM {} {}", t, wh), "COUNT duplicate records");
+    let count = count.trim();
+    if count == "0" {
+        println!("No duplicate {}, nothing deleted.", t);
+        return;
+    }
+
+    // Preview the duplicates that would be removed.
+    let preview = format!("SELECT * FROM {} {} ORDER BY {}, id LIMIT {}", t, wh, columns.join(", "), 
+                          TMSADM_ARGS.preview_rows);
+    run_command(make_sqlite3_raw_cmd(&preview), "PREVIEW duplicate records", true);
+    let sql = format!("DELETE FROM {} {}", t, wh);
+    if TMSADM_ARGS.dry_run {
+        println!("Would delete {} duplicate {} with: {}", count, t, sql);
+        return;
+    }
+    check_db_writable();
+    let confirmation = match confirm_deletion(|| confirm(&format!("Delete the {} duplicate {}", count, t))) {
+        Some(c) => c,
+        None => return,
+    };
+    let delete = archived_delete_sql(t, strip_where(&wh));
+    let deleted = run_deletes(&format!("BEGIN; {}; SELECT changes(); COMMIT;", delete), "DELETE duplicate records");
+    record_affected(deleted.trim().parse().unwrap_or_default());
+    write_audit_entry(t, &sql, confirmation);
+    println!("Deleted {} duplicate {}.", deleted.trim(), t);
+}
+
+// ---------------------------------------------------------------------------
+// process_apply_retention:
+// ---------------------------------------------------------------------------
+/** Delete the records that are older than the retention policy allows.  The
+ * number of records that would be deleted from each table is shown first;
+ * with --dry-run nothing else happens.  Otherwise, after confirmation, all
+ * the deletes run in a single transaction and the number of records removed
+ * from each table is reported.
+ */
+fn process_apply_retention(policy_file: &str) {
+    let path = get_absolute_path(policy_file);
+    let policy = RetentionPolicy::load(&path);
+    let rules = policy.rules();
+    if rules.is_empty() {
+        println!("The retention policy in {} has no rules, nothing deleted.", path);
+        return;
+    }
+
+    // Preview what would be deleted.
+    for (table, rule) in &rules {
+        let cond = rule.condition();
+        let sql = format!("SELECT COUNT(*) FROM {} WHERE {}", table, cond);
+        let count = run_capture(&sql, "COUNT expired records");
+        println!("{}: {} record(s) older than {} days by {}: DELETE FROM {} WHERE {}", 
+                 table, count.trim(), rule.max_age_days, rule.column, table, cond);
+    }
+    if TMSADM_ARGS.dry_run {
+        println!("Dry run, nothing deleted.");
+        return;
+    }
+    check_db_writable();
+    let confirmation = match confirm_deletion(|| confirm_records("deletion")) {
+        Some(c) => c,
+        None => return,
+    };
+
+    // Delete in one transaction, reporting each table's count.
+    let mut sql = "BEGIN; ".to_string();
+    for (table, rule) in &rules {
+        sql += &format!("{}; SELECT '{}', changes(); ", archived_delete_sql(table, &rule.condition()), table);
+    }
+    sql += "COMMIT;";
+    let out = run_deletes(&sql, "APPLY retention");
+    let mut total = 0;
+    for line in out.lines() {
+        if let Some((table, count)) = line.split_once('|') {
+            total += count.parse::<u64>().unwrap_or_default();
+            println!("Deleted {} record(s) from {}.", count, table);
+            if let Some((_, rule)) = rules.iter().find(|(t, _)| *t == table) {
+                write_audit_entry(table, &format!("DELETE FROM {} WHERE {}", table, rule.condition()), confirmation);
+            }
+        }
+    }
+    record_affected(total);
+}
+
+// ---------------------------------------------------------------------------
+// process_purge_soft_deleted:
+// ---------------------------------------------------------------------------
+/** Remove the records that --soft-delete marked deleted before the cutoff
+ * from the resources' tables or, without --resource, from every TMS table
+ * that has a deleted_at column.  The number of records to be removed from 
+ * each table is shown first; with --dry-run nothing else happens.  
+ * Otherwise, after confirmation, all the deletes run in a single transaction.
+ */
+fn process_purge_soft_deleted(cutoff: &str) {
+    let tables: Vec<&str> = if TMSADM_ARGS.resource.is_empty() {
+        TMS_TABLES.iter().copied().filter(|t| has_deleted_column(t)).collect()
+    } else {
+        TMSADM_ARGS.resource.iter().map(TmsResource::table).collect()
+    };
+    for table in &tables {
+        if !has_deleted_column(table) {
+            panic!("The --purge-soft-deleted-before option requires a {} column, which the {} table doesn't have.", 
+                   DELETED_COLUMN, table);
+        }
+    }
+    if tables.is_empty() {
+        panic!("The --purge-soft-deleted-before option requires a table with a {} column, but none of the TMS tables have one.", 
+               DELETED_COLUMN);
+    }
+    let cond = format!("{col} IS NOT NULL AND julianday({col}) < julianday({})", sql_quote(cutoff), col = DELETED_COLUMN);
+
+    // Preview what would be deleted.
+    for table in &tables {
+        let count = run_capture(&format!("SELECT COUNT(*) FROM {} WHERE {}", table, cond), "COUNT soft-deleted records");
+        println!("{}: {} record(s) marked deleted before {}: DELETE FROM {} WHERE {}", 
+                 table, count.trim(), cutoff, table, cond);
+    }
+    if TMSADM_ARGS.dry_run {
+        println!("Dry run, nothing deleted.");
+        return;
+    }
+    check_db_writable();
+    let confirmation = match confirm_deletion(|| confirm_records("deletion")) {
+        Some(c) => c,
+        None => return,
+    };
+
+    // Delete in one transaction, reporting each table's count.
+    let mut sql = "BEGIN; ".to_string();
+    for table in &tables {
+        sql += &format!("{}; SELECT '{}', changes(); ", archived_delete_sql(table, &cond), table);
+    }
+    sql += "COMMIT;";
+    let out = run_deletes(&sql, "PURGE soft-deleted records");
+    let mut total = 0;
+    for line in out.lines() {
+        if let Some((table, count)) = line.split_once('|') {
+            total += count.parse::<u64>().unwrap_or_default();
+            println!("Deleted {} record(s) from {}.", count, table);
+            write_audit_entry(table, &format!("DELETE FROM {} WHERE {}", table, cond), confirmation);
+        }
+    }
+    record_affected(total);
+}
+
+// ---------------------------------------------------------------------------
+// process_batch:
+// ---------------------------------------------------------------------------
+/** Run the tmsadm commands in the batch file in order, one per line with 
+ * shell-style quoting, skipping blank lines and lines starting with #.  Each
+ * command runs against this run's database unless it names its own, while
+ * this run holds the database lock.  The lock file is passed to each command
+ * as its stdin, so that commands against this run's database share the lock
+ * while those naming another database take that database's own lock.  Since
+ * the commands can't read stdin, --ids-stdin and --input - are refused.
+ * Unless confirmation is turned off, the
+ * commands are listed and confirmed once, and then run without prompting.
+ * With --dry-run, each command runs as a dry run instead, which shows the 
+ * statements and record counts of its changes without making them, so the
+ * batch prints the whole change plan.  The batch stops at the first failed 
+ * command unless --continue-on-error is set, and ends with a summary.
+ */
+fn process_batch(path: &str, lock: Option<&File>) {
+    let path = get_absolute_path(path);
+    let text = match fs::read_to_string(&path) {
+        Ok(t) => t,
+        Err(e) => panic!("Unable to read batch file {}: {}", path, e),
+    };
+
+    // Parse every command before running any of them.
+    let mut commands = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {continue;}
+        let words = match shlex::split(line) {
+            Some(w) => w,
+            None => panic!("Unbalanced quotes on line {} of batch file {}.", i + 1, path),
+        };
+        if words.iter().any(|w| w == "--batch" || w.starts_with("--batch=")) {
+            panic!("Batch files cannot contain --batch, see line {} of {}.", i + 1, path);
+        }
+        let stdin = words.windows(2).any(|w| w[0] == "--input" && w[1] == "-") 
+            || words.iter().any(|w| w == "--ids-stdin" || w == "--input=-");
+        if stdin {
+            panic!("Batch commands cannot read stdin, see line {} of {}.", i + 1, path);
+        }
+        commands.push((i + 1, line.to_string(), words));
+    }
+    if commands.is_empty() {
+        println!("Batch file {} has no commands.", path);
+        return;
+    }
+
+    if !TMSADM_ARGS.confirm_delete_off && !TMSADM_ARGS.dry_run {
+        for (n, line, _) in &commands {println!("{:>4}: {}", n, line);}
+        if !confirm(&format!("Run the above {} command(s)", commands.len())) {
+            println!("Nothing run.");
+            return;
+        }
+    }
+
+    let program = match std::env::current_exe() {
+        Ok(p) => p,
+        Err(e) => panic!("Unable to find the tmsadm program: {}", e),
+    };
+    let (mut succeeded, mut failed) = (0, 0);
+    for (n, line, words) in &commands {
+        println!("\n*** Line {}: {}", n, line);
+        let mut cmd = Command::new(&program);
+        cmd.args(words).env(BATCH_LOCK_ENV, "1");
+        if let Some(lock) = lock {
+            match lock.try_clone() {
+                Ok(f) => {cmd.stdin(f);},
+                Err(e) => panic!("Unable to pass the database lock to line {}: {}", n, e),
+            }
+        }
+        let has = |names: &[&str]| words.iter().any(|w| 
+            names.iter().any(|n| w == n || w.starts_with(&format!("{}=", n))));
+        if !has(&["--dbpath", "-d"]) {cmd.arg("--dbpath").arg(db_path());}
+        if !has(&["--config"]) {cmd.arg("--config").arg(get_absolute_path(&TMSADM_ARGS.config));}
+        if TMSADM_ARGS.dry_run {cmd.arg("--dry-run");} else {cmd.arg("--yes");}
+        match cmd.status() {
+            Ok(status) if status.success() => succeeded += 1,
+            Ok(status) => {
+                failed += 1;
+                eprintln!("Line {} failed with {}.", n, status);
+                if !TMSADM_ARGS.continue_on_error {break;}
+            },
+            Err(e) => panic!("Unable to run {}: {}", program.display(), e),
+        }
+    }
+
+    let skipped = commands.len() - succeeded - failed;
+    let finished = if TMSADM_ARGS.dry_run {"Batch dry run finished"} else {"Batch finished"};
+    println!("\n{}: {} succeeded, {} failed, {} not run.", finished, succeeded, failed, skipped);
+    if failed > 0 {exit(1);}
+}
+
+// ---------------------------------------------------------------------------
+// process_all_profiles:
+// ---------------------------------------------------------------------------
+/** Run this command once for each profile in the configuration file, in name
+ * order, by running tmsadm with the same arguments and --profile.  Each run
+ * checks its own permissions and takes its own database's lock.  A line with
+ * the profile's name comes before each run's output.  Commands that can 
+ * change a database are refused without --i-understand, and those still 
+ * prompt for confirmation in each run unless it's turned off.  Every profile
+ * is run even if some fail, and the failures are counted at the end.
+ */
+fn process_all_profiles() {
+    let destructive = TMSADM_ARGS.operation.as_ref().is_some_and(TmsOperation::is_mutation)
+        || TMSADM_ARGS.undo || !TMSADM_ARGS.set_pragma.is_empty();
+    if destructive && !TMSADM_ARGS.i_understand {
+        panic!("The --all-profiles option refuses commands that can change the databases unless --i-understand is also given.");
+    }
+    let path = get_absolute_path(&TMSADM_ARGS.config);
+    let profiles: Vec<String> = TmsadmConfig::load(&path).profile.into_keys().collect();
+    if profiles.is_empty() {
+        panic!("The --all-profiles option requires profiles, but configuration file {} has none.", path);
+    }
+    let program = match std::env::current_exe() {
+        Ok(p) => p,
+        Err(e) => panic!("Unable to find the tmsadm program: {}", e),
+    };
+    let args: Vec<OsString> = std::env::args_os().skip(1)
+        .filter(|a| a != "--all-profiles" && a != "--i-understand").collect();
+
+    let mut failed = Vec::new();
+    for name in &profiles {
+        println!("== profile {} ==", name);
+        let mut cmd = Command::new(&program);
+        cmd.args(&args).arg("--profile").arg(name);
+        match cmd.status() {
+            Ok(status) if status.success() => (),
+            Ok(status) => {
+                eprintln!("Profile {} failed with {}.", name, status);
+                failed.push(name.as_str());
+            },
+            Err(e) => panic!("Unable to run {}: {}", program.display(), e),
+        }
+    }
+    println!("\nAll profiles finished: {} succeeded, {} failed.", profiles.len() - failed.len(), failed.len());
+    if !failed.is_empty() {exit(1);}
+}
+
+// ---------------------------------------------------------------------------
+// process_undo:
+// ---------------------------------------------------------------------------
+/** Restore the database from the newest <dbpath>.bak-* backup, or from the 
+ * --from backup, after confirmation.  Tmsadm doesn't make these backups, so
+ * they must be copied beforehand by other means.  The current database is 
+ * kept as <dbpath>.pre-undo and the backup is copied rather than moved, so 
+ * both remain available.  An existing .pre-undo file from an earlier undo is
+ * never replaced, so the undo is refused until it's moved away.  A database
+ * with a non-empty write-ahead log isn't restored, since the log would be 
+ * applied to the restored file.
+ */
+fn process_undo() {
+    if dry_run_unsupported("--undo") {return;}
+    let dbpath = db_file_path();
+    let wal = dbpath.clone() + "-wal";
+    if fs::metadata(&wal).is_ok_and(|m| m.len() > 0) {
+        panic!("The database has a write-ahead log that must be checkpointed before undoing: {}", wal);
+    }
+    let backup = match &TMSADM_ARGS.from {
+        Some(p) => get_absolute_path(p),
+        None => {
+            let backups = find_backups(&dbpath);
+            if backups.is_empty() {
+                panic!("No backups of {} were found ({}.bak-*).", dbpath, dbpath);
+            }
+            if backups.len() > 1 {
+                println!("Available backups, newest first (use --from to choose another):");
+                for b in &backups {println!("  {}", b);}
+            }
+            backups[0].clone()
+        },
+    };
+    if !Path::new(&backup).is_file() {
+        panic!("Backup file does not exist: {}", backup);
+    }
+    let pre_undo = dbpath.clone() + ".pre-undo";
+    let exists = Path::new(&dbpath).exists();
+    if exists && Path::new(&pre_undo).exists() {
+        panic!("{} is left from an earlier undo; move it away before undoing again.", pre_undo);
+    }
+    if exists {check_db_writable();}
+
+    let question = format!("Restore {} from {}", dbpath, backup);
+    if !TMSADM_ARGS.confirm_delete_off && !confirm(&question) {
+        println!("Nothing changed.");
+        return;
+    }
+    if exists {
+        if let Err(e) = fs::rename(&dbpath, &pre_undo) {
+            panic!("Unable to preserve {} as {}: {}", dbpath, pre_undo, e);
+        }
+    }
+    if let Err(e) = fs::copy(&backup, &dbpath) {
+        panic!("Unable to restore {} from {}: {} (the previous database is {})", dbpath, backup, e, pre_undo);
+    }
+    println!("Restored {} from {}; the previous database is {}.", dbpath, backup, pre_undo);
+}
+
+// ---------------------------------------------------------------------------
+// find_backups:
+// ---------------------------------------------------------------------------
+/** Return the <dbpath>.bak-* files beside the database, newest first. */
+fn find_backups(dbpath: &str) -> Vec<String> {
+    let path = Path::new(dbpath);
+    let dir = path.parent().unwrap_or(Path::new("/"));
+    let prefix = path.file_name().unwrap_or_default().to_string_lossy().into_owned() + ".bak-";
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(e) => panic!("Unable to read directory {}: {}", dir.display(), e),
+    };
+    let mut backups: Vec<(std::time::SystemTime, String)> = entries.filter_map(Result::ok)
+        .filter(|e| e.file_name().to_string_lossy().starts_with(&prefix))
+        .filter_map(|e| {
+            let modified = e.metadata().and_then(|m| m.modified()).ok()?;
+            Some((modified, e.path().to_string_lossy().into_owned()))
+        })
+        .collect();
+    backups.sort_by(|a, b| b.cmp(a));
+    backups.into_iter().map(|(_, p)| p).collect()
+}
+
+// ---------------------------------------------------------------------------
+// process_print_path:
+// ---------------------------------------------------------------------------
+/** Print the absolute database path that --dbpath resolves to, after any 
+ * --profile and variable expansion, and whether the file exists.
+ */
+fn process_print_path() {
+    let path = db_file_path();
+    let exists = if Path::new(&path).is_file() {"exists"} else {"does not exist"};
+    println!("{} ({})", path, exists);
+}
+
+// ---------------------------------------------------------------------------
+// process_show_config:
+// ---------------------------------------------------------------------------
+/** Print the settings that result from combining the configuration file's
+ * profile, environment variables and the command line.
+ */
+fn process_show_config() {
+    let args = &*TMSADM_ARGS;
+    let profile = match &args.profile {
+        Some(p) => format!("{} (from {})", p, get_absolute_path(&args.config)),
+        None => "none".to_string(),
+    };
+    let settings = [
+        ("operation", format!("{:?}", args.operation)),
+        ("resource", format!("{:?}", args.resource)),
+        ("profile", profile),
+        ("dbpath", db_path()),
+        ("sqlite3 program", sqlite3_program().to_string()),
+        ("key", if args.key.is_some() {"set"} else {"not set"}.to_string()),
+        ("format", format!("{:?}", args.format)),
+        ("json_off", args.json_off.to_string()),
+        ("echo", args.echo.to_string()),
+        ("header_off", args.header_off.to_string()),
+        ("limit", args.limit.map_or("none".to_string(), |n| n.to_string())),
+        ("max_rows", args.max_rows.map_or("none".to_string(), |n| n.to_string())),
+        ("busy_timeout", format!("{:?}", args.busy_timeout)),
+        ("confirm_delete_off", args.confirm_delete_off.to_string()),
+        ("confirm_threshold", format!("{:?}", args.confirm_threshold)),
+    ];
+    for (name, value) in settings {
+        println!("{:<20}{}", name.to_string() + ":", value);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// process_check_config:
+// ---------------------------------------------------------------------------
+/** Check every setting in the configuration file and print the problems
+ * found, exiting with a non-zero code if there are any.  The file is examined
+ * as generic TOML so that all problems are reported, not just the first.
+ */
+fn process_check_config() {
+    let path = get_absolute_path(&TMSADM_ARGS.config);
+    let mut problems = Vec::new();
+    let mut profiles = 0;
+    match fs::read_to_string(&path).map(|t| t.parse::<toml::Table>()) {
+        Err(e) => problems.push(format!("unable to read the file: {}", e)),
+        Ok(Err(e)) => problems.push(format!("invalid TOML: {}", e)),
+        Ok(Ok(table)) => {
+            for (key, value) in &table {
+                match (key.as_str(), value.as_table()) {
+                    ("profile", Some(t)) => {
+                        profiles = t.len();
+                        for (name, profile) in t {
+                            check_config_profile(name, profile, &mut problems);
+                        }
+                    },
+                    ("profile", None) => problems.push("profile: must be a table of profiles".to_string()),
+                    ("permissions", Some(t)) => {
+                        for (user, ops) in t {check_config_permissions(user, ops, &mut problems);}
+                    },
+                    ("permissions", None) => 
+                        problems.push("permissions: must be a table of users".to_string()),
+                    ("resource_aliases", Some(t)) => {
+                        for (alias, resource) in t {check_config_alias(alias, resource, &mut problems);}
+                    },
+                    ("resource_aliases", None) => 
+                        problems.push("resource_aliases: must be a table of aliases".to_string()),
+                    ("sanitize", Some(t)) => {
+                        for (column, rule) in t {
+                            if !matches!(rule.as_str(), Some("drop" | "hash")) {
+                                problems.push(format!("sanitize.{}: must be \"drop\" or \"hash\"", column));
+                            }
+                        }
+                    },
+                    ("sanitize", None) => 
+                        problems.push("sanitize: must be a table of columns".to_string()),
+                    _ => problems.push(format!("{}: unknown setting", key)),
+                }
+            }
+            if let Some(name) = &TMSADM_ARGS.profile {
+                if table.get("profile").and_then(|p| p.get(name)).is_none() {
+                    problems.push(format!("profile.{}: selected by --profile but not defined", name));
+                }
+            }
+        },
+    }
+
+    if problems.is_empty() {
+        println!("Configuration file {} is valid ({} profile(s)).", path, profiles);
+    } else {
+        println!("Configuration file {} has {} problem(s):", path, problems.len());
+        for problem in &problems {println!("  {}", problem);}
+        exit(1);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// check_config_permissions:
+// ---------------------------------------------------------------------------
+/** Add the problems with a user's permitted operations to the list. */
+fn check_config_permissions(user: &str, ops: &toml::Value, problems: &mut Vec<String>) {
+    let ops = match ops.as_array() {
+        Some(a) => a,
+        None => {
+            problems.push(format!("permissions.{}: must be a list of operations", user));
+            return;
+        },
+    };
+    for op in ops {
+        match op.as_str() {
+            Some(name) if TmsOperation::from_str(name).is_ok() || PSEUDO_OPERATIONS.contains(&name) => (),
+            _ => problems.push(format!("permissions.{}: unknown operation {}", user, op)),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// check_config_alias:
+// ---------------------------------------------------------------------------
+/** Add the problems with a resource alias to the list. */
+fn check_config_alias(alias: &str, resource: &toml::Value, problems: &mut Vec<String>) {
+    if TmsResource::from_str(alias).is_ok() {
+        problems.push(format!("resource_aliases.{}: is already a resource name", alias));
+    }
+    match resource.as_str().map(TmsResource::from_str) {
+        Some(Ok(_)) => (),
+        _ => problems.push(format!("resource_aliases.{}: unknown resource {}", alias, resource)),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// check_config_profile:
+// ---------------------------------------------------------------------------
+/** Add the problems with a configuration file profile to the list. */
+fn check_config_profile(name: &str, profile: &toml::Value, problems: &mut Vec<String>) {
+    let settings = match profile.as_table() {
+        Some(t) => t,
+        None => {
+            problems.push(format!("profile.{}: must be a table of settings", name));
+            return;
+        },
+    };
+    for (key, value) in settings {
+        let problem = match (key.as_str(), value) {
+            ("dbpath", toml::Value::String(p)) => {
+                let dbpath = get_absolute_path(p);
+                match Path::new(&dbpath).parent() {
+                    Some(dir) if dir.is_dir() => None,
+                    _ => Some(format!("directory of {} does not exist", dbpath)),
+                }
+            },
+            ("format", toml::Value::String(f)) => match f.parse::<TmsFormat>() {
+                Ok(_) => None,
+                Err(_) => Some(format!("unknown format {}", f)),
+            },
+            ("limit", toml::Value::Integer(n)) if *n < 0 || *n > i32::MAX as i64 => 
+                Some(format!("limit must be between 0 and {}", i32::MAX)),
+            ("limit", toml::Value::Integer(_)) => None,
+            ("max_rows", toml::Value::Integer(n)) if *n < 0 => Some("max_rows cannot be negative".to_string()),
+            ("max_rows", toml::Value::Integer(_)) => None,
+            ("require_sanitize", toml::Value::Boolean(_)) => None,
+            ("require_sanitize", _) => Some("must be true or false".to_string()),
+            ("dbpath", _) | ("format", _) => Some("must be a string".to_string()),
+            ("limit", _) | ("max_rows", _) => Some("must be an integer".to_string()),
+            _ => Some("unknown setting".to_string()),
+        };
+        if let Some(problem) = problem {
+            problems.push(format!("profile.{}.{}: {}", name, key, problem));
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// check_permission:
+// ---------------------------------------------------------------------------
+/** Refuse the operation if the configuration file has a permissions table
+ * that doesn't allow it for the current $USER.  The operation is either an
+ * --operation name or one of the PSEUDO_OPERATIONS that stand for the modes
+ * which change the database without one.  A missing configuration file
+ * permits everything.  This only guards against mistakes; the database 
+ * file's own permissions are what actually control access.
+ */
+fn check_permission(operation: &str) {
+    let path = get_absolute_path(&TMSADM_ARGS.config);
+    if !Path::new(&path).is_file() {return;}
+    let permissions = match TmsadmConfig::load(&path).permissions {
+        Some(p) => p,
+        None => return,
+    };
+    let user = std::env::var("USER").unwrap_or_default();
+    let allowed = permissions.get(&user).or_else(|| permissions.get("*"));
+    let permitted = allowed.is_some_and(|ops| ops.iter().any(|op| 
+        op.eq_ignore_ascii_case(operation)));
+    if !permitted {
+        panic!("operation {} not permitted for user {}", operation, user);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// check_require_sanitize:
+// ---------------------------------------------------------------------------
+/** Refuse to export, or to LIST into an --output file, without --sanitize if
+ * the --profile sets require_sanitize.  The DUMP and DUMP-ALL operations 
+ * write whole tables that can't be sanitized, so they're refused too.
+ */
+fn check_require_sanitize(operation: &TmsOperation) {
+    let name = match &TMSADM_ARGS.profile {
+        Some(n) => n,
+        None => return,
+    };
+    let path = get_absolute_path(&TMSADM_ARGS.config);
+    let required = TmsadmConfig::load(&path).profile.get(name).and_then(|p| p.require_sanitize);
+    if required != Some(true) {return;}
+    match operation {
+        TmsOperation::EXPORT if !TMSADM_ARGS.sanitize => 
+            panic!("Profile {} requires the --sanitize option to export.", name),
+        TmsOperation::LIST if TMSADM_ARGS.output.is_some() && !TMSADM_ARGS.sanitize => 
+            panic!("Profile {} requires the --sanitize option to list into an output file.", name),
+        TmsOperation::DUMP | TmsOperation::DUMPALL => 
+            panic!("Profile {} requires sanitized exports, which the {} operation can't write.", name, operation.name()),
+        _ => (),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// load_sanitize_rules:
+// ---------------------------------------------------------------------------
+/** Return the configuration file's sanitize rules, which must exist. */
+fn load_sanitize_rules() -> BTreeMap<String, SanitizeRule> {
+    let path = get_absolute_path(&TMSADM_ARGS.config);
+    if !Path::new(&path).is_file() {
+        panic!("The --sanitize option requires the configuration file {}.", path);
+    }
+    let rules = TmsadmConfig::load(&path).sanitize;
+    if rules.is_empty() {
+        panic!("The --sanitize option requires a sanitize table in configuration file {}.", path);
+    }
+    rules
+}
+
+// ---------------------------------------------------------------------------
+// process_schema_version:
+// ---------------------------------------------------------------------------
+/** Print the database's schema version.  The highest version recorded in a
+ * known migrations table is used if there is one, otherwise the version comes
+ * from PRAGMA user_version.
+ */
+fn process_schema_version() {
+    let names: Vec<String> = SCHEMA_VERSION_TABLES.iter().map(|t| sql_quote(t)).collect();
+    let sql = format!("SELECT name FROM sqlite_master WHERE type = 'table' AND name IN ({})", 
+                      names.join(", "));
+    let found = run_capture(&sql, "FIND schema version table");
+    let table = SCHEMA_VERSION_TABLES.iter().find(|t| found.lines().any(|l| l.trim() == **t));
+    let (version, source) = match table {
+        Some(t) => (run_capture(&format!("SELECT MAX(version) FROM {}", t), "READ schema version"), 
+                    format!("table {}", t)),
+        None => (run_capture("PRAGMA user_version", "READ schema version"), 
+                 "PRAGMA user_version".to_string()),
+    };
+    if TMSADM_ARGS.raw_scalar {
+        print!("{}", version.trim());
+        let _ = io::stdout().flush();
+    } else {
+        println!("Schema version {} (from {})", version.trim(), source);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// process_show_pragmas:
+// ---------------------------------------------------------------------------
+/** Print the database's storage and tuning PRAGMAs. */
+fn process_show_pragmas() {
+    for pragma in SHOW_PRAGMAS {
+        let value = run_capture(&format!("PRAGMA {}", pragma), "READ pragma");
+        println!("{} = {}", pragma, value.trim());
+    }
+}
+
+// ---------------------------------------------------------------------------
+// process_vacuum_estimate:
+// ---------------------------------------------------------------------------
+/** Print the database file's size and an estimate of the space VACUUM would
+ * reclaim, which is the size of the pages on the freelist.  VACUUM can also
+ * reclaim space from partly filled pages, so the estimate is a lower bound.
+ */
+fn process_vacuum_estimate() {
+    let out = run_capture("PRAGMA page_size; PRAGMA page_count; PRAGMA freelist_count;", "READ page counts");
+    let values: Vec<u64> = out.lines().filter_map(|l| l.trim().parse().ok()).collect();
+    let (page_size, page_count, free) = match values[..] {
+        [size, count, free] => (size, count, free),
+        _ => panic!("READ page counts: unexpected values returned by {}: {}", sqlite3_program(), out.trim()),
+    };
+    let file_size = match fs::metadata(db_file_path()) {
+        Ok(m) => m.len(),
+        Err(e) => panic!("Unable to read the size of {}: {}", db_file_path(), e),
+    };
+    let reclaimable = free * page_size;
+    let percent = if page_count == 0 {0.0} else {free as f64 * 100.0 / page_count as f64};
+    println!("file size = {} bytes", group_thousands(file_size));
+    println!("page size = {} bytes", group_thousands(page_size));
+    println!("pages = {} ({} free)", group_thousands(page_count), group_thousands(free));
+    println!("reclaimable = {} bytes ({:.1}%)", group_thousands(reclaimable), percent);
+}
+
+// ---------------------------------------------------------------------------
+// process_expect_counts:
+// ---------------------------------------------------------------------------
+/** Count the records of each table named in the expected counts file and 
+ * report whether the count matches, exiting with a non-zero code if any 
+ * table's count differs or the table doesn't exist.  A file with a .json 
+ * extension is read as JSON and any other file as TOML.
+ */
+fn process_expect_counts(path: &str) {
+    let path = get_absolute_path(path);
+    let text = match fs::read_to_string(&path) {
+        Ok(t) => t,
+        Err(e) => panic!("Unable to read expected counts file {}: {}", path, e),
+    };
+    let expected: Result<BTreeMap<String, u64>, String> = if path.ends_with(".json") {
+        serde_json::from_str(&text).map_err(|e| e.to_string())
+    } else {
+        toml::from_str(&text).map_err(|e| e.to_string())
+    };
+    let expected = match expected {
+        Ok(m) if !m.is_empty() => m,
+        Ok(_) => panic!("The expected counts file {} doesn't name any tables.", path),
+        Err(e) => panic!("Unable to parse expected counts file {}, which must map table names to counts: {}", path, e),
+    };
+
+    let mut mismatches = 0;
+    for (table, count) in &expected {
+        if !table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') || column_types(table).is_empty() {
+            println!("{}: MISSING (expected {})", table, count);
+            mismatches += 1;
+            continue;
+        }
+        let out = run_capture(&format!("SELECT COUNT(*) FROM {}", table), "COUNT records");
+        let found = match out.trim().parse::<u64>() {
+            Ok(n) => n,
+            Err(_) => panic!("COUNT records: unexpected count returned by {}: {}", sqlite3_program(), out.trim()),
+        };
+        if found == *count {
+            println!("{}: OK ({})", table, found);
+        } else {
+            println!("{}: MISMATCH (expected {}, found {})", table, count, found);
+            mismatches += 1;
+        }
+    }
+    if mismatches > 0 {
+        println!("{} of {} table counts differ.", mismatches, expected.len());
+        exit(1);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// process_dump_schema:
+// ---------------------------------------------------------------------------
+/** Print the CREATE statements for the database's tables, then their indexes
+ * and triggers, each in the order they were created.  With --table or 
+ * --resource, only the statements for those tables are printed.
+ */
+fn process_dump_schema() {
+    let tables = match (&TMSADM_ARGS.table, current_resource()) {
+        (t, _) if !t.is_empty() => t.clone(),
+        (_, Some(r)) => vec![r.table().to_string()],
+        _ => Vec::new(),
+    };
+    for table in &tables {
+        if column_types(table).is_empty() {
+            panic!("Table {} does not exist in {}.", table, db_file_path());
+        }
+    }
+    let mut sql = "SELECT sql || ';' FROM sqlite_master WHERE type IN ('table','index','trigger') \
+                   AND sql IS NOT NULL".to_string();
+    if !tables.is_empty() {
+        let names: Vec<String> = tables.iter().map(|t| sql_quote(t)).collect();
+        sql += &format!(" AND tbl_name IN ({})", names.join(", "));
+    }
+    sql += " ORDER BY CASE type WHEN 'table' THEN 0 WHEN 'index' THEN 1 ELSE 2 END, rowid";
+    let schema = run_capture(&sql, "DUMP schema");
+    with_output(|out| out.write_all(schema.as_bytes()));
+}
+
+// ---------------------------------------------------------------------------
+// process_build_where:
+// ---------------------------------------------------------------------------
+/** Build a WHERE clause for the resource's table by prompting for one 
+ * condition at a time.  Each condition is a column, chosen by name or number
+ * from those listed, an operator and, except for the NULL tests, a value, 
+ * which is quoted unless it's a number for a numeric column.  Conditions are
+ * chained with AND or OR.  The finished clause is shown in the form that 
+ * --sqlwhere takes and can be run with LIST, along with any other filters.
+ */
+fn process_build_where(resource: &TmsResource) {
+    const OPERATORS: [&str; 9] = ["=", "!=", "<", "<=", ">", ">=", "LIKE", "IS NULL", "IS NOT NULL"];
+    let columns = column_types(resource.table());
+    println!("Columns of {}:", resource.table());
+    for (i, (name, kind)) in columns.iter().enumerate() {println!("{:>4}. {} ({})", i + 1, name, kind);}
+
+    let mut clause = String::from("WHERE");
+    loop {
+        let column = loop {
+            let answer = match prompt_line("Column (name or number)") {
+                Some(a) => a,
+                None => return,
+            };
+            let found = match answer.parse::<usize>() {
+                Ok(n) if n >= 1 => columns.get(n - 1),
+                _ => columns.iter().find(|(name, _)| name.eq_ignore_ascii_case(&answer)),
+            };
+            match found {
+                Some(c) => break c,
+                None => println!("{} isn't a column of {}.", answer, resource.table()),
+            }
+        };
+        let operator = loop {
+            let answer = match prompt_line(&format!("Operator ({})", OPERATORS.join(", "))) {
+                Some(a) => a.to_ascii_uppercase(),
+                None => return,
+            };
+            match OPERATORS.iter().find(|op| **op == answer.split_whitespace().collect::<Vec<_>>().join(" ")) {
+                Some(op) => break *op,
+                None => println!("{} isn't one of the operators.", answer),
+            }
+        };
+        clause += &format!(" {} {}", column.0, operator);
+        if !operator.starts_with("IS") {
+            let value = match prompt_line("Value") {
+                Some(v) => v,
+                None => return,
+            };
+            let numeric = ["INT", "REAL", "FLOA", "DOUB", "NUMERIC"].iter()
+                .any(|t| column.1.to_ascii_uppercase().contains(t));
+            let literal = if numeric && value.parse::<f64>().is_ok() {value} else {sql_quote(&value)};
+            clause += &format!(" {}", literal);
+        }
+        println!("  {}", clause);
+        let more = loop {
+            match prompt_line("Add another condition (and, or, no)").map(|a| a.to_ascii_lowercase()).as_deref() {
+                Some("and") => break "AND",
+                Some("or") => break "OR",
+                Some("no") | Some("n") | Some("") | None => break "",
+                Some(a) => println!("{} isn't and, or or no.", a),
+            }
+        };
+        if more.is_empty() {break;}
+        clause += &format!(" {}", more);
+    }
+
+    println!("\nThe clause, for use with --sqlwhere:\n\n  {}", shlex::try_quote(&clause).unwrap_or_default());
+    if confirm(&format!("List the {} it selects", resource.table())) {
+        let sql = format!("{}{}{}{}", list_sql(resource), make_where_clause_and(&format!("({})", strip_where(&clause))), 
+                          make_order_clause(), make_limit_clause());
+        run_command(make_sqlite3_raw_cmd(&sql), &format!("LIST {}", resource.table()), true);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// prompt_line:
+// ---------------------------------------------------------------------------
+/** Ask the user for a line of input, returning it trimmed, or None at the 
+ * end of the input.
+ */
+fn prompt_line(question: &str) -> Option<String> {
+    print!("{}: ", question);
+    let _ = io::stdout().flush();
+    let mut input = String::new();
+    match io::stdin().read_line(&mut input) {
+        Ok(0) => {
+            println!("\nNothing built.");
+            None
+        },
+        Ok(_) => Some(input.trim().to_string()),
+        Err(e) => panic!("Unable to read the answer: {}", e),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// process_set_pragmas:
+// ---------------------------------------------------------------------------
+/** Set the --set-pragma PRAGMAs.  Changing the page size or auto_vacuum mode
+ * requires a VACUUM, which rewrites the whole database, so unless 
+ * confirmation is turned off the user is prompted first.  The new values are
+ * read back and shown, since sqlite ignores some changes, such as to a WAL
+ * database's page size.
+ */
+fn process_set_pragmas() {
+    if dry_run_unsupported("--set-pragma") {return;}
+    check_db_writable();
+    let vacuum = TMSADM_ARGS.set_pragma.iter()
+        .any(|(name, _)| name == "page_size" || name == "auto_vacuum");
+    if vacuum && !TMSADM_ARGS.confirm_delete_off 
+        && !confirm("Changing page_size or auto_vacuum runs VACUUM, which rewrites the database. Continue") {
+        println!("Nothing changed.");
+        return;
+    }
+    let mut sql = String::new();
+    for (name, value) in &TMSADM_ARGS.set_pragma {
+        sql += &format!("PRAGMA {} = {}; ", name, value);
+    }
+    if vacuum {sql += "VACUUM; ";}
+    run_capture(&sql, "SET pragmas");
+    for (name, _) in &TMSADM_ARGS.set_pragma {
+        let value = run_capture(&format!("PRAGMA {}", name), "READ pragma");
+        println!("{} = {}", name, value.trim());
+    }
+    if TMSADM_ARGS.set_pragma.iter().any(|(name, _)| name == "page_size") 
+        && run_capture("PRAGMA journal_mode", "READ pragma").trim() == "wal" {
+        eprintln!("Warning: the page size of a database in WAL mode can't be changed.");
+    }
+}
+
+// ---------------------------------------------------------------------------
+// process_report_delegations_per_client:
+// ---------------------------------------------------------------------------
+/** Show the number of delegations for each client, most delegations first.
+ * The client's application name is included when the client exists. Record
+ * filters select the delegations that are counted; the limit applies to the
+ * number of clients shown.
+ */
+fn process_report_delegations_per_client() {
+    let mut sql = format!("SELECT d.tenant, d.client_id, c.app_name, COUNT(*) AS delegations \
+        FROM (SELECT * FROM delegations {}) d LEFT JOIN clients c \
+        ON d.client_id = c.client_id AND d.tenant = c.tenant \
+        GROUP BY d.tenant, d.client_id ORDER BY delegations DESC", make_where_clause());
+    sql += &make_limit_clause();
+    let cmd = make_sqlite3_raw_cmd(&sql);
+    run_command(cmd, "REPORT delegations-per-client", true);
+}
+
+// ---------------------------------------------------------------------------
+// process_report_group_by:
+// ---------------------------------------------------------------------------
+/** Count the resource's selected records for each value of the column. */
+fn process_report_group_by(resource: &TmsResource, column: &str) {
+    if !column_types(resource.table()).iter().any(|(name, _)| name == column) {
+        panic!("The {} table has no column named {}.", resource.table(), column);
+    }
+    let mut sql = format!("SELECT {column}, COUNT(*) AS count FROM (SELECT * FROM {} {}) \
+                           GROUP BY {column} ORDER BY 2 DESC", resource.table(), make_where_clause());
+    sql += &make_limit_clause();
+    let cmd = make_sqlite3_raw_cmd(&sql);
+    run_command(cmd, &format!("REPORT group by {}", column), true);
+}
+
+// ---------------------------------------------------------------------------
+// process_report_count_by_day:
+// ---------------------------------------------------------------------------
+/** Count the resource's selected records for each day of the timestamp 
+ * column, in date order.  Records whose column isn't a date are counted
+ * under a null day.
+ */
+fn process_report_count_by_day(resource: &TmsResource, column: &str) {
+    if !column_types(resource.table()).iter().any(|(name, _)| name == column) {
+        panic!("The {} table has no column named {}.", resource.table(), column);
+    }
+    let mut sql = format!("SELECT date({column}) AS day, COUNT(*) AS count FROM (SELECT * FROM {} {}) \
+                           GROUP BY day ORDER BY day", resource.table(), make_where_clause());
+    sql += &make_limit_clause();
+    let cmd = make_sqlite3_raw_cmd(&sql);
+    run_command(cmd, &format!("REPORT count by day of {}", column), true);
+}
+
+// ---------------------------------------------------------------------------
+// process_report_duplicates:
+// ---------------------------------------------------------------------------
+/** List the combinations of the columns' values that more than one of the 
+ * resource's selected records share, most duplicated first.
+ */
+fn process_report_duplicates(resource: &TmsResource, columns: &[String]) {
+    check_columns(resource.table(), columns);
+    let columns = columns.join(", ");
+    let mut sql = format!("SELECT {columns}, COUNT(*) AS count FROM (SELECT * FROM {} {}) \
+                           GROUP BY {columns} HAVING COUNT(*) > 1 ORDER BY count DESC", 
+                          resource.table(), make_where_clause());
+    sql += &make_limit_clause();
+    let cmd = make_sqlite3_raw_cmd(&sql);
+    run_command(cmd, &format!("REPORT duplicates of {}", columns), true);
+}
+
+// ---------------------------------------------------------------------------
+// check_columns:
+// ---------------------------------------------------------------------------
+/** Panic if the table doesn't have each of the columns. */
+fn check_columns(table: &str, columns: &[String]) {
+    let known = column_types(table);
+    for column in columns {
+        if !known.iter().any(|(name, _)| name == column) {
+            panic!("The {} table has no column named {}.", table, column);
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// process_purge_user:
+// ---------------------------------------------------------------------------
+/** Delete all of a user's records from every table in a single transaction.
+ * Tables that reference clients are purged before the clients table.  
+ * Sqlite3 stops at the first failed statement, which closes the connection
+ * before COMMIT and rolls back the whole transaction.
+ */
+fn process_purge_user(user: &str) {
+    let user = sql_quote(user);
+
+    // Delete from each table inside one transaction.
+    let mut sql = "BEGIN; ".to_string();
+    for t in PURGE_TABLES {
+        sql += &format!("DELETE FROM {t} WHERE {USER_COLUMN} = {user}; ");
+    }
+    sql += "COMMIT;";
+
+    if TMSADM_ARGS.dry_run || !TMSADM_ARGS.confirm_delete_off {
+        // First show how many records would be deleted from each table.
+        let counts: Vec<String> = PURGE_TABLES.iter()
+            .map(|t| format!("SELECT '{t}' AS 'table', COUNT(*) AS count FROM {t} WHERE {USER_COLUMN} = {user}"))
+            .collect();
+        let cmd = make_sqlite3_raw_cmd(&counts.join(" UNION ALL "));
+        run_command(cmd, "COUNT user records", true);
+    }
+    if TMSADM_ARGS.dry_run {
+        println!("Would delete the above records with: {}", sql);
+        println!("Dry run, nothing deleted.");
+        return;
+    }
+    check_db_writable();
+    let confirmation = match confirm_deletion(|| confirm_records("deletion")) {
+        Some(c) => c,
+        None => return,
+    };
+    let mut run = "BEGIN; ".to_string();
+    for t in PURGE_TABLES {
+        run += &format!("{}; SELECT changes(); ", archived_delete_sql(t, &format!("{USER_COLUMN} = {user}")));
+    }
+    run += "COMMIT;";
+    let out = run_deletes(&run, "PURGE user");
+    record_affected(out.lines().filter_map(|l| l.trim().parse::<u64>().ok()).sum());
+    for t in PURGE_TABLES {
+        write_audit_entry(t, &format!("DELETE FROM {t} WHERE {USER_COLUMN} = {user}"), confirmation);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// make_sqlite3_cmd:
+// ---------------------------------------------------------------------------
+/** Create the command object that issues an OS call with this format:
+ * 
+ *   sqlite3 [OPTIONS] FILENAME [SQL]
+ * 
+ * The SQL statement is completed with the WHERE clause and limit chosen on
+ * the command line.
+ */
+fn make_sqlite3_cmd(sql_stmt: &str) -> Command {
+    make_sqlite3_raw_cmd(&make_sql(sql_stmt))
+}
+
+// ---------------------------------------------------------------------------
+// make_sql:
+// ---------------------------------------------------------------------------
+/** Complete the SQL statement prototype with the WHERE clause and limit 
+ * chosen on the command line.
+ */
+fn make_sql(sql_stmt: &str) -> String {
+    let mut sql = sql_stmt.to_string();
+    sql += &make_where_clause();
+    sql += &make_order_clause();
+    sql += &make_limit_clause();
+    sql
+}
+
+// ---------------------------------------------------------------------------
+// make_order_clause:
+// ---------------------------------------------------------------------------
+/** Return the ORDER BY clause chosen on the command line, the resource's 
+ * default order for LIST and EXPORT, or the empty string.  With --stable, 
+ * the default order is by id, which is unique, so the order of the records 
+ * never varies.  Orphaned delegations are joined with clients, so their 
+ * default order names the delegations table's alias.  Distinct values have 
+ * no default order.
+ */
+fn make_order_clause() -> String {
+    if let Some(col) = &TMSADM_ARGS.sort {
+        return format!(" ORDER BY {}{}", col, if TMSADM_ARGS.desc {" DESC"} else {""});
+    }
+    let listing = matches!(TMSADM_ARGS.operation, Some(TmsOperation::LIST) | Some(TmsOperation::EXPORT));
+    if !listing || TMSADM_ARGS.no_default_sort || TMSADM_ARGS.distinct.is_some() {return String::new();}
+    if TMSADM_ARGS.since_id.is_some() || TMSADM_ARGS.first {return " ORDER BY id".to_string();}
+    if TMSADM_ARGS.last {return format!(" ORDER BY {} DESC, id DESC", CREATED_COLUMN);}
+    let orphans = TMSADM_ARGS.orphans && current_resource() == Some(&TmsResource::delegation);
+    if TMSADM_ARGS.stable {return if orphans {" ORDER BY d.id"} else {" ORDER BY id"}.to_string();}
+    match current_resource() {
+        Some(TmsResource::delegation) if TMSADM_ARGS.orphans => 
+            format!(" ORDER BY d.{}", TmsResource::delegation.default_order()),
+        Some(r) => format!(" ORDER BY {}", r.default_order()),
+        None => String::new(),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// make_limit_clause:
+// ---------------------------------------------------------------------------
+/** Return the LIMIT clause chosen on the command line or the empty string. */
+fn make_limit_clause() -> String {
+    match TMSADM_ARGS.limit {
+        Some(n) => format!(" LIMIT {}", n),
+        None => String::new(),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// make_sqlite3_raw_cmd:
+// ---------------------------------------------------------------------------
+/** Create the sqlite3 command object for a complete SQL string that will be
+ * passed to sqlite3 as is.
+ */
+fn make_sqlite3_raw_cmd(sql: &str) -> Command {
+    // Build the command with user selected options.
+    // Only the json format echoes SQL, since an echoed statement would corrupt 
+    // the other formats' line-oriented streams, and output files never 
+    // contain echoed SQL.
+    let continued = CONTINUED_PAGE.load(Ordering::Relaxed);
+    let echo = TMSADM_ARGS.echo && TMSADM_ARGS.format == TmsFormat::json 
+        && TMSADM_ARGS.output.is_none() && TMSADM_ARGS.output_dir.is_none() && !continued;
+    let mut cmd = new_sqlite3_cmd();
+    if TMSADM_ARGS.format == TmsFormat::sql_insert {
+        // Sqlite3 writes the statements, including column names with headers on.
+        let table = match output_table() {
+            Some(t) => t,
+            None => panic!("The sql-insert format requires the --resource option."),
+        };
+        cmd.arg("-cmd");
+        cmd.arg(format!(".mode insert {}", table));
+        cmd.arg("-header");
+    } else if TMSADM_ARGS.format == TmsFormat::tsv {
+        // Sqlite3's tabs mode separates fields with tabs and never quotes them.
+        cmd.arg("-tabs");
+        if !&TMSADM_ARGS.header_off && !continued {cmd.arg("-header");}
+        if let Some(token) = &TMSADM_ARGS.null_token {
+            cmd.arg("-nullvalue");
+            cmd.arg(token);
+        }
+    } else {
+        // Derived formats are produced from sqlite3's json output, as is
+        // truncated non-json output.
+        let derived = TMSADM_ARGS.format.is_derived();
+        let truncated = TMSADM_ARGS.json_off && TMSADM_ARGS.max_width.is_some();
+        if !&TMSADM_ARGS.json_off || derived || truncated {cmd.arg("-json");}
+        if !&TMSADM_ARGS.header_off && !continued {cmd.arg("-header");}
+        if let Some(sep) = &TMSADM_ARGS.separator {
+            cmd.arg("-separator");
+            cmd.arg(sep);
+        }
+        if let Some(token) = &TMSADM_ARGS.null_token {
+            cmd.arg("-nullvalue");
+            cmd.arg(token);
+        }
+    }
+    if echo {cmd.arg("-echo");}
+    cmd.arg(db_path());
+    cmd.arg(sql);
+    cmd
+}
+
+// ---------------------------------------------------------------------------
+// new_sqlite3_cmd:
+// ---------------------------------------------------------------------------
+/** Create an sqlite3 command object with the options every invocation uses.
+ * The -bail option guarantees that a multi-statement string stops at the
+ * first error.  Encrypted databases are opened with SQLCipher, keyed before
+ * any other statement runs.  Callers add their output options, then the 
+ * database path.
+ */
+fn new_sqlite3_cmd() -> Command {
+    let mut cmd = Command::new(sqlite3_program());
+    if let Some(key) = &TMSADM_ARGS.key {
+        // The key would be visible to other users on the command line.
+        cmd.arg("-init");
+        cmd.arg(key_init_file(key));
+    }
+    cmd.arg("-bail");
+    if TMSADM_ARGS.readonly || TMSADM_ARGS.jobs.is_some() {cmd.arg("-readonly");}
+    if let Some(ms) = TMSADM_ARGS.busy_timeout {
+        cmd.arg("-cmd");
+        cmd.arg(format!(".timeout {}", ms));
+    }
+    for (alias, path) in &TMSADM_ARGS.attach {
+        cmd.arg("-cmd");
+        cmd.arg(format!("ATTACH DATABASE {} AS {}", sql_quote(path), alias));
+    }
+    cmd
+}
+
+// ---------------------------------------------------------------------------
+// key_init_file:
+// ---------------------------------------------------------------------------
+/** Return the file that sqlcipher runs with -init to set the --key before
+ * anything else, writing it the first time.  Only the user can read the 
+ * file, and it's removed when tmsadm exits.
+ * 
+ * This function panics if the file can't be written.
+ */
+fn key_init_file(key: &DbKey) -> PathBuf {
+    let mut file = KEY_FILE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(path) = file.as_ref() {return path.clone();}
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_nanos());
+    let path = std::env::temp_dir().join(format!("tmsadm-key-{}-{}.sql", std::process::id(), nanos));
+    let written = fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(&path)
+        .and_then(|mut f| writeln!(f, "PRAGMA key = {};", sql_quote(&key.0)));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&path);
+        panic!("Unable to write the database key file {}: {}", path.display(), e);
+    }
+    *file = Some(path.clone());
+    path
+}
+
+// ---------------------------------------------------------------------------
+// remove_key_file:
+// ---------------------------------------------------------------------------
+/** Remove the file that passes the --key to sqlcipher, if it was written. */
+fn remove_key_file() {
+    if let Some(path) = KEY_FILE.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        let _ = fs::remove_file(path);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// read_sqlite_version:
+// ---------------------------------------------------------------------------
+/** Return the version number that the sqlite3 program reports, such as 
+ * 3.45.1.
+ */
+fn read_sqlite_version(program: &str) -> String {
+    match Command::new(program).arg("-version").output() {
+        Ok(out) => String::from_utf8_lossy(&out.stdout).split_whitespace().next().unwrap_or_default().to_string(),
+        Err(e) => panic!("Unable to run {}: {}", program, e),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// check_sqlite_version:
+// ---------------------------------------------------------------------------
+/** Panic if the sqlite3 program is older than tmsadm needs, or than the 
+ * --min-sqlite-version, rather than letting its output or syntax errors be
+ * misread later.
+ */
+fn check_sqlite_version() {
+    let found = match parse_version(&SQLITE_VERSION) {
+        Ok(v) => v,
+        Err(_) => panic!("Unable to read the version of {}: {}", sqlite3_program(), *SQLITE_VERSION),
+    };
+    let mut required = vec![(MIN_SQLITE_VERSION, "tmsadm")];
+    if let Some(v) = TMSADM_ARGS.min_sqlite_version {required.push((v, "--min-sqlite-version"));}
+    for ((major, minor, patch), by) in required {
+        if found < (major, minor, patch) {
+            panic!("{} {} is too old, {} requires version {}.{}.{} or later.", 
+                   sqlite3_program(), *SQLITE_VERSION, by, major, minor, patch);
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// sqlite3_program:
+// ---------------------------------------------------------------------------
+/** Return the name of the program that new_sqlite3_cmd runs. */
+fn sqlite3_program() -> &'static str {
+    if TMSADM_ARGS.key.is_some() {SQLCIPHER} else {SQLITE3}
+}
+
+// ---------------------------------------------------------------------------
+// query_count:
+// ---------------------------------------------------------------------------
+/** Return the number of records the completed SELECT prototype would return.
+ * The count is computed by sqlite3 and captured rather than printed.
+ */
+fn query_count(list_sql: &str) -> u64 {
+    let sql = format!("SELECT COUNT(*) FROM ({})", make_sql(list_sql));
+    let out = run_capture(&sql, "COUNT records");
+    match out.trim().parse::<u64>() {
+        Ok(n) => n,
+        Err(_) => panic!("COUNT records: unexpected count returned by {}: {}", sqlite3_program(), out.trim()),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// make_where_clause:
+// ---------------------------------------------------------------------------
+/** Return the WHERE clause selected on the command line or the empty string.
+ * The --ids list, the --user, --host and --tenant shortcut filters, and the
+ * created date range are each turned into a condition and combined with AND,
+ * along with a --sqlwhere clause for each one given.  The ids have already
+ * been validated as integers by the argument parser, the dates have been 
+ * validated as ISO-8601 and the shortcut values are quoted, so they are safe
+ * to interpolate.  A single --sqlwhere clause by itself is used exactly as
+ * written.
+ */
+fn make_where_clause() -> String {
+    let mut conds = Vec::new();
+    if !TMSADM_ARGS.ids.is_empty() {
+        let ids: Vec<String> = TMSADM_ARGS.ids.iter().map(|id| id.to_string()).collect();
+        conds.push(format!("id IN ({})", ids.join(",")));
+    }
+    if let Some(id) = TMSADM_ARGS.since_id {
+        conds.push(format!("id > {}", id));
+    }
+    let shortcuts = [(USER_COLUMN, &TMSADM_ARGS.user), 
+                     (HOST_COLUMN, &TMSADM_ARGS.host), 
+                     (TENANT_COLUMN, &TMSADM_ARGS.tenant)];
+    for (col, value) in shortcuts {
+        if let Some(v) = value {
+            if TMSADM_ARGS.ci {
+                conds.push(format!("lower({}) = lower({})", col, sql_quote(v)));
+            } else {
+                conds.push(format!("{} = {}", col, sql_quote(v)));
+            }
+        }
+    }
+    if let Some(id) = &TMSADM_ARGS.client_id {
+        conds.push(format!("{} = {}", CLIENT_ID_COLUMN, sql_quote(id)));
+    }
+
+    if let Some(text) = &TMSADM_ARGS.search {
+        conds.push(make_search_condition(text));
+    }
+    if let Some(cond) = make_deleted_condition() {
+        conds.push(cond);
+    }
+    if TMSADM_ARGS.expire_cleanup {
+        conds.push(EXPIRED_CONDITION.to_string());
+    }
+
+    // Dates are compared as julian days so that differences in timestamp 
+    // formatting don't matter.  An until date without a time includes the 
+    // whole day.
+    if let Some(since) = &TMSADM_ARGS.created_since {
+        conds.push(format!("julianday({}) >= julianday({})", CREATED_COLUMN, sql_quote(since)));
+    }
+    if let Some(until) = &TMSADM_ARGS.created_until {
+        if until.len() == 10 {
+            conds.push(format!("julianday({}) < julianday({}, '+1 day')", CREATED_COLUMN, sql_quote(until)));
+        } else {
+            conds.push(format!("julianday({}) <= julianday({})", CREATED_COLUMN, sql_quote(until)));
+        }
+    }
+
+    // Add the user's clauses.  Only the first needs to start with WHERE.
+    let clauses = sqlwhere_clauses();
+    for (i, wh) in clauses.iter().enumerate() {
+        check_sqlwhere(wh);
+        if conds.is_empty() && clauses.len() == 1 {return wh.clone();}
+        let cond = if i > 0 && !has_where(wh) {wh.trim()} else {strip_where(wh)};
+        conds.push(format!("({})", cond));
+    }
+
+    if conds.is_empty() {String::new()} 
+        else {format!("WHERE {}", conds.join(" AND "))}
+}
+
+// ---------------------------------------------------------------------------
+// make_deleted_condition:
+// ---------------------------------------------------------------------------
+/** Return the condition that leaves out the records marked deleted by 
+ * --soft-delete, or with --only-deleted selects just them, if the resource's
+ * table has a deleted_at column.  Orphaned delegations are selected by a 
+ * join, so they aren't filtered.
+ */
+fn make_deleted_condition() -> Option<String> {
+    if TMSADM_ARGS.include_deleted || TMSADM_ARGS.orphans {return None;}
+    let table = current_resource()?.table();
+    if !has_deleted_column(table) {
+        if TMSADM_ARGS.only_deleted {
+            panic!("The --only-deleted option requires a {} column, which the {} table doesn't have.", 
+                   DELETED_COLUMN, table);
+        }
+        return None;
+    }
+    Some(format!("{} IS {}NULL", DELETED_COLUMN, if TMSADM_ARGS.only_deleted {"NOT "} else {""}))
+}
+
+// ---------------------------------------------------------------------------
+// has_deleted_column:
+// ---------------------------------------------------------------------------
+/** Return true if the table has the column that --soft-delete sets.  The 
+ * answer is remembered, since every WHERE clause for the table needs it.
+ */
+fn has_deleted_column(table: &str) -> bool {
+    let mut known = DELETED_COLUMN_TABLES.lock().unwrap();
+    if let Some((_, has)) = known.iter().find(|(t, _)| t == table) {return *has;}
+    let sql = format!("SELECT COUNT(*) FROM pragma_table_info({}) WHERE name = {}", 
+                      sql_quote(table), sql_quote(DELETED_COLUMN));
+    let has = run_capture(&sql, "FIND deleted_at column").trim() != "0";
+    known.push((table.to_string(), has));
+    has
+}
+
+// ---------------------------------------------------------------------------
+// column_types:
+// ---------------------------------------------------------------------------
+/** Return the name and declared type of each of the table's columns. */
+fn column_types(table: &str) -> Vec<(String, String)> {
+    let sql = format!("SELECT name, type FROM pragma_table_info({})", sql_quote(table));
+    let mut columns = Vec::new();
+    query_rows(&sql, "FIND column types", |row| {
+        let field = |name: &str| row.get(name).and_then(Value::as_str).unwrap_or_default().to_string();
+        columns.push((field("name"), field("type")));
+    });
+    columns
+}
+
+// ---------------------------------------------------------------------------
+// required_columns:
+// ---------------------------------------------------------------------------
+/** Return the names of the table's columns that an insert must give values,
+ * which are the NOT NULL columns that have no default and aren't the 
+ * primary key.
+ */
+fn required_columns(table: &str) -> Vec<String> {
+    let sql = format!("SELECT name FROM pragma_table_info({}) WHERE \"notnull\" AND dflt_value IS NULL AND NOT pk", 
+                      sql_quote(table));
+    let mut columns = Vec::new();
+    query_rows(&sql, "FIND required columns", |row| {
+        columns.push(row.get("name").and_then(Value::as_str).unwrap_or_default().to_string());
+    });
+    columns
+}
+
+// ---------------------------------------------------------------------------
+// make_search_condition:
+// ---------------------------------------------------------------------------
+/** Return a condition that matches records of the resource's table in which
+ * any text column contains the text.  The text columns are those with text
+ * affinity according to PRAGMA table_info, and LIKE's wildcards in the text 
+ * are escaped so that it matches literally (ignoring ASCII case).
+ */
+fn make_search_condition(text: &str) -> String {
+    let table = match current_resource() {
+        Some(r) => r.table(),
+        None => panic!("The --search option requires the --resource option."),
+    };
+    let sql = format!("SELECT name FROM pragma_table_info({}) WHERE upper(type) LIKE '%CHAR%' \
+                       OR upper(type) LIKE '%CLOB%' OR upper(type) LIKE '%TEXT%'", sql_quote(table));
+    let columns = run_capture(&sql, "FIND text columns");
+    let mut pattern = String::from("%");
+    for c in text.chars() {
+        if matches!(c, '\\' | '%' | '_') {pattern.push('\\');}
+        pattern.push(c);
+    }
+    pattern.push('%');
+    let pattern = sql_quote(&pattern);
+    let likes: Vec<String> = columns.lines().map(str::trim).filter(|c| is_identifier(c))
+        .map(|c| format!("{} LIKE {} ESCAPE '\\'", c, pattern)).collect();
+    if likes.is_empty() {
+        panic!("The {} table has no text columns to search.", table);
+    }
+    format!("({})", likes.join(" OR "))
+}
+
+// ---------------------------------------------------------------------------
+// check_sqlwhere:
+// ---------------------------------------------------------------------------
+/** Reject a --sqlwhere clause that could end the statement early or that 
+ * leaves a quoted string or identifier open.  Semicolons are only allowed 
+ * inside quotes, and a doubled quote inside quotes is an escaped quote.
+ */
+fn check_sqlwhere(clause: &str) {
+    for (_, token) in sql_tokens(clause) {
+        match token {
+            SqlToken::Symbol(s) if s == ";" => 
+                panic!("The --sqlwhere clause cannot contain a semicolon outside of quotes: {}", clause),
+            SqlToken::Literal {closed: false, ..} | SqlToken::Quoted {closed: false, ..} => 
+                panic!("The --sqlwhere clause has unbalanced quotes: {}", clause),
+            _ => {},
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// sql_tokens:
+// ---------------------------------------------------------------------------
+/** Split a WHERE clause into tokens, each with the char offset where it 
+ * starts.  Whitespace separates tokens and is dropped.  Inside a string 
+ * literal or quoted identifier, a doubled quote is an escaped quote.  Numbers
+ * include forms like 1e5 and 0x1F, and a symbol is a two character operator 
+ * or comment start, or else any other single character.
+ */
+fn sql_tokens(clause: &str) -> Vec<(usize, SqlToken)> {
+    const PAIRS: &[&str] = &["<=", "<>", ">=", "!=", "==", "--", "/*"];
+    let chars: Vec<char> = clause.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let token = if c == '\'' || c == '"' {
+            let mut text = String::new();
+            let mut closed = false;
+            i += 1;
+            while i < chars.len() {
+                if chars[i] == c && chars.get(i + 1) != Some(&c) {
+                    closed = true;
+                    i += 1;
+                    break;
+                }
+                if chars[i] == c {i += 1;}
+                text.push(chars[i]);
+                i += 1;
+            }
+            if c == '\'' {SqlToken::Literal {value: text, closed}} else {SqlToken::Quoted {name: text, closed}}
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {i += 1;}
+            SqlToken::Word(chars[start..i].iter().collect())
+        } else if c.is_ascii_digit() {
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {i += 1;}
+            SqlToken::Number(chars[start..i].iter().collect())
+        } else {
+            let pair: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+            let symbol = if PAIRS.contains(&pair.as_str()) {pair} else {c.to_string()};
+            i += symbol.chars().count();
+            SqlToken::Symbol(symbol)
+        };
+        tokens.push((start, token));
+    }
+    tokens
+}
+
+// ---------------------------------------------------------------------------
+// sqlwhere_clauses:
+// ---------------------------------------------------------------------------
+/** Return the --sqlwhere clauses followed by the --edit-where clause, if one
+ * has been written.
+ */
+fn sqlwhere_clauses() -> Vec<String> {
+    let mut clauses = TMSADM_ARGS.sqlwhere.clone();
+    if let Some(clause) = EDITED_WHERE.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        clauses.push(clause.clone());
+    }
+    clauses
+}
+
+// ---------------------------------------------------------------------------
+// edit_where:
+// ---------------------------------------------------------------------------
+/** Open $VISUAL or $EDITOR, or else vi, on a template that lists the columns
+ * of the resource's table as comments, and use what's saved, without the 
+ * comment lines, as another --sqlwhere clause.  The clause must start with
+ * WHERE and is validated like any other.  Saving an empty clause cancels 
+ * the operation, as an empty message does for git commit.
+ */
+fn edit_where(resource: &TmsResource) {
+    let table = resource.table();
+    let mut template = format!("\n-- Write a WHERE clause for the {} table above. Lines starting \
+                                with -- are ignored,\n-- and an empty clause cancels.\n--\n\
+                                -- Columns:\n", table);
+    for (name, kind) in column_types(table) {template += &format!("--   {} ({})\n", name, kind);}
+    template = format!("WHERE {}", template);
+    let path = std::env::temp_dir().join(format!("tmsadm-where-{}.sql", std::process::id()));
+    if let Err(e) = fs::write(&path, &template) {
+        panic!("Unable to write WHERE clause template {}: {}", path.display(), e);
+    }
+
+    // The editor setting can include arguments, as in "code --wait".
+    let editor = std::env::var("VISUAL").or_else(|_| std::env::var("EDITOR"))
+        .ok().filter(|e| !e.trim().is_empty()).unwrap_or_else(|| "vi".to_string());
+    let status = Command::new("sh").arg("-c").arg(format!("{} \"$1\"", editor)).arg("sh").arg(&path).status();
+    let text = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+    match status {
+        Ok(s) if s.success() => (),
+        Ok(s) => panic!("The editor {} failed ({}), nothing done.", editor, s),
+        Err(e) => panic!("Unable to run the editor {}: {}", editor, e),
+    }
+    let text = match text {
+        Ok(t) => t,
+        Err(e) => panic!("Unable to read the edited WHERE clause {}: {}", path.display(), e),
+    };
+    let clause = text.lines().filter(|l| !l.trim_start().starts_with("--"))
+        .collect::<Vec<_>>().join(" ").split_whitespace().collect::<Vec<_>>().join(" ");
+    if clause.is_empty() || clause.eq_ignore_ascii_case("WHERE") {
+        println!("The WHERE clause is empty, nothing done.");
+        exit(1);
+    }
+    strip_where(&clause);
+    check_sqlwhere(&clause);
+    println!("Using --sqlwhere {}", shlex::try_quote(&clause).unwrap_or_default());
+    *EDITED_WHERE.lock().unwrap_or_else(|e| e.into_inner()) = Some(clause);
+}
+
+// ---------------------------------------------------------------------------
+// check_strict_sqlwhere:
+// ---------------------------------------------------------------------------
+/** With --strict-sql, check each --sqlwhere clause against the columns of 
+ * each resource's table.  The clauses are validated first.
+ */
+fn check_strict_sqlwhere() {
+    let clauses = sqlwhere_clauses();
+    if clauses.is_empty() {return;}
+    if TMSADM_ARGS.resource.is_empty() {
+        panic!("The --strict-sql option requires the --resource option.");
+    }
+    for resource in &TMSADM_ARGS.resource {
+        let columns: Vec<String> = column_types(resource.table()).into_iter().map(|(name, _)| name).collect();
+        for clause in &clauses {
+            check_sqlwhere(clause);
+            check_strict_sql(clause, &columns);
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// check_strict_sql:
+// ---------------------------------------------------------------------------
+/** Panic, naming the offending token, unless the clause only contains the 
+ * table's columns, string and numeric literals, comparison operators, 
+ * parentheses, commas and the keywords of simple conditions.  This rules out
+ * subqueries, function calls, qualified names, comments and statements such
+ * as ATTACH and PRAGMA, whatever the rest of the clause is.
+ */
+fn check_strict_sql(clause: &str, columns: &[String]) {
+    const ALLOWED: &[&str] = &["where", "and", "or", "not", "in", "like", "is", "null", "between", "escape"];
+    const OPERATORS: &[&str] = &["=", "==", "<", ">", "<=", ">=", "<>", "!=", "(", ")", ","];
+    let reject = |token: &str| -> ! {
+        panic!("The --strict-sql option doesn't allow {} in the --sqlwhere clause: {}", token, clause)
+    };
+    let is_column = |name: &str| columns.iter().any(|c| c.eq_ignore_ascii_case(name));
+    let tokens = sql_tokens(clause);
+    for (i, (start, token)) in tokens.iter().enumerate() {
+        let next = tokens.get(i + 1);
+        match token {
+            SqlToken::Literal {..} | SqlToken::Number(_) => {},
+            SqlToken::Quoted {name, ..} => if !is_column(name) {reject(&format!("\"{}\"", name))},
+            SqlToken::Word(word) => {
+                let lower = word.to_ascii_lowercase();
+                let call = matches!(next, Some((_, SqlToken::Symbol(s))) if s == "(") && lower != "in";
+                if call || !(ALLOWED.contains(&lower.as_str()) || is_column(word)) {reject(word);}
+            },
+            SqlToken::Symbol(s) if OPERATORS.contains(&s.as_str()) => {},
+            // A minus sign is only allowed as part of a negative number.
+            SqlToken::Symbol(s) if s == "-" && matches!(next, Some((n, SqlToken::Number(_))) if *n == start + 1) => {},
+            SqlToken::Symbol(s) => reject(s),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// make_where_clause_and:
+// ---------------------------------------------------------------------------
+/** Return the WHERE clause selected on the command line with the condition
+ * added using AND.  The selected conditions are parenthesized so that an OR
+ * in a --sqlwhere clause doesn't change the meaning.
+ */
+fn make_where_clause_and(condition: &str) -> String {
+    match make_where_clause() {
+        w if w.is_empty() => format!("WHERE {}", condition),
+        w => format!("WHERE ({}) AND {}", strip_where(&w), condition),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// strip_where:
+// ---------------------------------------------------------------------------
+/** Return the conditions in a --sqlwhere clause without the leading WHERE
+ * keyword, which is required.
+ */
+fn strip_where(clause: &str) -> &str {
+    if !has_where(clause) {
+        panic!("The --sqlwhere clause must start with WHERE: {}", clause);
+    }
+    clause.trim_start()[5..].trim()
+}
+
+// ---------------------------------------------------------------------------
+// has_where:
+// ---------------------------------------------------------------------------
+/** Return whether the clause starts with the WHERE keyword. */
+fn has_where(clause: &str) -> bool {
+    clause.trim_start().get(..5).is_some_and(|kw| kw.eq_ignore_ascii_case("WHERE"))
+}
+
+// ***************************************************************************
+//                               Config Structs
+// ***************************************************************************
+// ---------------------------------------------------------------------------
+// init_tms_args:
+// ---------------------------------------------------------------------------
+/** Get the command line arguments.  Options that a selected --profile sets
+ * are taken from the profile unless they're given on the command line, and
+ * --ids-stdin reads the ids before anything else uses stdin.  An --env-file
+ * is loaded first so that options read from the environment see its values.
+ */
+fn init_tmsadm_args() -> TmsadmArgs {
+    // Tests share fixed arguments rather than the test harness's own.
+    #[cfg(not(test))]
+    let argv: Vec<OsString> = std::env::args_os().collect();
+    #[cfg(test)]
+    let argv: Vec<OsString> = tests::TEST_ARGS.iter().map(OsString::from).collect();
+    load_env_file(&argv);
+    let aliases = load_resource_aliases(&argv);
+    let argv = resolve_resource_aliases(translate_subcommand(argv, &aliases), &aliases);
+    let mut app = TmsadmArgs::clap();
+    if argv.iter().any(|a| a == "--version" || a == "-V") {
+        // The arguments aren't parsed yet, so an encrypted database's program isn't known.
+        let version = format!("{} (sqlite3 {})", env!("CARGO_PKG_VERSION"), read_sqlite_version(SQLITE3));
+        app = app.version(&*Box::leak(version.into_boxed_str()));
+    }
+    let matches = match app.get_matches_from_safe(argv) {
+        Ok(m) => m,
+        Err(e) if errors_json_requested() && e.use_stderr() => {
+            let message = e.message.lines().next().unwrap_or_default();
+            write_error_json(1, "usage", message.trim_start_matches("error: "));
+            std::process::exit(1);
+        },
+        Err(e) => e.exit(),
+    };
+    let mut args = TmsadmArgs::from_clap(&matches);
+    if args.ids_stdin {args.ids = read_stdin_ids();}
+    if args.limit == Some(0) {
+        eprintln!("Note: --limit 0 is deprecated; omit --limit or use --no-limit to return all records.");
+        args.limit = None;
+    }
+    if args.check_config {return args;}
+    if let Some(name) = &args.profile {
+        let path = get_absolute_path(&args.config);
+        let config = TmsadmConfig::load(&path);
+        let profile = match config.profile.get(name) {
+            Some(p) => p,
+            None => panic!("Profile {} is not defined in configuration file {}.", name, path),
+        };
+        if let Some(dbpath) = &profile.dbpath {
+            if matches.occurrences_of("dbpath") == 0 {args.dbpath = dbpath.clone();}
+        }
+        if let Some(format) = &profile.format {
+            if matches.occurrences_of("format") == 0 {
+                args.format = match format.parse() {
+                    Ok(f) => f,
+                    Err(_) => panic!("Invalid format for profile {} in configuration file {}: {}", 
+                                     name, path, format),
+                };
+            }
+        }
+        if let Some(limit) = profile.limit {
+            if limit < 0 {
+                panic!("Invalid limit for profile {} in configuration file {}: {}", name, path, limit);
+            }
+            // A profile's limit is for listing and never applies to a delete.
+            let deleting = args.operation == Some(TmsOperation::DELETE);
+            if matches.occurrences_of("limit") == 0 && !args.no_limit && !deleting {
+                args.limit = Some(limit).filter(|n| *n > 0);
+            }
+        }
+        if let Some(max) = profile.max_rows {
+            if matches.occurrences_of("max-rows") == 0 {args.max_rows = Some(max);}
+        }
+    }
+    if args.first || args.last {args.limit = Some(1);}
+    if args.template.is_some() {
+        if matches.occurrences_of("format") > 0 && args.format != TmsFormat::template {
+            panic!("The --template option selects the template format, so it can't be combined with --format {:?}.", 
+                   args.format);
+        }
+        args.format = TmsFormat::template;
+    }
+    if args.expand_where {
+        for clause in &mut args.sqlwhere {
+            *clause = match shellexpand::env(clause) {
+                Ok(x) => x.into_owned(),
+                Err(e) => panic!("Undefined variable {} in --sqlwhere {}: {}", e.var_name, clause, e.cause),
+            };
+        }
+    }
+    args
+}
+
+// ---------------------------------------------------------------------------
+// load_env_file:
+// ---------------------------------------------------------------------------
+/** Load the KEY=VALUE pairs of the --env-file on the command line, if any,
+ * into the environment.  This happens before the arguments are parsed, so 
+ * the file is found by scanning them.  Variables that are already set keep
+ * their values.
+ */
+fn load_env_file(argv: &[OsString]) {
+    let mut path = None;
+    for (i, arg) in argv.iter().enumerate() {
+        match arg.to_str() {
+            Some("--env-file") => path = argv.get(i + 1).and_then(|p| p.to_str()),
+            Some(a) => if let Some(p) = a.strip_prefix("--env-file=") {path = Some(p)},
+            None => (),
+        }
+    }
+    let path = match path {
+        Some(p) => get_absolute_path(p),
+        None => return,
+    };
+    match dotenvy::from_path(&path) {
+        Ok(_) => (),
+        Err(dotenvy::Error::LineParse(line, _)) => 
+            panic!("Malformed line in env file {}: {}", path, line),
+        Err(e) => panic!("Unable to load env file {}: {}", path, e),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// load_resource_aliases:
+// ---------------------------------------------------------------------------
+/** Return the resource aliases defined in the configuration file, keyed by 
+ * lowercase alias, for resolving before the arguments are parsed.  The file
+ * is found by scanning the arguments for --config.  A missing or invalid 
+ * file defines no aliases, and aliases that are resource names or that name
+ * unknown resources are ignored, since the file is only required with 
+ * --profile and --check-config reports its problems.
+ */
+fn load_resource_aliases(argv: &[OsString]) -> BTreeMap<String, String> {
+    let mut path = "~/.tms/tmsadm.toml";
+    for (i, arg) in argv.iter().enumerate() {
+        match arg.to_str() {
+            Some("--config") => if let Some(p) = argv.get(i + 1).and_then(|p| p.to_str()) {path = p},
+            Some(a) => if let Some(p) = a.strip_prefix("--config=") {path = p},
+            None => (),
+        }
+    }
+    let path = get_absolute_path(path);
+    let table = match fs::read_to_string(&path).map(|t| t.parse::<toml::Table>()) {
+        Ok(Ok(t)) => t,
+        _ => return BTreeMap::new(),
+    };
+    let mut aliases = BTreeMap::new();
+    if let Some(t) = table.get("resource_aliases").and_then(toml::Value::as_table) {
+        for (alias, resource) in t {
+            if TmsResource::from_str(alias).is_ok() {continue;}
+            if let Some(r) = resource.as_str().filter(|r| TmsResource::from_str(r).is_ok()) {
+                aliases.insert(alias.to_ascii_lowercase(), r.to_ascii_lowercase());
+            }
+        }
+    }
+    aliases
+}
+
+// ---------------------------------------------------------------------------
+// resolve_resource_aliases:
+// ---------------------------------------------------------------------------
+/** Replace any resource aliases in the --resource values with the resources
+ * they stand for.  When aliases are defined, a value that's neither a 
+ * resource nor an alias is rejected with a list of both, since the parser 
+ * only knows the resources.
+ */
+fn resolve_resource_aliases(mut args: Vec<OsString>, aliases: &BTreeMap<String, String>) -> Vec<OsString> {
+    if aliases.is_empty() {return args;}
+    let resolve = |value: &str| -> String {
+        value.split(',').map(|v| {
+            let v = v.trim().to_ascii_lowercase();
+            if let Some(r) = aliases.get(&v) {return r.clone();}
+            if TmsResource::from_str(&v).is_err() {
+                let mut names: Vec<&str> = vec!["pubkey", "client", "delegation"];
+                names.extend(aliases.keys().map(String::as_str));
+                panic!("Unknown resource {}, expected one of: {}", v, names.join(", "));
+            }
+            v
+        }).collect::<Vec<_>>().join(",")
+    };
+    let mut i = 1;
+    while i < args.len() {
+        let arg = args[i].to_string_lossy().into_owned();
+        if (arg == "--resource" || arg == "-r") && i + 1 < args.len() {
+            args[i + 1] = resolve(&args[i + 1].to_string_lossy()).into();
+            i += 1;
+        } else if let Some(v) = arg.strip_prefix("--resource=") {
+            args[i] = format!("--resource={}", resolve(v)).into();
+        }
+        i += 1;
+    }
+    args
+}
+
+// ---------------------------------------------------------------------------
+// translate_subcommand:
+// ---------------------------------------------------------------------------
+/** Translate the subcommand form of the command line, such as
+ * 
+ *   tmsadm list pubkey --host myhost
+ * 
+ * into the equivalent flag form, --operation LIST --resource pubkey --host
+ * myhost, which is then parsed as usual.  The subcommand is an operation name
+ * in any case, or count for LIST --count-only, and the resource, which can 
+ * be plural or a resource alias, is optional.  Command lines that don't start
+ * with a subcommand are returned unchanged.
+ */
+fn translate_subcommand(mut args: Vec<OsString>, aliases: &BTreeMap<String, String>) -> Vec<OsString> {
+    let word = |i: usize| args.get(i).and_then(|a| a.to_str()).map(str::to_ascii_lowercase);
+    let operation = match word(1).as_deref() {
+        Some("count") => "count",
+        Some(w) => match TmsOperation::from_str(w) {
+            Ok(op) => op.name(),
+            Err(_) => return args,
+        },
+        None => return args,
+    };
+    let resource = word(2).and_then(|w| {
+        if let Some(r) = aliases.get(&w) {return Some(r.clone());}
+        let w = w.strip_suffix('s').unwrap_or(&w).to_string();
+        if let Some(r) = aliases.get(&w) {return Some(r.clone());}
+        TmsResource::from_str(&w).ok().map(|_| w)
+    });
+
+    // Replace the subcommand words with the options they stand for.
+    let mut flags: Vec<OsString> = match operation {
+        "count" => vec!["--operation".into(), "LIST".into(), "--count-only".into()],
+        op => vec!["--operation".into(), op.into()],
+    };
+    let used = if let Some(r) = resource {
+        flags.extend(["--resource".into(), r.into()]);
+        3
+    } else {2};
+    args.splice(1..used, flags);
+    args
+}
+
+// ---------------------------------------------------------------------------
+// TmsadmArgs:
+// ---------------------------------------------------------------------------
+#[derive(Debug, StructOpt)]
+#[structopt(name = "tmsadm", about = "Command line arguments for tmsadm program.", before_help = TMSADM_INFO)]
+pub struct TmsadmArgs {
+    /// Specify the operation to carry out. Not used by informational modes such as
+    /// --schema-version, --show-pragmas, --show-config, --print-path, --check-config
+    /// and --verify-export, or by --set-pragma, --batch and --undo.
+    /// 
+    #[structopt(short, long, required_unless_one=&["schema-version","show-pragmas","set-pragma","show-config","print-path","check-config","verify-export","batch","undo","dump-schema","build-where","expect-counts","healthcheck","vacuum-estimate"], possible_values=&["LIST","DELETE","INIT","IMPORT","VALIDATE","EXPORT","REPORT","UPDATE","ROTATE","DUMP","DUMP-ALL","OPTIMIZE"])]
+    pub operation: Option<TmsOperation>,
+
+    /// Specify the resource type to which the operation will be applied. Required 
+    /// for LIST and DELETE unless a mode that spans tables, such as --purge-user, 
+    /// is selected. LIST and DELETE also accept a comma separated list, such as 
+    /// pubkey,client,delegation, and run on each resource in turn. Each listing is
+    /// preceded by a header line naming its table, and each delete is confirmed 
+    /// separately. Aliases defined in the configuration file's resource_aliases
+    /// table are also accepted.
+    /// 
+    #[structopt(short, long, use_delimiter = true, possible_values=&["pubkey","client","delegation"])]
+    pub resource: Vec<TmsResource>,
+
+    /// Path to TMS database file. An sqlite URI filename, such as 
+    /// file:/opt/tms/tms.db?mode=ro, is passed to sqlite3 as is, and the file it 
+    /// names is used for the existence check, locking and backups.
+    /// 
+    #[structopt(short, long, default_value = DEFAULT_DBPATH)]
+    pub dbpath: String,
+
+    /// Select a profile defined in the configuration file, which can set the dbpath,
+    /// format and limit. Options given on the command line override the profile.
+    /// 
+    #[structopt(long)]
+    pub profile: Option<String>,
+
+    /// Run the command once with each profile defined in the configuration file,
+    /// in name order, showing each profile's name before its output. Commands that
+    /// can change a database are refused unless --i-understand is also given.
+    /// 
+    #[structopt(long, conflicts_with_all=&["profile","dbpath","batch"])]
+    pub all_profiles: bool,
+
+    /// Allow --all-profiles to run a command that can change every profile's 
+    /// database.
+    /// 
+    #[structopt(long, requires = "all-profiles")]
+    pub i_understand: bool,
+
+    /// Path to the tmsadm configuration file, which defines the profiles.
+    /// 
+    #[structopt(long, default_value="~/.tms/tmsadm.toml")]
+    pub config: String,
+
+    /// File of KEY=VALUE lines loaded into the environment before anything else,
+    /// so that variables in --dbpath and other paths, and options read from the
+    /// environment, can be defined there. Variables already set take precedence.
+    /// 
+    #[structopt(long)]
+    pub env_file: Option<String>,
+
+    /// Only expand ~ and environment variables in paths, without making them 
+    /// absolute, and pass them on as is. This is for chrooted or containerized
+    /// setups where the current directory that absolute paths are built from 
+    /// isn't the one that relative paths are meant to be resolved against.
+    /// 
+    #[structopt(long)]
+    pub no_absolutize: bool,
+
+    /// Set JSON formatting (default=false, implying json is on).
+    /// 
+    #[structopt(short, long)]
+    pub json_off: bool,
+
+    /// Output format for records. The ndjson format writes one JSON object per 
+    /// line with no surrounding brackets. The authorized_keys format writes the
+    /// pubkey resource as an OpenSSH authorized_keys file, one key per line with
+    /// a tms_user_id@host comment. The sql-insert format writes an INSERT statement
+    /// with column names for each record, which can be replayed into another 
+    /// database. The json-typed format writes an object whose columns array gives the
+    /// declared type of each column and whose rows array holds the records, and only
+    /// applies to the LIST and EXPORT operations. The yaml format writes a sequence 
+    /// with a mapping for each record. The tsv format writes a header line and then
+    /// one line per record with its values separated by tabs. Values are never 
+    /// quoted or escaped, so a value containing a tab or newline makes its line 
+    /// ambiguous, and --separator and --max-width don't apply. The template format 
+    /// writes a line per record from the --template, which selects it. The none format 
+    /// writes no records, only the number of rows returned or changed, to stderr,
+    /// for statements run for effect.
+    /// Formats other than json ignore --json-off and never echo SQL.
+    /// 
+    #[structopt(short, long, default_value="json", 
+                possible_values=&["json","ndjson","authorized_keys","sql-insert","json-typed","yaml","tsv","template",
+                                  "none"])]
+    pub format: TmsFormat,
+
+    /// Write each record shown by LIST and EXPORT as a line made from the template,
+    /// with each {column} placeholder replaced by the record's value, nulls by 
+    /// nothing, and {{ and }} by literal braces. This selects the template format. 
+    /// Placeholders must name the table's columns. For example:
+    /// 
+    ///   --template "{tms_user_id} on {host} since {created}"
+    /// 
+    #[structopt(long, parse(try_from_str = parse_template))]
+    pub template: Option<String>,
+
+    /// Echo the SQL command in the json output. SQL is not echoed by default.
+    /// 
+    #[structopt(long)]
+    pub echo: bool,
+
+    /// Deprecated and has no effect, since SQL is no longer echoed by default. Use
+    /// --echo to echo SQL.
+    /// 
+    #[structopt(short, long)]
+    pub echo_off: bool,
+
+    /// Retrieve SQL column headings when using non-JSON format (default=false, implying headers on).
+    /// 
+    #[structopt(short, long)]
+    pub header_off: bool,
+
+    /// Truncate each column value to at most this many characters, marking truncated 
+    /// values with an ellipsis. Only applies to non-JSON output (--json-off).
+    /// 
+    #[structopt(long)]
+    pub max_width: Option<usize>,
+
+    /// The column separator for non-JSON output (--json-off), which defaults to "|". 
+    /// The value must be a single character or one of the escapes \t (tab) or \\ 
+    /// (backslash).
+    /// 
+    #[structopt(long, requires="json-off", parse(try_from_str = parse_separator))]
+    pub separator: Option<String>,
+
+    /// The text shown for NULL values in non-JSON output (--json-off), such as NULL,
+    /// which distinguishes them from empty strings. NULL values are shown as empty 
+    /// strings by default. JSON output always uses null.
+    /// 
+    #[structopt(long, requires="json-off")]
+    pub null_token: Option<String>,
+
+    /// Add a fingerprint column holding the SHA256 fingerprint of each key when
+    /// listing the pubkey resource, as shown in sshd logs. The column is null for 
+    /// keys that can't be parsed. Only applies to the json and ndjson formats.
+    /// 
+    #[structopt(long, conflicts_with_all=&["json-off","max-width"])]
+    pub fingerprint: bool,
+
+    /// Path to another database to compare with the LIST operation. The records of
+    /// the resource whose ids are in only one of the databases are listed with a 
+    /// leading diff column, which is "added" for records only in --dbpath and 
+    /// "removed" for records only in the other database.
+    /// 
+    #[structopt(long, conflicts_with_all=&["orphans","fingerprint"])]
+    pub diff_against: Option<String>,
+
+    /// List only the distinct values of this column of the selected records with the
+    /// LIST operation, such as the hosts that have keys. Example:
+    /// 
+    ///   --distinct host
+    /// 
+    #[structopt(long, conflicts_with_all=&["orphans","fingerprint","diff-against"], 
+                parse(try_from_str = parse_column))]
+    pub distinct: Option<String>,
+
+    /// Print the number of distinct values of this column of the selected records 
+    /// with the LIST operation, such as the number of hosts that have delegations.
+    /// 
+    #[structopt(long, conflicts_with_all=&["orphans","fingerprint","diff-against","distinct","count-only"], 
+                parse(try_from_str = parse_column))]
+    pub count_distinct: Option<String>,
+
+    /// Leave this column out of the records shown by LIST and EXPORT, such as the
+    /// public_key column of pubkeys. Can be repeated. Example:
+    /// 
+    ///   --columns-exclude public_key
+    /// 
+    #[structopt(long, number_of_values = 1, conflicts_with_all=&["distinct","diff-against"], 
+                parse(try_from_str = parse_column))]
+    pub columns_exclude: Vec<String>,
+
+    /// Show timestamps in the records shown by LIST and EXPORT in the local timezone
+    /// as YYYY-MM-DD HH:MM:SS. The created and updated columns and columns whose 
+    /// names end in _at are converted unless --time-columns is given.
+    /// 
+    #[structopt(long, conflicts_with_all=&["distinct","diff-against"])]
+    pub localtime: bool,
+
+    /// Include each record's rowid, sqlite's internal key, as the first column of
+    /// the records shown by LIST and EXPORT.
+    /// 
+    #[structopt(long, conflicts_with_all=&["distinct","diff-against"])]
+    pub with_rowid: bool,
+
+    /// Column converted by --localtime, replacing the default columns. Can be 
+    /// repeated.
+    /// 
+    #[structopt(long, number_of_values = 1, requires = "localtime", parse(try_from_str = parse_column))]
+    pub time_columns: Vec<String>,
+
+    /// The timezone of the text timestamps stored in the database, used when they're
+    /// converted to the local timezone: utc, local for the timezone tmsadm runs in, or
+    /// an offset given as --db-timezone=-05:00. Timestamps are assumed to be UTC when
+    /// omitted. Text timestamps with their own offset and integer epoch seconds are 
+    /// always converted exactly.
+    /// 
+    #[structopt(long, parse(try_from_str = parse_db_timezone))]
+    pub db_timezone: Option<String>,
+
+    /// Print only the number of records the LIST operation would return, taking the
+    /// WHERE clause and limit into account.
+    /// 
+    #[structopt(long, conflicts_with_all=&["fingerprint","diff-against","distinct"])]
+    pub count_only: bool,
+
+    /// Print single-value results, those of --count-only, --count-distinct and
+    /// --schema-version, as just the value with no label and no trailing newline, 
+    /// as in n=$(tmsadm ...).
+    /// 
+    #[structopt(long)]
+    pub raw_scalar: bool,
+
+    /// Sort the keys of each record in the json and ndjson formats, so that output 
+    /// doesn't change with the order of the table's columns, which makes exports
+    /// easier to compare.
+    /// 
+    #[structopt(long)]
+    pub normalize_json: bool,
+
+    /// Write the json format's records as a single line, with no whitespace, rather
+    /// than one record per line, which suits line-based log ingestion.
+    /// 
+    #[structopt(long, conflicts_with_all=&["fingerprint","col-transform","sanitize"])]
+    pub compact_json: bool,
+
+    /// Prefix each line of the results with the UTC time it was written, as in 
+    /// [2024-01-01T12:00:00Z], for collection into a shared log. The json formats
+    /// instead wrap the results in an object with timestamp and result keys.
+    /// 
+    #[structopt(long)]
+    pub timestamp_output: bool,
+
+    /// Transform the values of specific columns in the json and ndjson records shown 
+    /// by LIST and EXPORT, given as comma separated column=transform pairs. The 
+    /// transforms are fingerprint, which replaces a public key with its SHA256 
+    /// fingerprint, localtime, truncate:N, which shortens values to N characters, and
+    /// redact. For example:
+    /// 
+    ///   --col-transform public_key=fingerprint,created=localtime
+    /// 
+    #[structopt(long, use_delimiter = true, conflicts_with_all=&["fingerprint","distinct","diff-against"], 
+                parse(try_from_str = parse_col_transform))]
+    pub col_transform: Vec<(String, ColTransform)>,
+
+    /// Apply the configuration file's sanitize rules to the records written by
+    /// LIST and EXPORT in the json and ndjson formats, dropping some columns and
+    /// hashing others, so that exports can be shared without their secrets.
+    /// --json-off can't be used, since the rules apply to json records.
+    /// 
+    #[structopt(long, conflicts_with_all=&["distinct","diff-against"])]
+    pub sanitize: bool,
+
+    /// Retrieve all selected records with the LIST or EXPORT operation by fetching
+    /// pages of --limit records until a page comes back short, which bounds memory
+    /// use on large tables. The pages are combined into a single result. Records 
+    /// are listed in id order, descending with --sort id --desc.
+    /// 
+    #[structopt(long, conflicts_with_all=&["diff-against","distinct","count-only"])]
+    pub all_pages: bool,
+
+    /// Sort the listed records by this column, in ascending order unless --desc is 
+    /// set. Only applies to the LIST and EXPORT operations. For example, the 10 most 
+    /// recent records are selected by:
+    /// 
+    ///   --sort created --desc --limit 10
+    /// 
+    #[structopt(long, parse(try_from_str = parse_column))]
+    pub sort: Option<String>,
+
+    /// Sort in descending order.
+    /// 
+    #[structopt(long, requires="sort")]
+    pub desc: bool,
+
+    /// Return records in the database's own order when --sort isn't given. Otherwise
+    /// LIST and EXPORT return pubkeys and delegations newest first and clients by 
+    /// app_name.
+    /// 
+    #[structopt(long, conflicts_with="sort")]
+    pub no_default_sort: bool,
+
+    /// Return the records of LIST, EXPORT and DUMP-ALL in id order when --sort isn't 
+    /// given, so that the order is the same on every run and sqlite version. With 
+    /// --normalize-json, the output only changes when the records do. Tables other
+    /// than the TMS tables are ordered by rowid, which can be slower for a table 
+    /// without a rowid primary key.
+    /// 
+    #[structopt(long, conflicts_with_all=&["sort","no-default-sort"])]
+    pub stable: bool,
+
+    /// Limit the number of records returned. All records are returned when no limit
+    /// is given. A limit of 0 also means no limit but is deprecated; use --no-limit.
+    /// 
+    #[structopt(short, long, parse(try_from_str = parse_limit))]
+    pub limit: Option<i32>,
+
+    /// Return all records, overriding any limit set by the --profile.
+    /// 
+    #[structopt(long, conflicts_with = "limit")]
+    pub no_limit: bool,
+
+    /// Fail instead of listing or exporting records if more than this many are 
+    /// selected, which are counted first. A --profile can set a default.
+    /// 
+    #[structopt(long)]
+    pub max_rows: Option<u64>,
+
+    /// Don't prompt user for confirmation on deletes (default=false, implying conformation on).
+    /// Also applies to client updates.
+    /// 
+    #[structopt(long, visible_alias = "yes")]
+    pub confirm_delete_off: bool,
+
+    /// Read the answer to confirmation prompts from this file instead of the 
+    /// terminal, so that a scripted run still has an explicit confirmation. An
+    /// answer starting with y confirms; a missing or empty file doesn't.
+    /// 
+    #[structopt(long, value_name = "path", conflicts_with = "confirm-delete-off")]
+    pub confirm_from: Option<String>,
+
+    /// Before asking to confirm a DELETE, describe it in a sentence naming the table,
+    /// the conditions that select the records and how many records would be deleted.
+    /// 
+    #[structopt(long, conflicts_with = "confirm-delete-off")]
+    pub explain_confirm: bool,
+
+    /// Treat a confirmation prompt that isn't answered within this many seconds as
+    /// a no, and exit with code 5, so that an abandoned terminal doesn't leave a 
+    /// change waiting indefinitely.
+    /// 
+    #[structopt(long, value_name = "seconds", conflicts_with_all = &["confirm-delete-off","confirm-from"])]
+    pub prompt_timeout: Option<u64>,
+
+    /// Enable the selected clients using the UPDATE operation.
+    /// 
+    #[structopt(long, conflicts_with="disable")]
+    pub enable: bool,
+
+    /// Disable the selected clients using the UPDATE operation. Disabled clients 
+    /// remain in the database and can be enabled again.
+    /// 
+    #[structopt(long)]
+    pub disable: bool,
+
+    /// Copy the selected clients to the specified tenant using the UPDATE operation.
+    /// The copies get new ids and fresh created and updated times, and are inserted 
+    /// in a single transaction. Nothing is copied if a client_id already exists in 
+    /// the target tenant.
+    /// 
+    #[structopt(long, conflicts_with_all=&["enable","disable"])]
+    pub copy_to_tenant: Option<String>,
+
+    /// Rename a client's client_id from the first value to the second using the
+    /// UPDATE operation on the client resource. The delegations and pubkeys of the
+    /// client are updated to match in the same transaction. Other record filters,
+    /// such as --tenant, limit which tenants are affected. Nothing is renamed if 
+    /// the new client_id already exists in any of those tenants.
+    /// 
+    #[structopt(long, number_of_values = 2, value_names = &["old", "new"], 
+                conflicts_with_all=&["enable","disable","copy-to-tenant"])]
+    pub rename: Option<Vec<String>>,
+
+    /// Provide an SQL WHERE clause to be submitted as part of a SQL statement. The clause
+    /// must start with the word "WHERE" (case insensitive) and be written exactly as it would 
+    /// appear in an SQL statment. Example:
+    /// 
+    ///   "WHERE tms_user_id = 'bud' and host = 'example.com'"
+    /// 
+    /// Use the LIST operation to discover the columns that can be referenced for a chosen
+    /// resource. Discovery can use JSON or non-JSON formatting and "--limit 1" to minimize
+    /// output.
+    /// 
+    /// The option can be repeated, in which case the clauses are combined with AND. Only
+    /// the first clause needs to start with WHERE. Example:
+    /// 
+    ///   -s "WHERE tms_user_id = 'bud'" -s "host = 'example.com'"
+    /// 
+    #[structopt(short, long, number_of_values = 1)]
+    pub sqlwhere: Vec<String>,
+
+    /// Write a WHERE clause in $VISUAL or $EDITOR, which opens on a template listing
+    /// the columns of the resource's table, and use it like another --sqlwhere 
+    /// clause. Saving an empty clause cancels the operation.
+    /// 
+    #[structopt(long, conflicts_with_all=&["ids-stdin","batch"])]
+    pub edit_where: bool,
+
+    /// Only accept --sqlwhere clauses made of the resource's columns, string and number
+    /// literals, comparison operators, parentheses, commas and the keywords WHERE, AND,
+    /// OR, NOT, IN, LIKE, IS, NULL, BETWEEN and ESCAPE. Anything else, such as a 
+    /// subquery, a function call or a comment, is rejected.
+    /// 
+    #[structopt(long)]
+    pub strict_sql: bool,
+
+    /// Refuse to run unless the sqlite3 program is at least this version, such as 
+    /// 3.35.0 for --sqlwhere clauses that use newer SQL. Tmsadm itself requires 3.33.0.
+    /// The version found is shown by --version and --debug.
+    /// 
+    #[structopt(long, value_name = "version", parse(try_from_str = parse_version))]
+    pub min_sqlite_version: Option<(u32, u32, u32)>,
+
+    /// Replace $VAR and ${VAR} in the --sqlwhere clauses with the environment 
+    /// variables' values before the clauses are checked. Undefined variables are
+    /// an error.
+    /// 
+    #[structopt(long, requires = "sqlwhere")]
+    pub expand_where: bool,
+
+    /// Comma separated list of integer record ids to select, typically used with 
+    /// DELETE. The ids are converted to "WHERE id IN (...)" and cannot be combined 
+    /// with --sqlwhere. Example:
+    /// 
+    ///   --ids 3,7,12
+    /// 
+    #[structopt(long, use_delimiter = true, conflicts_with = "sqlwhere")]
+    pub ids: Vec<i64>,
+
+    /// Select only records whose id is greater than this one, in ascending id order. 
+    /// Only applies to the LIST and EXPORT operations. Polling scripts can pass the 
+    /// highest id they've seen to fetch just the newer records.
+    /// 
+    #[structopt(long, conflicts_with_all = &["sort","no-default-sort"])]
+    pub since_id: Option<i64>,
+
+    /// List only the selected record with the lowest id, which is the first one
+    /// added.
+    /// 
+    #[structopt(long, conflicts_with_all = &["last","limit","no-limit","sort","no-default-sort","since-id",
+                                          "all-pages","count-only","orphans"])]
+    pub first: bool,
+
+    /// List only the most recently created of the selected records, using the id to
+    /// break ties.
+    /// 
+    #[structopt(long, conflicts_with_all = &["limit","no-limit","sort","no-default-sort","since-id",
+                                          "all-pages","count-only","orphans"])]
+    pub last: bool,
+
+    /// Keep listing the selected records as they're added, polling every 
+    /// --tail-interval seconds until interrupted. Only records added after tailing
+    /// starts are shown unless --since-id is given.
+    /// 
+    #[structopt(long, conflicts_with_all = &["limit","sort","first","last","all-pages","count-only","distinct",
+                                          "count-distinct","diff-against","join-clients","orphans","output"])]
+    pub tail: bool,
+
+    /// Seconds between the polls made by --tail.
+    /// 
+    #[structopt(long, default_value = "2")]
+    pub tail_interval: u64,
+
+    /// Read the record ids to select from stdin instead of --ids, separated by 
+    /// whitespace or newlines. Deletion is confirmed on the terminal since stdin is 
+    /// used for the ids. Example:
+    /// 
+    ///   other-tool | tmsadm -o DELETE -r delegation --ids-stdin
+    /// 
+    #[structopt(long, conflicts_with_all=&["sqlwhere","ids"])]
+    pub ids_stdin: bool,
+
+    /// Select records whose tms_user_id matches exactly. The shortcut filters are
+    /// combined with each other and with --sqlwhere or --ids using AND.
+    /// 
+    #[structopt(long)]
+    pub user: Option<String>,
+
+    /// Select records whose host matches exactly.
+    /// 
+    #[structopt(long)]
+    pub host: Option<String>,
+
+    /// Select records whose tenant matches exactly.
+    /// 
+    #[structopt(long)]
+    pub tenant: Option<String>,
+
+    /// Select delegations whose client_id matches exactly, with the LIST or DELETE 
+    /// operation. A warning is shown if no client has the id, within the --tenant 
+    /// if one is given.
+    /// 
+    #[structopt(long, value_name = "id", conflicts_with = "orphans")]
+    pub client_id: Option<String>,
+
+    /// Match the --user, --host and --tenant values ignoring ASCII case. Conditions
+    /// given with --sqlwhere are not affected.
+    /// 
+    #[structopt(long)]
+    pub ci: bool,
+
+    /// Select records in which any text column contains this text, ignoring ASCII 
+    /// case. The text matches literally, including any % and _ characters.
+    /// 
+    #[structopt(long, conflicts_with="orphans")]
+    pub search: Option<String>,
+
+    /// Select records created at or after this ISO-8601 date or timestamp, such as
+    /// 2024-01-31 or 2024-01-31T12:00:00Z.
+    /// 
+    #[structopt(long, parse(try_from_str = parse_iso8601))]
+    pub created_since: Option<String>,
+
+    /// Select records created at or before this ISO-8601 date or timestamp. A date
+    /// without a time includes the whole day.
+    /// 
+    #[structopt(long, parse(try_from_str = parse_iso8601))]
+    pub created_until: Option<String>,
+
+    /// Delete all records belonging to the specified tms_user_id from the 
+    /// delegations, pubkeys and clients tables in a single transaction. Requires
+    /// the DELETE operation; no resource is specified. Record counts for each 
+    /// table are shown before confirmation and nothing is deleted if any 
+    /// statement fails.
+    /// 
+    #[structopt(long)]
+    pub purge_user: Option<String>,
+
+    /// Select delegations whose client_id and tenant don't match any client record.
+    /// Only applies to the delegation resource and cannot be combined with other
+    /// record filters. Use with LIST to show orphans or DELETE to remove them.
+    /// 
+    #[structopt(long, conflicts_with_all = &["sqlwhere", "ids", "user", "host", "tenant",
+                                          "created-since", "created-until", "since-id"])]
+    pub orphans: bool,
+
+    /// Include each delegation's client app_name, app_version and enabled flag, as 
+    /// client_app_name, client_app_version and client_enabled, with the LIST 
+    /// operation on the delegation resource. They're null for orphaned delegations.
+    /// 
+    #[structopt(long, conflicts_with_all = &["orphans", "count-only", "distinct", "count-distinct", 
+                                          "all-pages", "diff-against"])]
+    pub join_clients: bool,
+
+    /// Skip the delete confirmation prompt when the number of records to be deleted
+    /// is at or below this threshold. Larger deletes are always confirmed unless
+    /// --confirm-delete-off is set.
+    /// 
+    #[structopt(long)]
+    pub confirm_threshold: Option<u64>,
+
+    /// The number of records shown before a delete, or a client update or copy, is
+    /// confirmed, followed by a count of the records not shown. No more than this 
+    /// many are shown whatever the --limit, which doesn't apply to deletes. Also 
+    /// named --preview-limit.
+    /// 
+    #[structopt(long, visible_alias = "preview-limit", default_value = "10")]
+    pub preview_rows: u64,
+
+    /// Append a json line to this file for each completed DELETE operation, 
+    /// recording the user, the table, the statement run and whether the deletion
+    /// was confirmed at a prompt, skipped with --confirm-delete-off or allowed by
+    /// --confirm-threshold.
+    /// 
+    #[structopt(long, env = "TMSADM_AUDIT_LOG")]
+    pub audit_log: Option<String>,
+
+    /// Before the DELETE operation deletes records, append each of them to this file
+    /// as a json line with the time it was archived and its table. This includes the
+    /// delegations removed by --cascade and the records removed by the cleanup options,
+    /// such as --purge-user and --expire-cleanup. The records are only deleted if the
+    /// archive is written.
+    /// 
+    #[structopt(long, value_name = "path", conflicts_with="soft-delete")]
+    pub deleted_archive: Option<String>,
+
+    /// When the DELETE operation deletes clients, also delete their delegations, in
+    /// the same transaction, rather than leaving them without a client.
+    /// 
+    #[structopt(long, conflicts_with="soft-delete")]
+    pub cascade: bool,
+
+    /// Skip checking that the database file exists before calling sqlite3. Sqlite3
+    /// creates a new database if the file doesn't exist, so use this option with
+    /// care.
+    /// 
+    #[structopt(long)]
+    pub no_db_check: bool,
+
+    /// Open the database read-only, so that sqlite3 rejects any change to it. This
+    /// is the safer way to query a database that the TMS server is using.
+    /// 
+    #[structopt(long)]
+    pub readonly: bool,
+
+    /// Don't show informational warnings, such as those about a database that may
+    /// be in use or --sqlwhere names that aren't columns.
+    /// 
+    #[structopt(long)]
+    pub no_warnings: bool,
+
+    /// Check that the database can be read, opening it read-only to run SELECT 1,
+    /// and exit with 0 if it can or 6 if it can't, printing nothing. This is meant 
+    /// for liveness probes.
+    /// 
+    #[structopt(long)]
+    pub healthcheck: bool,
+
+    /// Also have --healthcheck check that the pubkeys, clients and delegations 
+    /// tables exist.
+    /// 
+    #[structopt(long, requires = "healthcheck")]
+    pub healthcheck_tables: bool,
+
+    /// Describe the --healthcheck result, and any failure, on stdout.
+    /// 
+    #[structopt(long, requires = "healthcheck")]
+    pub verbose: bool,
+
+    /// Skip checking that the database contains the pubkeys, clients and delegations
+    /// tables before operating on it.
+    /// 
+    #[structopt(long)]
+    pub no_schema_check: bool,
+
+    /// The aggregate report generated by the REPORT operation, which doesn't take a
+    /// resource. The delegations-per-client report counts delegations by client.
+    /// 
+    #[structopt(long, possible_values=&["delegations-per-client"])]
+    pub report: Option<TmsReport>,
+
+    /// Generate a REPORT that counts the resource's selected records for each value 
+    /// of this column, largest counts first. The limit applies to the number of 
+    /// values reported.
+    /// 
+    #[structopt(long, conflicts_with="report", parse(try_from_str = parse_column))]
+    pub group_by: Option<String>,
+
+    /// Generate a REPORT that counts the resource's selected records for each day of
+    /// this timestamp column, in date order. Use --created-since and --created-until
+    /// to bound the days.
+    /// 
+    #[structopt(long, conflicts_with_all=&["report","group-by"], parse(try_from_str = parse_column))]
+    pub count_by_day: Option<String>,
+
+    /// Generate a REPORT of the values of these comma separated columns that more 
+    /// than one of the resource's selected records share, with their counts. For
+    /// example, duplicate keys for the same user and host are found by:
+    /// 
+    ///   --duplicates tms_user_id,host
+    /// 
+    #[structopt(long, use_delimiter = true, conflicts_with_all=&["report","group-by","count-by-day"], 
+                parse(try_from_str = parse_column))]
+    pub duplicates: Vec<String>,
+
+    /// Path to the input file used by the IMPORT operation, or - to read stdin. The
+    /// input is a JSON array of objects in the same format as LIST's JSON output, or 
+    /// one JSON object per line as in ndjson output. For the ROTATE operation, the 
+    /// file contains the new public key, such as an id_ed25519.pub file.
+    /// 
+    #[structopt(long)]
+    pub input: Option<String>,
+
+    /// Fail the IMPORT operation on the first malformed line of newline-delimited 
+    /// input instead of skipping and reporting it.
+    /// 
+    #[structopt(long)]
+    pub strict: bool,
+
+    /// The new public key in OpenSSH format used by the ROTATE operation, which 
+    /// replaces the key of the pubkey record selected by --user and --host. Example:
+    /// 
+    ///   --public-key "ssh-ed25519 AAAAC3Nza... bud@example.com"
+    /// 
+    #[structopt(long, conflicts_with="input")]
+    pub public_key: Option<String>,
+
+    /// Path to the output file written by the EXPORT operation, or that the results 
+    /// of the LIST, REPORT and DUMP operations are written to instead of stdout. SQL is 
+    /// never echoed into the file. Use --force to overwrite an existing file.
+    /// 
+    #[structopt(long)]
+    pub output: Option<String>,
+
+    /// Path to a file that gets a copy of the results of the LIST, REPORT and DUMP 
+    /// operations while they're also written to stdout, like the tee command. Use 
+    /// --force to overwrite an existing file.
+    /// 
+    #[structopt(long, conflicts_with = "output")]
+    pub tee: Option<String>,
+
+    /// Compress EXPORT or DUMP output with gzip as it's written, appending ".gz" to
+    /// the output file name.
+    /// 
+    #[structopt(long)]
+    pub gzip: bool,
+
+    /// Write the SHA-256 of each EXPORT, DUMP or DUMP-ALL output file to a .sha256 
+    /// file beside it, which --verify-export or sha256sum -c can check later.
+    /// 
+    #[structopt(long)]
+    pub checksum: bool,
+
+    /// Check that an exported file still matches the checksum written by --checksum
+    /// and exit, with a non-zero code if it doesn't. No database is needed.
+    /// 
+    #[structopt(long, value_name = "file")]
+    pub verify_export: Option<String>,
+
+    /// Directory that the DUMP-ALL operation writes a file for each table to, 
+    /// named after the table with an extension for the format. The directory is
+    /// created if needed.
+    /// 
+    #[structopt(long)]
+    pub output_dir: Option<String>,
+
+    /// Number of tables that the DUMP-ALL operation writes at once, each using its
+    /// own read-only connection. A table that fails doesn't stop the others, and 
+    /// the failures are listed at the end.
+    /// 
+    #[structopt(long, value_name = "N", parse(try_from_str = parse_jobs))]
+    pub jobs: Option<usize>,
+
+    /// Additional table to write with the DUMP-ALL operation, after the pubkeys, 
+    /// clients and delegations tables, or a table to write with the DUMP operation
+    /// instead of the resource's table. Can be repeated.
+    /// 
+    #[structopt(long, number_of_values = 1, parse(try_from_str = parse_column))]
+    pub table: Vec<String>,
+
+    /// Run ANALYZE to rebuild all of the query planner's statistics before the 
+    /// OPTIMIZE operation's PRAGMA optimize. This can take a while on large tables.
+    /// 
+    #[structopt(long)]
+    pub analyze: bool,
+
+    /// Delete records older than the limits in the specified TOML retention policy 
+    /// file, which has an optional table per resource with the timestamp column
+    /// and the maximum age in days. Example: 
+    /// 
+    ///   [delegation]
+    ///   column = "created"
+    ///   max_age_days = 90
+    /// 
+    /// Requires the DELETE operation; no resource is specified. All deletes run
+    /// in a single transaction.
+    /// 
+    #[structopt(long)]
+    pub apply_retention: Option<String>,
+
+    /// Delete the selected delegations whose expires_at time has passed, using the DELETE
+    /// operation on the delegation resource. A preview of how long ago they expired is 
+    /// shown first, and the delete is otherwise confirmed and run like any other.
+    /// 
+    #[structopt(long, conflicts_with="orphans")]
+    pub expire_cleanup: bool,
+
+    /// Delete the resource's selected records that share the values of these comma 
+    /// separated columns with another selected record, keeping only the newest (highest
+    /// id) record of each group, using the DELETE operation. The records to be removed 
+    /// are previewed first.
+    /// 
+    #[structopt(long, use_delimiter = true, conflicts_with_all=&["orphans","soft-delete","expire-cleanup"], 
+                parse(try_from_str = parse_column))]
+    pub dedupe: Vec<String>,
+
+    /// Mark the records selected by the DELETE operation as deleted by setting their
+    /// deleted_at column to the current time instead of removing them. The table 
+    /// must have a deleted_at column. Marked records are left out of later selections
+    /// unless --include-deleted or --only-deleted is given.
+    /// 
+    #[structopt(long, conflicts_with="orphans")]
+    pub soft_delete: bool,
+
+    /// Include the records marked deleted by --soft-delete, which are otherwise 
+    /// left out of the records selected from tables that have a deleted_at column.
+    /// 
+    #[structopt(long)]
+    pub include_deleted: bool,
+
+    /// Select only the records marked deleted by --soft-delete, such as to list them 
+    /// before removing them for good. The table must have a deleted_at column.
+    /// 
+    #[structopt(long, conflicts_with_all=&["include-deleted","soft-delete","orphans"])]
+    pub only_deleted: bool,
+
+    /// Permanently delete the records that --soft-delete marked deleted before this 
+    /// ISO-8601 date or timestamp, using the DELETE operation. Applies to the resources'
+    /// tables or, without --resource, to each TMS table with a deleted_at column.
+    /// 
+    #[structopt(long, value_name = "date", conflicts_with_all=&["soft-delete","orphans","only-deleted"], 
+                parse(try_from_str = parse_iso8601))]
+    pub purge_soft_deleted_before: Option<String>,
+
+    /// After a DELETE, count the records its WHERE clause selects and fail if any
+    /// remain.
+    /// 
+    #[structopt(long, conflicts_with_all = &["soft-delete","dry-run"])]
+    pub verify: bool,
+
+    /// Show what would be deleted without changing the database. Applies to the DELETE
+    /// operation, which shows the number of records and the DELETE statement, to the
+    /// UPDATE of enabled flags, and to --purge-user, --apply-retention and --batch, 
+    /// whose lines are each run as a dry run. Other changes have no dry run and are 
+    /// skipped.
+    /// 
+    #[structopt(long)]
+    pub dry_run: bool,
+
+    /// Exit with code 3 if the LIST or REPORT operation returns no rows, such as 
+    /// when an expected client has disappeared. Output is written as usual.
+    /// 
+    #[structopt(long)]
+    pub fail_on_empty: bool,
+
+    /// Exit with code 4 if the LIST or REPORT operation returns any rows. Combined
+    /// with a filter, such as --sqlwhere, this asserts that no records match.
+    /// 
+    #[structopt(long, conflicts_with="fail-on-empty")]
+    pub fail_on_rows: bool,
+
+    /// Exit with code 7 if the DELETE operation deletes no records, so that a cleanup
+    /// job can assert that it removed something. A dry run never fails this way.
+    /// 
+    #[structopt(long)]
+    pub require_deletes: bool,
+
+    /// When the operation finishes, write a json object to stderr with the op, the 
+    /// resource, the number of rows returned, the number of records affected by a
+    /// change (or null), the elapsed_ms and the exit code.
+    /// 
+    #[structopt(long)]
+    pub summary_json: bool,
+
+    /// When the operation finishes, replace this file with one holding just the 
+    /// number of rows returned by LIST, EXPORT or REPORT, or the number of records 
+    /// changed by other operations, for monitoring tools to read.
+    /// 
+    #[structopt(long, value_name = "path")]
+    pub count_file: Option<String>,
+
+    /// Report a failure on stderr as a json object with code, kind and message 
+    /// fields instead of as text. The kind is usage, database, error, or for exits
+    /// with a non-zero code, empty, rows or failed.
+    /// 
+    #[structopt(long)]
+    pub errors_json: bool,
+
+    /// Allow an operation to use or overwrite an existing file, such as INIT on an
+    /// existing database file.
+    /// 
+    #[structopt(long)]
+    pub force: bool,
+
+    /// Wait up to this many milliseconds for a locked database to become available
+    /// instead of failing immediately. Useful while the TMS Server is writing.
+    /// 
+    #[structopt(long)]
+    pub busy_timeout: Option<u32>,
+
+    /// Attach another database as name=path so that --sqlwhere can refer to its 
+    /// tables as name.table, as in cross-database comparisons. Can be repeated.
+    /// 
+    #[structopt(long, number_of_values = 1, value_name = "name=path", parse(try_from_str = parse_attach))]
+    pub attach: Vec<(String, String)>,
+
+    /// Stop any sqlite3 command that runs longer than this many seconds and fail with
+    /// an "operation timed out" error. By default commands can run indefinitely.
+    /// 
+    #[structopt(long)]
+    pub timeout: Option<u64>,
+
+    /// Run an sqlite3 command again, up to this many times, when it fails because 
+    /// the database is locked or busy, waiting 100ms before the first retry and 
+    /// twice as long before each one after that. Other errors are not retried.
+    /// 
+    #[structopt(long, default_value = "0")]
+    pub retries: u32,
+
+    /// The passphrase of a database encrypted with SQLCipher, which is opened with the 
+    /// sqlcipher program instead of sqlite3. The key is never shown, even with --debug,
+    /// and is passed to sqlcipher in a temporary file that only the user can read.
+    /// 
+    #[structopt(long, env = "TMS_DB_KEY", hide_env_values = true)]
+    pub key: Option<DbKey>,
+
+    /// Restore the database from its newest <dbpath>.bak-* backup after confirmation,
+    /// keeping the current database as <dbpath>.pre-undo. The available backups are
+    /// listed if there are several. Tmsadm doesn't create these backups, so copy the
+    /// database to a <dbpath>.bak-<suffix> file, for example with sqlite3's .backup
+    /// command, before making changes that may need undoing. Undo is refused while a
+    /// <dbpath>.pre-undo file from an earlier undo exists.
+    /// 
+    #[structopt(long)]
+    pub undo: bool,
+
+    /// Run the tmsadm commands in this file, one per line, in order. For example:
+    /// 
+    ///   list pubkey --host example.com
+    ///   delete delegation --orphans
+    /// 
+    /// The commands are confirmed once, unless confirmation is turned off, and use
+    /// this run's --dbpath unless they give their own. They can't read stdin.
+    /// 
+    #[structopt(long, value_name = "file", conflicts_with_all = &["operation","undo"])]
+    pub batch: Option<String>,
+
+    /// Keep running a --batch's commands after one fails.
+    /// 
+    #[structopt(long, requires = "batch")]
+    pub continue_on_error: bool,
+
+    /// The backup file that --undo restores instead of the newest one.
+    /// 
+    #[structopt(long, requires="undo")]
+    pub from: Option<String>,
+
+    /// Print the settings this run would use, after applying any --profile, environment 
+    /// variables and command line options, and exit.
+    /// 
+    #[structopt(long)]
+    pub show_config: bool,
+
+    /// Print the absolute database path that this run would use and whether the 
+    /// file exists, and exit.
+    /// 
+    #[structopt(long)]
+    pub print_path: bool,
+
+    /// Check the configuration file for problems, such as unknown settings, invalid 
+    /// formats or limits and database paths in directories that don't exist, print 
+    /// them and exit with a non-zero code if there are any. No database is needed.
+    /// 
+    #[structopt(long)]
+    pub check_config: bool,
+
+    /// Print the database's schema version and exit. The version is read from a 
+    /// migrations table if one exists, otherwise from PRAGMA user_version.
+    /// 
+    #[structopt(long)]
+    pub schema_version: bool,
+
+    /// Print the database's page_size, journal_mode, cache_size, auto_vacuum and 
+    /// user_version PRAGMAs and exit.
+    /// 
+    #[structopt(long)]
+    pub show_pragmas: bool,
+
+    /// Estimate how much space a VACUUM would reclaim from the free pages listed by
+    /// PRAGMA freelist_count and exit, without changing the database.
+    /// 
+    #[structopt(long)]
+    pub vacuum_estimate: bool,
+
+    /// Print the CREATE statements for the database's tables, indexes and triggers 
+    /// and exit. Only the statements for the --table tables or the resource's table
+    /// are printed if either is given.
+    /// 
+    #[structopt(long)]
+    pub dump_schema: bool,
+
+    /// Compare each table's record count with the count expected by the file, a 
+    /// TOML or, with a .json extension, JSON object of table = count pairs, and 
+    /// exit with a non-zero code if any differ.
+    /// 
+    #[structopt(long, value_name = "file")]
+    pub expect_counts: Option<String>,
+
+    /// Build a --sqlwhere clause for the resource's table step by step, choosing a 
+    /// column, operator and value for each condition, and optionally list the 
+    /// records it selects.
+    /// 
+    #[structopt(long, conflicts_with_all = &["confirm-delete-off","confirm-from","sqlwhere"])]
+    pub build_where: bool,
+
+    /// Set a PRAGMA stored in the database, given as name=value, and exit. Only 
+    /// page_size, auto_vacuum, journal_mode and user_version can be set. Changing
+    /// page_size or auto_vacuum runs VACUUM, which is confirmed first. Can be 
+    /// repeated.
+    /// 
+    #[structopt(long, number_of_values = 1, value_name = "name=value", 
+                conflicts_with = "show-pragmas", parse(try_from_str = parse_pragma))]
+    pub set_pragma: Vec<(String, String)>,
+
+    /// Write the time taken by each sqlite3 command to stderr. A transaction, such as
+    /// the one --purge-user runs, is a single command.
+    /// 
+    #[structopt(long)]
+    pub trace: bool,
+
+    /// Print debugging information such as the parsed command line arguments.
+    /// 
+    #[structopt(long)]
+    pub debug: bool,
+}
+
+// ---------------------------------------------------------------------------
+// RetentionPolicy:
+// ---------------------------------------------------------------------------
+/** The retention policy file read by --apply-retention, written in TOML with
+ * an optional table per resource.  For example:
+ * 
+ *   [delegation]
+ *   column = "created"
+ *   max_age_days = 90
+ * 
+ *   [pubkey]
+ *   column = "updated"
+ *   max_age_days = 180
+ */
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionPolicy {
+    pub pubkey: Option<RetentionRule>,
+    pub client: Option<RetentionRule>,
+    pub delegation: Option<RetentionRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionRule {
+    /// Timestamp column that determines a record's age.
+    pub column: String,
+    /// Records older than this many days are deleted.
+    pub max_age_days: u32,
+}
+
+impl RetentionPolicy {
+    /** Read and parse the policy file, panicking on any error. */
+    fn load(path: &str) -> RetentionPolicy {
+        let text = match fs::read_to_string(path) {
+            Ok(t) => t,
+            Err(e) => panic!("Unable to read retention policy file {}: {}", path, e),
+        };
+        let policy: RetentionPolicy = match toml::from_str(&text) {
+            Ok(p) => p,
+            Err(e) => panic!("Invalid retention policy file {}: {}", path, e),
+        };
+        for (table, rule) in policy.rules() {
+            if !is_identifier(&rule.column) {
+                panic!("Invalid column name for {} in retention policy file {}: {}", 
+                       table, path, rule.column);
+            }
+        }
+        policy
+    }
+
+    /** The rules that are present, keyed by table in dependency order. */
+    fn rules(&self) -> Vec<(&'static str, &RetentionRule)> {
+        let all = [(TmsResource::delegation, &self.delegation),
+                   (TmsResource::pubkey, &self.pubkey),
+                   (TmsResource::client, &self.client)];
+        all.into_iter()
+           .filter_map(|(r, rule)| rule.as_ref().map(|rule| (r.table(), rule)))
+           .collect()
+    }
+}
+
+impl RetentionRule {
+    /** The condition that selects records older than the maximum age. */
+    fn condition(&self) -> String {
+        format!("julianday({}) < julianday('now', '-{} days')", self.column, self.max_age_days)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// TmsadmConfig:
+// ---------------------------------------------------------------------------
+/** The tmsadm configuration file, written in TOML, which defines named 
+ * profiles that --profile selects.  For example:
+ * 
+ *   [profile.prod]
+ *   dbpath = "/opt/tms/database/tms.db"
+ *   format = "ndjson"
+ *   limit = 100
+ *   max_rows = 10000
+ * 
+ * It can also restrict the operations each user may run.  When there's a
+ * permissions table, users are only permitted the operations listed for them
+ * or, failing that, for "*":
+ * 
+ *   [permissions]
+ *   alice = ["LIST", "EXPORT", "DELETE", "UNDO"]
+ *   "*" = ["LIST"]
+ * 
+ * Besides the operations, BATCH, UNDO and SET-PRAGMA permit --batch, --undo
+ * and --set-pragma, which change the database without an --operation.
+ * 
+ * And it can define other names for the resources, which --resource accepts:
+ * 
+ *   [resource_aliases]
+ *   grant = "delegation"
+ * 
+ * The sanitize table says which columns --sanitize drops from exported 
+ * records and which it replaces with a hash.  A profile that sets 
+ * require_sanitize = true refuses to export without --sanitize:
+ * 
+ *   [sanitize]
+ *   public_key = "drop"
+ *   client_secret = "hash"
+ */
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TmsadmConfig {
+    #[serde(default)]
+    pub profile: BTreeMap<String, TmsadmProfile>,
+    pub permissions: Option<BTreeMap<String, Vec<String>>>,
+    #[serde(default)]
+    pub resource_aliases: BTreeMap<String, String>,
+    #[serde(default)]
+    pub sanitize: BTreeMap<String, SanitizeRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TmsadmProfile {
+    /// Path to the profile's TMS database file.
+    pub dbpath: Option<String>,
+    /// Output format used with the profile.
+    pub format: Option<String>,
+    /// Record limit used with the profile.
+    pub limit: Option<i32>,
+    /// Largest number of records LIST and EXPORT return with the profile.
+    pub max_rows: Option<u64>,
+    /// Whether EXPORT requires --sanitize with the profile.
+    pub require_sanitize: Option<bool>,
+}
+
+impl TmsadmConfig {
+    /** Read and parse the configuration file, panicking on any error. */
+    fn load(path: &str) -> TmsadmConfig {
+        let text = match fs::read_to_string(path) {
+            Ok(t) => t,
+            Err(e) => panic!("Unable to read configuration file {}: {}", path, e),
+        };
+        match toml::from_str(&text) {
+            Ok(c) => c,
+            Err(e) => panic!("Invalid configuration file {}: {}", path, e),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// DbKey:
+// ---------------------------------------------------------------------------
+/** A database encryption key, which is never shown in debug output. */
+pub struct DbKey(String);
+
+impl std::str::FromStr for DbKey {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {return Err("the database key cannot be empty".to_string());}
+        Ok(DbKey(s.to_string()))
+    }
+}
+
+impl std::fmt::Debug for DbKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("DbKey(********)")
+    }
+}
+
+// ***************************************************************************
+//                               Command Runner
+// ***************************************************************************
+// ---------------------------------------------------------------------------
+// CommandOutput:
+// ---------------------------------------------------------------------------
+/** What remains of a finished command once its stdout has been read. */
+pub struct CommandOutput {
+    pub status: ExitStatus,
+    pub stderr: Vec<u8>,
+}
+
+// ---------------------------------------------------------------------------
+// CommandRunner:
+// ---------------------------------------------------------------------------
+/** Runs the commands that tmsadm builds, which separates building a command
+ * from running it.  A test double can record the command it's given and 
+ * simulate output, failures and exit codes without starting a process.
+ */
+pub trait CommandRunner: Sync {
+    /** Run the command to completion, writing the input to its stdin if there
+     * is any and passing its stdout to the reader.  An error is returned if 
+     * the command can't be run or the reader fails, in which case the command
+     * is stopped.
+     */
+    fn run(&self, command: &mut Command, input: Option<&str>,
+           reader: &mut dyn FnMut(&mut dyn BufRead) -> io::Result<()>) -> io::Result<CommandOutput>;
+}
+
+// ---------------------------------------------------------------------------
+// command_runner:
+// ---------------------------------------------------------------------------
+/** Return the runner of the thread's sqlite3 commands. */
+fn command_runner() -> &'static dyn CommandRunner {
+    COMMAND_RUNNER.with(|r| *r.borrow())
+}
+
+// ---------------------------------------------------------------------------
+// ProcessRunner:
+// ---------------------------------------------------------------------------
+/** Runs commands as child processes.  Each child's process id is remembered 
+ * while it runs so that the interrupt handler can terminate it, and a child
+ * that runs longer than the --timeout is terminated by a watchdog thread.  Stdin is
+ * written and stderr is drained on separate threads so that the child can't
+ * block on a full pipe while we're still reading stdout.
+ */
+pub struct ProcessRunner;
+
+impl CommandRunner for ProcessRunner {
+    fn run(&self, command: &mut Command, input: Option<&str>,
+           reader: &mut dyn FnMut(&mut dyn BufRead) -> io::Result<()>) -> io::Result<CommandOutput> {
+        command.stdout(Stdio::piped());
+        command.stderr(Stdio::piped());
+        if input.is_some() {command.stdin(Stdio::piped());}
+        let mut child = command.spawn()?;
+        CHILD_PIDS.lock().unwrap_or_else(|e| e.into_inner()).push(child.id());
+
+        // Stop a child that runs past the timeout.
+        let (done, finished) = mpsc::channel::<()>();
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let watchdog = TMSADM_ARGS.timeout.map(|secs| {
+            let pid = child.id();
+            let timed_out = Arc::clone(&timed_out);
+            thread::spawn(move || {
+                if finished.recv_timeout(Duration::from_secs(secs)) == Err(RecvTimeoutError::Timeout) {
+                    timed_out.store(true, Ordering::Relaxed);
+                    kill_process(pid);
+                }
+            })
+        });
+
+        // Write stdin and collect stderr in the background.
+        let writer = match (input, child.stdin.take()) {
+            (Some(text), Some(mut child_stdin)) => {
+                let text = text.to_string();
+                Some(thread::spawn(move || {let _ = child_stdin.write_all(text.as_bytes());}))
+            },
+            _ => None,
+        };
+        let mut child_stderr = child.stderr.take().expect("child stderr not captured");
+        let stderr_reader = thread::spawn(move || {
+            let mut buf = Vec::new();
+            let _ = child_stderr.read_to_end(&mut buf);
+            buf
+        });
+
+        // Read stdout, stopping the child if the reader fails.
+        let mut stdout = BufReader::new(child.stdout.take().expect("child stdout not captured"));
+        let read = reader(&mut stdout);
+        if read.is_err() {let _ = child.kill();}
+        drop(stdout);
+        let waited = child.wait();
+        forget_child(child.id());
+        drop(done);
+        if let Some(w) = watchdog {let _ = w.join();}
+        if let Some(w) = writer {let _ = w.join();}
+        let stderr = stderr_reader.join().unwrap_or_default();
+        if timed_out.load(Ordering::Relaxed) {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, format!("operation timed out after {} seconds", 
+                                      TMSADM_ARGS.timeout.unwrap_or_default())));
+        }
+        read?;
+        Ok(CommandOutput {status: waited?, stderr})
+    }
+}
+
+// ---------------------------------------------------------------------------
+// TeeWriter:
+// ---------------------------------------------------------------------------
+/** A writer that writes everything written to it to two writers. */
+struct TeeWriter<'a> {
+    first: &'a mut dyn Write,
+    second: &'a mut dyn Write,
+}
+
+impl Write for TeeWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.first.write(buf)?;
+        self.second.write_all(&buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.first.flush()?;
+        self.second.flush()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// TimestampWriter:
+// ---------------------------------------------------------------------------
+/** A writer that prefixes each line with the time it's written for 
+ * --timestamp-output, unless stamp_lines is off, and notes whether anything
+ * was written.
+ */
+struct TimestampWriter<'a> {
+    inner: &'a mut dyn Write,
+    stamp_lines: bool,
+    line_start: bool,
+    written: bool,
+}
+
+impl Write for TimestampWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for line in buf.split_inclusive(|b| *b == b'\n') {
+            if self.stamp_lines && self.line_start {write!(self.inner, "[{}] ", utc_timestamp())?;}
+            self.inner.write_all(line)?;
+            self.line_start = line.ends_with(b"\n");
+            self.written = true;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// CountingReader:
+// ---------------------------------------------------------------------------
+/** A reader that counts the bytes and lines read through it. */
+struct CountingReader<'a> {
+    inner: &'a mut dyn BufRead,
+    counts: &'a mut ReadCounts,
+}
+
+#[derive(Default)]
+struct ReadCounts {
+    bytes: usize,
+    lines: usize,
+}
+
+impl ReadCounts {
+    fn add(&mut self, data: &[u8]) {
+        self.bytes += data.len();
+        self.lines += data.iter().filter(|b| **b == b'\n').count();
+    }
+}
+
+impl Read for CountingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.counts.add(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl BufRead for CountingReader<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+    fn consume(&mut self, amt: usize) {
+        // The consumed bytes are still at the front of the inner buffer.
+        if let Ok(buf) = self.inner.fill_buf() {
+            self.counts.add(&buf[..amt.min(buf.len())]);
+        }
+        self.inner.consume(amt);
+    }
+}
+
+// ***************************************************************************
+//                               Utilities
+// ***************************************************************************
+// ---------------------------------------------------------------------------
+// process_healthcheck:
+// ---------------------------------------------------------------------------
+/** Exit with 0 if the database file exists and sqlite3 can open it read-only
+ * and read its schema, and with --healthcheck-tables finds the TMS tables,
+ * or else with EXIT_UNHEALTHY.  Failures are returned rather than raised, 
+ * so nothing is printed unless --verbose is set.
+ */
+fn process_healthcheck() {
+    match healthcheck() {
+        Ok(()) => {
+            if TMSADM_ARGS.verbose {println!("Database {} is healthy.", db_file_path());}
+        },
+        Err(problem) => {
+            if TMSADM_ARGS.verbose {println!("Database {} is unhealthy: {}", db_file_path(), problem);}
+            exit(EXIT_UNHEALTHY);
+        },
+    }
+}
+
+// ---------------------------------------------------------------------------
+// healthcheck:
+// ---------------------------------------------------------------------------
+/** Run the --healthcheck checks, returning the first problem found. */
+fn healthcheck() -> Result<(), String> {
+    if !Path::new(&db_file_path()).is_file() {
+        return Err("the database file does not exist".to_string());
+    }
+
+    // Reading the table names makes sqlite3 read the file, which SELECT 1 alone doesn't.
+    let mut command = new_sqlite3_cmd();
+    if !TMSADM_ARGS.readonly {command.arg("-readonly");}
+    command.arg(db_path());
+    command.arg("SELECT 1; SELECT name FROM sqlite_master WHERE type = 'table';");
+    let mut stdout = Vec::new();
+    let output = command_runner().run(&mut command, None, &mut |reader| reader.read_to_end(&mut stdout).map(|_| ()))
+        .map_err(|e| format!("unable to run {}: {}", sqlite3_program(), e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(stderr.lines().next().unwrap_or("sqlite3 failed").to_string());
+    }
+    let stdout = String::from_utf8_lossy(&stdout);
+    let mut lines = stdout.lines();
+    if lines.next().map(str::trim) != Some("1") {
+        return Err("SELECT 1 didn't return 1".to_string());
+    }
+    if TMSADM_ARGS.healthcheck_tables {
+        let names: Vec<&str> = lines.map(str::trim).collect();
+        let missing: Vec<&str> = TMS_TABLES.iter().copied().filter(|t| !names.contains(t)).collect();
+        if !missing.is_empty() {
+            return Err(format!("missing table(s) {}", missing.join(", ")));
+        }
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// check_db_file:
+// ---------------------------------------------------------------------------
+/** Check that the database file exists and, unless it's given as a URI or
+ * is encrypted, that it starts with the sqlite header.  An empty file is 
+ * also rejected, since sqlite3 would quietly treat it as a new database.
+ */
+fn check_db_file() {
+    if !Path::new(&db_file_path()).is_file() {
+        panic!("Database file does not exist: {}",db_file_path());
+    }
+    if is_uri(&TMSADM_ARGS.dbpath) || TMSADM_ARGS.key.is_some() {return;}
+
+    let mut header = [0u8; 16];
+    let valid = File::open(db_file_path())
+        .and_then(|mut f| f.read_exact(&mut header))
+        .map(|_| &header == SQLITE_HEADER)
+        .unwrap_or(false);
+    if !valid {
+        panic!("Not a valid SQLite database file: {}", db_file_path());
+    }
+}
+
+// ---------------------------------------------------------------------------
+// check_db_hot:
+// ---------------------------------------------------------------------------
+/** Warn when the database has a non-empty WAL file, which usually means the 
+ * TMS server has it open and is writing to it, so that reads may not match
+ * what the server sees.  This is only informational.
+ */
+fn check_db_hot() {
+    let wal = db_file_path() + "-wal";
+    if fs::metadata(&wal).is_ok_and(|m| m.len() > 0) {
+        eprintln!("Warning: {} is not empty, so the database may be in use by the TMS server. \
+                   Consider using --readonly or querying a copy made with sqlite3's .backup command.", wal);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// check_db_writable:
+// ---------------------------------------------------------------------------
+/** Check that the database file and its directory are writable before a user
+ * is asked to confirm changes that would fail.  Sqlite3 creates its journal
+ * or WAL files in the directory, so the directory is tested by creating and
+ * removing a scratch file there.
+ */
+fn check_db_writable() {
+    if TMSADM_ARGS.readonly {
+        panic!("The database can't be changed when it's opened with --readonly.");
+    }
+    let dbpath = db_file_path();
+    if let Err(e) = fs::OpenOptions::new().write(true).open(&dbpath) {
+        panic!("The database file is not writable: {}: {}", dbpath, e);
+    }
+    let scratch = dbpath.clone() + ".tmsadm.check";
+    match File::create(&scratch) {
+        Ok(_) => {let _ = fs::remove_file(&scratch);},
+        Err(e) => {
+            let dir = Path::new(&dbpath).parent().unwrap_or(Path::new("/"));
+            panic!("The database directory is not writable: {}: {}", dir.display(), e);
+        },
+    }
+}
+
+// ---------------------------------------------------------------------------
+// check_db_schema:
+// ---------------------------------------------------------------------------
+/** Check that the database contains the TMS tables, which catches a --dbpath
+ * that points at the wrong sqlite file before any query fails.
+ */
+fn check_db_schema() {
+    let names: Vec<String> = TMS_TABLES.iter().map(|t| sql_quote(t)).collect();
+    let sql = format!("SELECT name FROM sqlite_master WHERE type = 'table' AND name IN ({})", 
+                      names.join(", "));
+    let found = run_capture(&sql, "CHECK database schema");
+    let missing: Vec<&str> = TMS_TABLES.iter().copied()
+        .filter(|t| !found.lines().any(|l| l.trim() == *t)).collect();
+    if !missing.is_empty() {
+        panic!("{} does not look like a TMS database, it has no {} table(s). Use --no-schema-check to skip this check.", 
+               db_file_path(), missing.join(", "));
+    }
+}
+
+// ---------------------------------------------------------------------------
+// acquire_lock:
+// ---------------------------------------------------------------------------
+/** Take an exclusive advisory lock on the database's sibling lock file, 
+ * <dbpath>.tmsadm.lock, so that concurrent tmsadm runs can't interleave.  The
+ * lock is released when the returned file is dropped or the process exits.
+ * A command run by --batch uses the lock it was given as stdin instead, if
+ * that is this database's lock.
+ * The process exits if another instance holds the lock.  If the lock file
+ * can't be created, operations that change the database fail while read-only
+ * operations proceed without the lock, since read-only users may not be able
+ * to write to the database directory.
+ */
+fn acquire_lock() -> Option<File> {
+    let path = db_file_path() + ".tmsadm.lock";
+    if std::env::var_os(BATCH_LOCK_ENV).is_some() {
+        if let Some(file) = inherited_lock(&path) {return Some(file);}
+    }
+    let file = match fs::OpenOptions::new().create(true).truncate(false).write(true).open(&path) {
+        Ok(f) => f,
+        Err(e) => {
+            if TMSADM_ARGS.undo || TMSADM_ARGS.operation.as_ref().is_some_and(TmsOperation::is_mutation) {
+                panic!("Unable to create lock file {}: {}", path, e);
+            }
+            return None;
+        },
+    };
+    match file.try_lock() {
+        Ok(()) => Some(file),
+        Err(fs::TryLockError::WouldBlock) => {
+            eprintln!("Another tmsadm instance is running against this database (lock file {}).", path);
+            std::process::exit(1);
+        },
+        Err(fs::TryLockError::Error(e)) => panic!("Unable to lock {}: {}", path, e),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// inherited_lock:
+// ---------------------------------------------------------------------------
+/** Return stdin as the lock file at path if it is that file and holds its 
+ * lock.  A --batch run passes its lock down this way.  Locking again through
+ * the inherited file succeeds since it shares the batch's lock, while a lock
+ * file opened by anyone else fails to lock while the batch holds it.  So a
+ * command naming another database, or a hand-set BATCH_LOCK_ENV, finds no 
+ * lock here and takes the lock as usual.
+ */
+fn inherited_lock(path: &str) -> Option<File> {
+    let file = File::from(io::stdin().as_fd().try_clone_to_owned().ok()?);
+    let (held, wanted) = (file.metadata().ok()?, fs::metadata(path).ok()?);
+    if held.dev() != wanted.dev() || held.ino() != wanted.ino() {return None;}
+    file.try_lock().ok()?;
+    Some(file)
+}
+
+// ---------------------------------------------------------------------------
+// parse_iso8601:
+// ---------------------------------------------------------------------------
+/** Validate an ISO-8601 date (YYYY-MM-DD) or timestamp with an optional time
+ * separated by T or a space (HH:MM, optional :SS and fraction) and an 
+ * optional Z or +/-HH:MM offset.  The value is returned unchanged.
+ */
+fn parse_iso8601(s: &str) -> Result<String, String> {
+    let err = || format!("invalid ISO-8601 date or timestamp: {}", s);
+    let digits = |t: &str| !t.is_empty() && t.bytes().all(|b| b.is_ascii_digit());
+    let num = |t: &str| t.parse::<u32>().unwrap_or(u32::MAX);
+
+    // Check the date.
+    let date = s.get(..10).ok_or_else(err)?;
+    let parts: Vec<&str> = date.split('-').collect();
+    if parts.len() != 3 || parts[0].len() != 4 || parts[1].len() != 2 || parts[2].len() != 2 
+        || !parts.iter().all(|p| digits(p)) 
+        || !(1..=12).contains(&num(parts[1])) || !(1..=31).contains(&num(parts[2])) {
+        return Err(err());
+    }
+    let rest = &s[10..];
+    if rest.is_empty() {return Ok(s.to_string());}
+
+    // Check the time and offset.
+    let time = rest.strip_prefix('T').or_else(|| rest.strip_prefix(' ')).ok_or_else(err)?;
+    let (time, offset) = match time.find(['Z', '+', '-']) {
+        Some(i) => (&time[..i], &time[i..]),
+        None => (time, ""),
+    };
+    let (time, frac) = time.split_once('.').unwrap_or((time, "0"));
+    let hms: Vec<&str> = time.split(':').collect();
+    if !(2..=3).contains(&hms.len()) || !hms.iter().all(|p| p.len() == 2 && digits(p)) 
+        || num(hms[0]) > 23 || num(hms[1]) > 59 || (hms.len() == 3 && num(hms[2]) > 60) 
+        || !digits(frac) {
+        return Err(err());
+    }
+    if !offset.is_empty() && offset != "Z" {
+        let hm: Vec<&str> = offset[1..].split(':').collect();
+        if hm.len() != 2 || !hm.iter().all(|p| p.len() == 2 && digits(p)) 
+            || num(hm[0]) > 23 || num(hm[1]) > 59 {
+            return Err(err());
+        }
+    }
+    Ok(s.to_string())
+}
+
+// ---------------------------------------------------------------------------
+// parse_db_timezone:
+// ---------------------------------------------------------------------------
+/** Validate a --db-timezone, which is utc, local or a +/-HH:MM offset, and 
+ * return it in lower case.
+ */
+fn parse_db_timezone(s: &str) -> Result<String, String> {
+    let tz = s.to_ascii_lowercase();
+    if tz == "utc" || tz == "local" {return Ok(tz);}
+    let b = tz.as_bytes();
+    let valid = b.len() == 6 && (b[0] == b'+' || b[0] == b'-') && b[3] == b':' 
+        && [1, 2, 4, 5].iter().all(|i| b[*i].is_ascii_digit())
+        && tz[1..3].parse::<u32>().unwrap_or(99) <= 23 && tz[4..6].parse::<u32>().unwrap_or(99) <= 59;
+    if valid {Ok(tz)} else {Err(format!("invalid timezone {}, expected utc, local or +/-HH:MM", s))}
+}
+
+// ---------------------------------------------------------------------------
+// read_stdin_ids:
+// ---------------------------------------------------------------------------
+/** Read whitespace separated integer record ids from stdin.  An empty list 
+ * is rejected since it would otherwise select every record.
+ */
+fn read_stdin_ids() -> Vec<i64> {
+    let mut text = String::new();
+    if let Err(e) = io::stdin().read_to_string(&mut text) {
+        panic!("Unable to read ids from stdin: {}", e);
+    }
+    let ids: Vec<i64> = text.split_whitespace().map(|t| match t.parse() {
+        Ok(id) => id,
+        Err(_) => panic!("Invalid id read from stdin: {}", t),
+    }).collect();
+    if ids.is_empty() {
+        panic!("No ids were read from stdin.");
+    }
+    ids
+}
+
+// ---------------------------------------------------------------------------
+// parse_column:
+// ---------------------------------------------------------------------------
+/** Validate a column name, which must be a plain identifier so that it can be
+ * used in SQL without quoting.
+ */
+fn parse_column(s: &str) -> Result<String, String> {
+    if is_identifier(s) {Ok(s.to_string())} else {Err(format!("invalid column name: {}", s))}
+}
+
+// ---------------------------------------------------------------------------
+// parse_version:
+// ---------------------------------------------------------------------------
+/** Parse an sqlite version of the form major.minor or major.minor.patch. */
+fn parse_version(s: &str) -> Result<(u32, u32, u32), String> {
+    let parts: Vec<&str> = s.trim().split('.').collect();
+    let numbers: Option<Vec<u32>> = parts.iter().map(|p| p.parse().ok()).collect();
+    match numbers.as_deref() {
+        Some([major, minor]) => Ok((*major, *minor, 0)),
+        Some([major, minor, patch]) => Ok((*major, *minor, *patch)),
+        _ => Err(format!("expected a version such as 3.35.0: {}", s)),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// parse_limit:
+// ---------------------------------------------------------------------------
+/** Validate a record limit, which must not be negative. */
+fn parse_limit(s: &str) -> Result<i32, String> {
+    match s.parse::<i32>() {
+        Ok(n) if n >= 0 => Ok(n),
+        _ => Err(format!("limit must be a non-negative integer: {}", s)),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// parse_jobs:
+// ---------------------------------------------------------------------------
+/** Validate a --jobs count, which must be at least 1. */
+fn parse_jobs(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(n) if n >= 1 => Ok(n),
+        _ => Err(format!("jobs must be a positive integer: {}", s)),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// parse_pragma:
+// ---------------------------------------------------------------------------
+/** Parse a --set-pragma value of the form name=value, where the name is one
+ * of SET_PRAGMAS and the value is valid for it.
+ */
+fn parse_pragma(s: &str) -> Result<(String, String), String> {
+    let (name, value) = match s.split_once('=') {
+        Some((n, v)) => (n.trim().to_ascii_lowercase(), v.trim().to_ascii_uppercase()),
+        None => return Err(format!("expected name=value: {}", s)),
+    };
+    let valid = match name.as_str() {
+        "page_size" => value.parse::<u32>().is_ok_and(|n| n.is_power_of_two() && (512..=65536).contains(&n)),
+        "auto_vacuum" => ["NONE", "FULL", "INCREMENTAL", "0", "1", "2"].contains(&value.as_str()),
+        "journal_mode" => ["DELETE", "TRUNCATE", "PERSIST", "MEMORY", "WAL", "OFF"].contains(&value.as_str()),
+        "user_version" => value.parse::<i32>().is_ok(),
+        _ => return Err(format!("unsupported pragma {}, expected one of: {}", name, SET_PRAGMAS.join(", "))),
+    };
+    if !valid {return Err(format!("invalid value for {}: {}", name, value));}
+    Ok((name, value))
+}
+
+// ---------------------------------------------------------------------------
+// parse_template:
+// ---------------------------------------------------------------------------
+/** Validate the syntax of a --template, which is returned unchanged. */
+fn parse_template(s: &str) -> Result<String, String> {
+    template_parts(s).map(|_| s.to_string())
+}
+
+// ---------------------------------------------------------------------------
+// template_parts:
+// ---------------------------------------------------------------------------
+/** Split a --template into its literal text and {column} placeholders, where
+ * {{ and }} stand for literal braces.
+ */
+fn template_parts(template: &str) -> Result<Vec<TemplatePart>, String> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '}' if chars.peek() == Some(&c) => {
+                chars.next();
+                text.push(c);
+            },
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(format!("unclosed placeholder in template: {}", template)),
+                    }
+                }
+                if name.trim().is_empty() {return Err(format!("empty placeholder in template: {}", template));}
+                if !text.is_empty() {parts.push(TemplatePart::Text(std::mem::take(&mut text)));}
+                parts.push(TemplatePart::Column(name.trim().to_string()));
+            },
+            '}' => return Err(format!("unmatched }} in template: {}", template)),
+            c => text.push(c),
+        }
+    }
+    if !text.is_empty() {parts.push(TemplatePart::Text(text));}
+    Ok(parts)
+}
+
+// ---------------------------------------------------------------------------
+// check_template_columns:
+// ---------------------------------------------------------------------------
+/** Check that each --template placeholder names a column of the records the
+ * resource's listing returns, which are the table's columns less any 
+ * --columns-exclude ones, plus the rowid and joined client columns when 
+ * they're requested.
+ */
+fn check_template_columns(resource: &TmsResource, template: &str) {
+    let mut columns: Vec<String> = included_columns(resource.table()).into_iter().map(|(c, _)| c).collect();
+    if TMSADM_ARGS.with_rowid {columns.insert(0, "rowid".to_string());}
+    if TMSADM_ARGS.join_clients {
+        columns.extend(["client_app_name", "client_app_version", "client_enabled"].map(String::from));
+    }
+    let parts = template_parts(template).unwrap_or_default();
+    for part in &parts {
+        if let TemplatePart::Column(c) = part {
+            if !columns.contains(c) {
+                panic!("The --template placeholder {{{}}} doesn't name a column of the {} records: {}", 
+                       c, resource.table(), columns.join(", "));
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// parse_col_transform:
+// ---------------------------------------------------------------------------
+/** Parse a --col-transform value of the form column=transform, where the 
+ * transform is fingerprint, localtime, truncate:N or redact.
+ */
+fn parse_col_transform(s: &str) -> Result<(String, ColTransform), String> {
+    let (column, transform) = match s.split_once('=') {
+        Some((c, t)) => (parse_column(c.trim())?, t.trim().to_ascii_lowercase()),
+        None => return Err(format!("expected column=transform: {}", s)),
+    };
+    let transform = match transform.split_once(':') {
+        Some(("truncate", n)) => match n.parse::<usize>() {
+            Ok(n) => ColTransform::truncate(n),
+            Err(_) => return Err(format!("invalid truncate width: {}", n)),
+        },
+        None if transform == "fingerprint" => ColTransform::fingerprint,
+        None if transform == "localtime" => ColTransform::localtime,
+        None if transform == "redact" => ColTransform::redact,
+        _ => return Err(format!("unknown transform {}, expected one of: fingerprint, localtime, truncate:N, redact", 
+                                transform)),
+    };
+    Ok((column, transform))
+}
+
+// ---------------------------------------------------------------------------
+// parse_attach:
+// ---------------------------------------------------------------------------
+/** Parse an --attach value of the form name=path into the schema name and
+ * the database's absolute path.  The database must already exist, since
+ * sqlite3 would otherwise create an empty one.
+ */
+fn parse_attach(s: &str) -> Result<(String, String), String> {
+    let (alias, path) = match s.split_once('=') {
+        Some((a, p)) if !p.is_empty() => (a, p),
+        _ => return Err(format!("expected name=path: {}", s)),
+    };
+    if !is_identifier(alias) {
+        return Err(format!("invalid attached database name: {}", alias));
+    }
+    if ["main", "temp"].contains(&alias.to_ascii_lowercase().as_str()) {
+        return Err(format!("reserved database name: {}", alias));
+    }
+    let path = get_absolute_path(path);
+    if !Path::new(&path).is_file() {
+        return Err(format!("attached database file does not exist: {}", path));
+    }
+    Ok((alias.to_string(), path))
+}
+
+// ---------------------------------------------------------------------------
+// parse_separator:
+// ---------------------------------------------------------------------------
+/** Validate a column separator, which is a single character or one of the
+ * escapes \t and \\.  The separator is returned with any escape resolved.
+ */
+fn parse_separator(s: &str) -> Result<String, String> {
+    match s {
+        "\\t" => Ok("\t".to_string()),
+        "\\\\" => Ok("\\".to_string()),
+        _ if s.chars().count() == 1 => Ok(s.to_string()),
+        _ => Err(format!("separator must be a single character, \\t or \\\\: {}", s)),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// get_resource:
+// ---------------------------------------------------------------------------
+/** Return the resource argument, which is required for operations on a 
+ * single table.
+ */
+fn get_resource() -> &'static TmsResource {
+    match current_resource() {
+        Some(r) => r,
+        None => panic!("The --resource option is required for the {:?} operation.", 
+                       get_operation()),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// current_resource:
+// ---------------------------------------------------------------------------
+/** Return the resource being processed, which is the first --resource unless
+ * the operation is running on each of several in turn.
+ */
+fn current_resource() -> Option<&'static TmsResource> {
+    TMSADM_ARGS.resource.get(RESOURCE_INDEX.load(Ordering::Relaxed))
+}
+
+// ---------------------------------------------------------------------------
+// get_operation:
+// ---------------------------------------------------------------------------
+/** Return the operation argument, which the argument parser requires unless 
+ * an informational mode is selected.
+ */
+fn get_operation() -> &'static TmsOperation {
+    match &TMSADM_ARGS.operation {
+        Some(o) => o,
+        None => panic!("The --operation option is required."),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// lint_sqlwhere:
+// ---------------------------------------------------------------------------
+/** Warn about names in the --sqlwhere clauses that aren't columns of the 
+ * table, suggesting the closest column.  This is a best-effort check that 
+ * skips string literals, SQL keywords, function names, qualified names and 
+ * the tables named after FROM or JOIN, so it only ever warns.  The clauses
+ * are validated first so that a rejected clause isn't linted, and with 
+ * --no-warnings they're only validated.
+ */
+fn lint_sqlwhere(table: &str) {
+    let columns: Vec<String> = column_types(table).into_iter().map(|(name, _)| name).collect();
+    if columns.is_empty() {return;}
+    let mut unknown: Vec<String> = Vec::new();
+    for clause in &sqlwhere_clauses() {
+        check_sqlwhere(clause);
+        if TMSADM_ARGS.no_warnings {continue;}
+        for name in sqlwhere_names(clause) {
+            let known = columns.iter().any(|c| c.eq_ignore_ascii_case(&name)) 
+                || ["rowid", "oid", "_rowid_"].contains(&name.to_ascii_lowercase().as_str());
+            if !known && !unknown.contains(&name) {unknown.push(name);}
+        }
+    }
+    for name in unknown {
+        let closest = columns.iter().min_by_key(|c| levenshtein(&name.to_ascii_lowercase(), c));
+        match closest {
+            Some(c) if levenshtein(&name.to_ascii_lowercase(), c) <= 2.max(c.len() / 3) => 
+                eprintln!("Warning: --sqlwhere refers to {}, which isn't a column of {}; did you mean {}?", 
+                          name, table, c),
+            _ => eprintln!("Warning: --sqlwhere refers to {}, which isn't a column of {}.", name, table),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// sqlwhere_names:
+// ---------------------------------------------------------------------------
+/** Return the names in a WHERE clause that could be column references. */
+fn sqlwhere_names(clause: &str) -> Vec<String> {
+    const KEYWORDS: &[&str] = &["where", "and", "or", "not", "null", "is", "in", "like", "glob", 
+        "regexp", "match", "between", "escape", "collate", "nocase", "rtrim", "binary", "select", 
+        "from", "join", "left", "inner", "cross", "outer", "on", "using", "as", "case", "when", 
+        "then", "else", "end", "exists", "distinct", "all", "true", "false", "order", "group", 
+        "by", "having", "limit", "offset", "asc", "desc", "cast", "integer", "int", "text", 
+        "real", "numeric", "blob", "current_timestamp", "current_date", "current_time", "union", 
+        "except", "intersect", "isnull", "notnull"];
+    let tokens = sql_tokens(clause);
+    let mut names = Vec::new();
+    let mut after_from = false;
+    for (i, (_, token)) in tokens.iter().enumerate() {
+        let (name, quoted) = match token {
+            SqlToken::Quoted {name, ..} => (name, true),
+            SqlToken::Word(name) => (name, false),
+            _ => continue,
+        };
+
+        // Skip keywords, function calls, qualified names and table names.
+        let is_symbol = |t: Option<&(usize, SqlToken)>, symbol: &str| 
+            matches!(t, Some((_, SqlToken::Symbol(s))) if s == symbol);
+        let (prev, next) = (i.checked_sub(1).and_then(|p| tokens.get(p)), tokens.get(i + 1));
+        let lower = name.to_ascii_lowercase();
+        let qualified = is_symbol(prev, ".") || is_symbol(next, ".");
+        let keyword = !quoted && KEYWORDS.contains(&lower.as_str());
+        if !keyword && !qualified && !is_symbol(next, "(") && !after_from {names.push(name.clone());}
+        after_from = !quoted && (lower == "from" || lower == "join");
+    }
+    names
+}
+
+// ---------------------------------------------------------------------------
+// levenshtein:
+// ---------------------------------------------------------------------------
+/** Return the edit distance between two strings. */
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb {prev} else {prev + 1};
+            prev = row[j + 1];
+            row[j + 1] = cost.min(row[j] + 1).min(prev + 1);
+        }
+    }
+    row[b.len()]
+}
+
+// ---------------------------------------------------------------------------
+// is_identifier:
+// ---------------------------------------------------------------------------
+/** Determine whether the name is a plain SQL identifier that can be safely
+ * used without quoting.
+ */
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {},
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// ---------------------------------------------------------------------------
+// sql_quote:
+// ---------------------------------------------------------------------------
+/** Return the value as a single quoted SQL string literal.  Embedded single
+ * quotes are escaped by doubling them. 
+ */
+fn sql_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+// ---------------------------------------------------------------------------
+// confirm_records:
+// ---------------------------------------------------------------------------
+/** Prompt user to confirm the action on the records just listed. */
+fn confirm_records(action: &str) -> bool {
+    confirm(&format!("Confirm {} of the above listed records", action))
+}
+
+// ---------------------------------------------------------------------------
+// confirm:
+// ---------------------------------------------------------------------------
+/** Ask the user a yes or no question, returning true for yes.  The answer is
+ * read from stdin, or from the terminal when stdin supplied the ids, or from
+ * the --confirm-from file, where a missing or empty file means no.
+ */
+fn confirm(question: &str) -> bool {
+    // Prompt user for confirmation.
+    println!("\n{} (y/n):", question);
+    if let Some(path) = &TMSADM_ARGS.confirm_from {
+        let path = get_absolute_path(path);
+        let answer = fs::read_to_string(&path).unwrap_or_default();
+        let answer = answer.trim();
+        if answer.is_empty() {
+            println!("No answer in confirmation file {}.", path);
+            return false;
+        }
+        println!("{} (from {})", answer, path);
+        return answer.to_lowercase().starts_with('y');
+    }
+
+    // Collect response, waiting at most --prompt-timeout seconds for it.
+    let read = || {
+        let mut input = String::new();
+        let response = if TMSADM_ARGS.ids_stdin {
+            File::open("/dev/tty").and_then(|tty| BufReader::new(tty).read_line(&mut input))
+                .map_err(|e| io::Error::new(e.kind(), format!("unable to read the terminal, \
+                         use --confirm-delete-off with --ids-stdin: {}", e)))
+        } else {
+            io::stdin().read_line(&mut input)
+        };
+        response.map(|_| input)
+    };
+    let response = match TMSADM_ARGS.prompt_timeout {
+        Some(secs) => {
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || sender.send(read()));
+            match receiver.recv_timeout(Duration::from_secs(secs)) {
+                Ok(response) => response,
+                Err(_) => {
+                    println!("No response within {} seconds, nothing changed.", secs);
+                    exit(EXIT_PROMPT_TIMEOUT);
+                },
+            }
+        },
+        None => read(),
+    };
+    match response {
+        Ok(input) => input.to_lowercase().starts_with('y'),
+        Err(e) => {
+            println!("Nothing changed.");
+            panic!("error: {}", e)
+        },
+    }
+}
+
+// ---------------------------------------------------------------------------
+// record_affected:
+// ---------------------------------------------------------------------------
+/** Record the number of records changed by a mutation for --summary-json, 
+ * reporting it on stderr with the none format.  With the json format, a 
+ * result object giving the operation, resource and count is written to 
+ * stdout, so that callers can parse the result of any operation.  Modes that
+ * span several tables report a null resource.
+ */
+fn record_affected(count: u64) {
+    if TMSADM_ARGS.format == TmsFormat::none {eprintln!("{} record(s) changed", count);}
+    if TMSADM_ARGS.format == TmsFormat::json && !TMSADM_ARGS.json_off {
+        let spans_tables = TMSADM_ARGS.purge_user.is_some() || TMSADM_ARGS.apply_retention.is_some() 
+            || TMSADM_ARGS.purge_soft_deleted_before.is_some();
+        let resource = current_resource().filter(|_| !spans_tables).map(|r| format!("{:?}", r));
+        let result = serde_json::json!({
+            "operation": TMSADM_ARGS.operation.as_ref().map(TmsOperation::name),
+            "resource": resource,
+            "affected": count,
+        });
+        println!("{}", result);
+    }
+    *AFFECTED.lock().unwrap_or_else(|e| e.into_inner()) = Some(count);
+    AFFECTED_TOTAL.fetch_add(count, Ordering::Relaxed);
+}
+
+// ---------------------------------------------------------------------------
+// check_records_deleted:
+// ---------------------------------------------------------------------------
+/** Exit with EXIT_NO_DELETES if --require-deletes is set and the DELETE 
+ * operation just completed, other than a dry run, deleted no records, 
+ * including when the deletion wasn't confirmed.
+ */
+fn check_records_deleted() {
+    if TMSADM_ARGS.require_deletes && !TMSADM_ARGS.dry_run && AFFECTED_TOTAL.load(Ordering::Relaxed) == 0 {
+        eprintln!("No records were deleted (--require-deletes).");
+        exit(EXIT_NO_DELETES);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// exit:
+// ---------------------------------------------------------------------------
+/** Write the --summary-json summary, and with --errors-json the error, and 
+ * exit with the code.
+ */
+fn exit(code: i32) -> ! {
+    if code != 0 && TMSADM_ARGS.errors_json {
+        match code {
+            EXIT_EMPTY => write_error_json(code, "empty", "no rows were returned"),
+            EXIT_ROWS => write_error_json(code, "rows", "rows were returned"),
+            EXIT_PROMPT_TIMEOUT => write_error_json(code, "timeout", "no response to the confirmation prompt"),
+            EXIT_UNHEALTHY => write_error_json(code, "unhealthy", "the database can't be read"),
+            EXIT_NO_DELETES => write_error_json(code, "no-deletes", "no records were deleted"),
+            _ => write_error_json(code, "failed", "problems were found, see the output"),
+        }
+    }
+    remove_key_file();
+    write_summary(code);
+    write_count_file();
+    std::process::exit(code);
+}
+
+// ---------------------------------------------------------------------------
+// errors_json_requested:
+// ---------------------------------------------------------------------------
+/** Return true if --errors-json is on the command line, which is checked 
+ * before, and in case of, the arguments failing to parse.
+ */
+fn errors_json_requested() -> bool {
+    std::env::args_os().any(|a| a == "--errors-json")
+}
+
+// ---------------------------------------------------------------------------
+// install_error_hook:
+// ---------------------------------------------------------------------------
+/** Replace the panic message with a json error on stderr, whose kind is
+ * "database" for failed sqlite3 commands and "error" for everything else.
+ * Panics exit with code 101.
+ */
+fn install_error_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        let payload = info.payload();
+        let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown error".to_string());
+        let kind = if SQLITE_FAILED.load(Ordering::Relaxed) {"database"} else {"error"};
+        write_error_json(101, kind, &message);
+    }));
+}
+
+// ---------------------------------------------------------------------------
+// write_error_json:
+// ---------------------------------------------------------------------------
+/** Write an error to stderr as a json object with its exit code, kind and 
+ * message.
+ */
+fn write_error_json(code: i32, kind: &str, message: &str) {
+    let error = serde_json::json!({"code": code, "kind": kind, "message": message.trim()});
+    eprintln!("{}", error);
+}
+
+// ---------------------------------------------------------------------------
+// write_summary:
+// ---------------------------------------------------------------------------
+/** With --summary-json, write a single json object describing the run to
+ * stderr, so that it doesn't mix with the results on stdout.  Affected is 
+ * null unless a mutation reported how many records it changed.
+ */
+fn write_summary(exit: i32) {
+    if !TMSADM_ARGS.summary_json {return;}
+    let summary = serde_json::json!({
+        "op": TMSADM_ARGS.operation.as_ref().map(|o| format!("{:?}", o)),
+        "resource": (!TMSADM_ARGS.resource.is_empty())
+            .then(|| TMSADM_ARGS.resource.iter().map(TmsResource::table).collect::<Vec<_>>().join(",")),
+        "rows": ROW_COUNT.load(Ordering::Relaxed),
+        "affected": *AFFECTED.lock().unwrap_or_else(|e| e.into_inner()),
+        "elapsed_ms": START.elapsed().as_millis() as u64,
+        "exit": exit,
+    });
+    eprintln!("{}", summary);
+}
+
+// ---------------------------------------------------------------------------
+// SummaryGuard:
+// ---------------------------------------------------------------------------
+/** Writes the --summary-json summary when main returns or panics, and the 
+ * --count-file when it returns, after removing the --key file.  Runs that 
+ * end with exit() do the same themselves.
+ */
+struct SummaryGuard;
+
+impl SummaryGuard {
+    fn new() -> SummaryGuard {
+        lazy_static::initialize(&START);
+        SummaryGuard
+    }
+}
+
+impl Drop for SummaryGuard {
+    fn drop(&mut self) {
+        remove_key_file();
+        write_summary(if thread::panicking() {101} else {0});
+        if !thread::panicking() {write_count_file();}
+    }
+}
+
+// ---------------------------------------------------------------------------
+// write_count_file:
+// ---------------------------------------------------------------------------
+/** Write the number of rows returned by LIST, EXPORT or REPORT, or else the
+ * number of records changed, to the --count-file.  The count is written to a
+ * temporary file that then replaces the count file, so a monitor never reads
+ * a partial count.  The operation has already finished, so a failure to 
+ * write the file is reported rather than treated as fatal.
+ */
+fn write_count_file() {
+    let path = match &TMSADM_ARGS.count_file {
+        Some(p) => get_absolute_path(p),
+        None => return,
+    };
+    let count = match TMSADM_ARGS.operation {
+        Some(TmsOperation::LIST | TmsOperation::EXPORT | TmsOperation::REPORT) => ROW_COUNT.load(Ordering::Relaxed),
+        _ => AFFECTED.lock().unwrap_or_else(|e| e.into_inner()).unwrap_or(0),
+    };
+    let temp = path.clone() + ".tmp";
+    if let Err(e) = fs::write(&temp, format!("{}\n", count)).and_then(|_| fs::rename(&temp, &path)) {
+        eprintln!("Unable to write count file {}: {}", path, e);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// db_path:
+// ---------------------------------------------------------------------------
+/** Return the database name passed to sqlite3.  An sqlite URI filename, which
+ * starts with "file:", is passed as is so that its query parameters, such as
+ * mode=ro, are kept.  Sqlite3 accepts URI filenames without any option. 
+ * Other paths are made absolute.
+ */
+fn db_path() -> String {
+    if is_uri(&TMSADM_ARGS.dbpath) {TMSADM_ARGS.dbpath.clone()} 
+        else {get_absolute_path(&TMSADM_ARGS.dbpath)}
+}
+
+// ---------------------------------------------------------------------------
+// db_file_path:
+// ---------------------------------------------------------------------------
+/** Return the absolute path of the database file, which is the path used to
+ * check, lock and back up the file.  See file_path.
+ */
+fn db_file_path() -> String {
+    file_path(&TMSADM_ARGS.dbpath)
+}
+
+// ---------------------------------------------------------------------------
+// file_path:
+// ---------------------------------------------------------------------------
+/** Return the absolute path of the file that a database name refers to, 
+ * which for an sqlite URI filename is its path portion without the 
+ * authority, query string or fragment, and with any %-escapes decoded.
+ */
+fn file_path(dbpath: &str) -> String {
+    if !is_uri(dbpath) {return get_absolute_path(dbpath);}
+    let mut path = &dbpath["file:".len()..];
+    path = &path[..path.find(['?', '#']).unwrap_or(path.len())];
+    if let Some(rest) = path.strip_prefix("//") {
+        path = &rest[rest.find('/').unwrap_or(rest.len())..];
+    }
+
+    // Decode %HH escapes, leaving any malformed ones alone.
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {decoded.push(b); i += 3;},
+            (c, _) => {decoded.push(c); i += 1;},
+        }
+    }
+    get_absolute_path(&String::from_utf8_lossy(&decoded))
+}
+
+// ---------------------------------------------------------------------------
+// is_uri:
+// ---------------------------------------------------------------------------
+/** Return true if the database path is an sqlite URI filename. */
+fn is_uri(path: &str) -> bool {
+    path.starts_with("file:")
+}
+
+// ---------------------------------------------------------------------------
+// get_absolute_path:
+// ---------------------------------------------------------------------------
+/** Replace tilde (~) and environment variable values in a path name and
+ * then construct the absolute path name.  The difference between 
+ * absolutize and standard canonicalize methods is that absolutize does not 
+ * care about whether the file exists and what the file really is.
+ * 
  * Here's a short version of how canonicalize would be used: 
  * 
  *   let p = shellexpand::full(path).unwrap();
@@ -338,77 +6769,1643 @@ fn confirm_delete() -> bool {
  *   path.to_owned()
  *   path.deref().to_string()
  * 
- * I went with the former on a hunch that it's the most appropriate, happy
- * to change if my guess is wrong.
+ * I went with the former on a hunch that it's the most appropriate, happy
+ * to change if my guess is wrong.
+ * 
+ * A path that references an undefined environment variable causes a panic
+ * naming the variable, since silently using the literal path only leads to
+ * a confusing file-not-found error later.
+ * 
+ * With --no-absolutize, the expanded path is returned as is, so relative 
+ * paths are resolved by whatever opens them.
+ */
+fn get_absolute_path(path: &str) -> String {
+    // Replace ~ and environment variable values.
+    let s = match shellexpand::full(path) {
+        Ok(x) => x,
+        Err(e) => panic!("Undefined variable {} in path {}: {}", e.var_name, path, e.cause),
+    };
+    if *NO_ABSOLUTIZE {return s.into_owned();}
+
+    // Convert to absolute path if necessary.
+    // Return original input on error.
+    let p = Path::new(s.deref());
+    let p1 = match p.absolutize() {
+        Ok(x) => x,
+        Err(_) => return path.to_owned(),
+    };
+    let p2 = match p1.to_str() {
+        Some(x) => x,
+        None => return path.to_owned(),
+    };
+
+    p2.to_owned()
+}
+
+// ---------------------------------------------------------------------------
+// run_command:
+// ---------------------------------------------------------------------------
+/** Make an operating system call and stream its stdout to our stdout one
+ * line at a time.  See run_command_to for details.
+ */
+fn run_command(command: Command, task: &str, query: bool) {
+    with_output(|out| {
+        if !TMSADM_ARGS.timestamp_output {
+            run_command_to(command, task, query, out);
+            return Ok(());
+        }
+
+        // Prefixed lines would corrupt json, which gets a single wrapper.
+        let json = matches!(TMSADM_ARGS.format, TmsFormat::json | TmsFormat::json_typed) && !TMSADM_ARGS.json_off;
+        if json {write!(out, "{{\"timestamp\":\"{}\",\"result\":", utc_timestamp())?;}
+        let mut w = TimestampWriter {inner: out, stamp_lines: !json, line_start: true, written: false};
+        run_command_to(command, task, query, &mut w);
+        if json {
+            if !w.written {write!(out, "null")?;}
+            writeln!(out, "}}")?;
+        }
+        Ok(())
+    });
+}
+
+// ---------------------------------------------------------------------------
+// utc_timestamp:
+// ---------------------------------------------------------------------------
+/** Return the current time in UTC as an ISO-8601 timestamp with seconds. */
+fn utc_timestamp() -> String {
+    format_utc(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()))
+}
+
+// ---------------------------------------------------------------------------
+// format_utc:
+// ---------------------------------------------------------------------------
+/** Return the seconds since the epoch as an ISO-8601 UTC timestamp. */
+fn format_utc(secs: u64) -> String {
+    let (days, time) = (secs / 86400, secs % 86400);
+
+    // Convert the days since the epoch to a civil date.
+    let z = days + 719468;
+    let (era, doe) = (z / 146097, z % 146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 {mp + 3} else {mp - 9};
+    let year = yoe + era * 400 + (month <= 2) as u64;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+// ---------------------------------------------------------------------------
+// open_output_file:
+// ---------------------------------------------------------------------------
+/** Create the --output file that the results of a LIST or REPORT operation 
+ * are written to instead of stdout.  An existing file is only overwritten if
+ * --force is set.
+ */
+fn open_output_file() {
+    let path = match &TMSADM_ARGS.output {
+        Some(p) => get_absolute_path(p),
+        None => return,
+    };
+    if Path::new(&path).exists() && !TMSADM_ARGS.force {
+        panic!("Output file already exists: {} (use --force to overwrite)", path);
+    }
+    match File::create(&path) {
+        Ok(f) => *OUTPUT_FILE.lock().unwrap() = Some(f),
+        Err(e) => panic!("Unable to create output file {}: {}", path, e),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// open_tee_file:
+// ---------------------------------------------------------------------------
+/** Create the --tee file that gets a copy of the results written to stdout.
+ * An existing file is only overwritten if --force is set.
+ */
+fn open_tee_file() {
+    let path = match &TMSADM_ARGS.tee {
+        Some(p) => get_absolute_path(p),
+        None => return,
+    };
+    if Path::new(&path).exists() && !TMSADM_ARGS.force {
+        panic!("Tee file already exists: {} (use --force to overwrite)", path);
+    }
+    match File::create(&path) {
+        Ok(f) => *TEE_FILE.lock().unwrap() = Some(f),
+        Err(e) => panic!("Unable to create tee file {}: {}", path, e),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// with_output:
+// ---------------------------------------------------------------------------
+/** Pass the --output file, if one is open, or stdout to the function and 
+ * flush what it writes.  What's written to stdout is also written to the
+ * --tee file, if one is open.  A reader that went away, such as head, isn't
+ * an error.
+ * 
+ * This function panics if the output can't be written.
+ */
+fn with_output(f: impl FnOnce(&mut dyn Write) -> io::Result<()>) {
+    let mut file = OUTPUT_FILE.lock().unwrap();
+    let result = match file.as_mut() {
+        Some(file) => {
+            let mut w = BufWriter::new(file);
+            f(&mut w).and_then(|_| w.flush())
+        },
+        None => {
+            let stdout = io::stdout();
+            let mut out = stdout.lock();
+            match TEE_FILE.lock().unwrap().as_mut() {
+                Some(tee) => {
+                    let mut copy = BufWriter::new(tee);
+                    let mut w = TeeWriter {first: &mut out, second: &mut copy};
+                    f(&mut w).and_then(|_| w.flush())
+                },
+                None => f(&mut out).and_then(|_| out.flush()),
+            }
+        },
+    };
+    if let Err(e) = result {
+        if e.kind() == io::ErrorKind::BrokenPipe {return;}
+        match &TMSADM_ARGS.output {
+            Some(p) if file.is_some() => panic!("Unable to write output file {}: {}", get_absolute_path(p), e),
+            _ => panic!("Unable to write output: {}", e),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// run_command_to:
+// ---------------------------------------------------------------------------
+/** Make an operating system call and stream its stdout to the writer one
+ * line at a time.  Output is never collected into a single buffer, so a LIST
+ * over a very large table runs in bounded memory.  Stderr is drained on a
+ * separate thread so that a chatty child can't block on a full pipe while
+ * we're still reading stdout.
+ * 
+ * The task parameter prefixes any error message logged or returned by this
+ * function.  The query parameter indicates that the command returns rows, in 
+ * which case a json result with no rows is written as an empty array rather
+ * than as nothing at all, so downstream json parsers always get valid input.
+ * 
+ * This function panics if the command cannot be run or if it returns a
+ * non-zero exit code.
+ */
+fn run_command_to(mut command: Command, task: &str, query: bool, out: &mut dyn Write) {
+    // Show the exact invocation when debugging.
+    if TMSADM_ARGS.debug {
+        println!("*** {} command *** \n{}\n", task, format_command(&command));
+    }
+
+    // The json-typed format describes the resource's columns.
+    let columns = match (&TMSADM_ARGS.format, output_table()) {
+        (TmsFormat::json_typed, Some(t)) => included_columns(&t),
+        _ => Vec::new(),
+    };
+
+    // Sqlite3 writes one line per row, after any echoed SQL and, outside of
+    // the json and insert modes, a header line.  It writes nothing else, not
+    // even headers, for an empty result.
+    let args: Vec<_> = command.get_args().collect();
+    let (echo_bytes, echo_lines) = match args.last() {
+        Some(sql) if args.contains(&OsStr::new("-echo")) => {
+            let sql = sql.to_string_lossy();
+            (sql.len() + 1, sql.matches('\n').count() + 1)
+        },
+        _ => (0, 0),
+    };
+    let header = args.contains(&OsStr::new("-header")) && !args.contains(&OsStr::new("-json")) 
+        && TMSADM_ARGS.format != TmsFormat::sql_insert;
+
+    // Stream stdout to the writer.
+    let mut counts = ReadCounts::default();
+    let result = run_sqlite3(command_runner(), &mut command, task, None, &mut |reader| {
+        let mut reader = CountingReader {inner: reader, counts: &mut counts};
+        let reader = &mut reader;
+        if TMSADM_ARGS.format == TmsFormat::none {
+            io::copy(reader, &mut io::sink()).map(|_| ())
+        } else if TMSADM_ARGS.fingerprint {
+            stream_fingerprints(reader, out, echo_lines)
+        } else if !TMSADM_ARGS.col_transform.is_empty() || TMSADM_ARGS.sanitize {
+            stream_col_transforms(reader, out, echo_lines)
+        } else if TMSADM_ARGS.format == TmsFormat::ndjson {
+            stream_ndjson(reader, out)
+        } else if TMSADM_ARGS.format == TmsFormat::yaml {
+            stream_yaml(reader, out)
+        } else if TMSADM_ARGS.format == TmsFormat::authorized_keys {
+            stream_authorized_keys(reader, out)
+        } else if TMSADM_ARGS.format == TmsFormat::template {
+            stream_template(reader, out)
+        } else if TMSADM_ARGS.format == TmsFormat::json_typed {
+            stream_json_typed(reader, out, &columns)
+        } else if TMSADM_ARGS.format == TmsFormat::sql_insert || TMSADM_ARGS.format == TmsFormat::tsv {
+            stream_lines(reader, out).map(|_| ())
+        } else if TMSADM_ARGS.json_off && TMSADM_ARGS.max_width.is_some() {
+            stream_truncated_list(reader, out)
+        } else if query && !TMSADM_ARGS.json_off && (TMSADM_ARGS.normalize_json || TMSADM_ARGS.compact_json) {
+            stream_json_rows(reader, out)
+        } else if query && !TMSADM_ARGS.json_off {
+            stream_json(reader, out)
+        } else {
+            stream_lines(reader, out).map(|_| ())
+        }
+    });
+    let rows = if counts.bytes > echo_bytes {
+        counts.lines.saturating_sub(echo_lines + header as usize)
+    } else {0};
+    ROW_COUNT.fetch_add(rows as u64, Ordering::Relaxed);
+    if TMSADM_ARGS.format == TmsFormat::none && query {eprintln!("{}: {} row(s)", task, rows);}
+
+    // A reader that went away, such as head, isn't an error.
+    if let Err(e) = result {
+        if e.kind() == io::ErrorKind::BrokenPipe {return;}
+        let msg = task.to_string() + ": " + &e.to_string();
+        panic!("{}", msg);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// run_capture:
+// ---------------------------------------------------------------------------
+/** Run the SQL with sqlite3's plain output format and return its stdout.
+ * This is used for internal queries whose results are consumed by tmsadm
+ * rather than shown to the user, so none of the output options apply.
+ * 
+ * This function panics if the command cannot be run or if it returns a
+ * non-zero exit code.
+ */
+fn run_capture(sql: &str, task: &str) -> String {
+    let mut command = new_sqlite3_cmd();
+    command.arg(db_path());
+    command.arg(sql);
+    if TMSADM_ARGS.debug {
+        println!("*** {} command *** \n{}\n", task, format_command(&command));
+    }
+
+    let mut stdout = Vec::new();
+    if let Err(e) = run_sqlite3(command_runner(), &mut command, task, None, &mut |reader| reader.read_to_end(&mut stdout).map(|_| ())) {
+        let msg = task.to_string() + ": " + &e.to_string();
+        panic!("{}", msg);
+    }
+    String::from_utf8_lossy(&stdout).into_owned()
+}
+
+// ---------------------------------------------------------------------------
+// query_rows:
+// ---------------------------------------------------------------------------
+/** Run the query with sqlite3's json output format and pass each row to the 
+ * callback as it's read, so that large result sets aren't buffered.  The
+ * user's output options don't apply since the rows are consumed internally.
+ * 
+ * This function panics if the command cannot be run, if it returns a non-zero
+ * exit code or if a row can't be parsed.
+ */
+fn query_rows(sql: &str, task: &str, mut f: impl FnMut(Map<String, Value>)) {
+    let mut command = new_sqlite3_cmd();
+    command.arg("-json");
+    command.arg(db_path());
+    command.arg(sql);
+    if TMSADM_ARGS.debug {
+        println!("*** {} command *** \n{}\n", task, format_command(&command));
+    }
+
+    // Parse and hand off each row.
+    let result = run_sqlite3(command_runner(), &mut command, task, None, &mut |reader| {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {break;}
+            if let Some(row) = json_row(&line) {
+                let row = serde_json::from_str(row).map_err(|e| 
+                    io::Error::new(io::ErrorKind::InvalidData, format!("unable to parse row: {}", e)))?;
+                f(row);
+            }
+        }
+        Ok(())
+    });
+    if let Err(e) = result {
+        let msg = task.to_string() + ": " + &e.to_string();
+        panic!("{}", msg);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// run_script:
+// ---------------------------------------------------------------------------
+/** Run an SQL script by writing it to sqlite3's stdin and return sqlite3's 
+ * stdout.  Scripts are used when the SQL could exceed the operating system's
+ * limit on the length of a command line argument.  The script is written on
+ * a separate thread so that sqlite3 can't block on a full stdout pipe while
+ * we're still writing.
+ * 
+ * This function panics if the command cannot be run or if it returns a
+ * non-zero exit code.
+ */
+fn run_script(script: &str, task: &str) -> String {
+    let mut command = new_sqlite3_cmd();
+    command.arg(db_path());
+    if TMSADM_ARGS.debug {
+        println!("*** {} command *** \n{} < script\n", task, format_command(&command));
+    }
+
+    let mut stdout = Vec::new();
+    if let Err(e) = run_sqlite3(command_runner(), &mut command, task, Some(script), &mut |reader| reader.read_to_end(&mut stdout).map(|_| ())) {
+        let msg = task.to_string() + ": " + &e.to_string();
+        panic!("{}", msg);
+    }
+    String::from_utf8_lossy(&stdout).into_owned()
+}
+
+// ---------------------------------------------------------------------------
+// run_sqlite3:
+// ---------------------------------------------------------------------------
+/** Run the command with the command runner, passing its stdout to
+ * the reader.  The input, if any, is written to the command's stdin.  With 
+ * --trace, the time taken is written to stderr.  An error is returned if the
+ * command can't be run or the reader fails.
+ * 
+ * With --retries, a command that fails because the database is locked or 
+ * busy is run again after a delay that doubles each time, starting at 100ms.
+ * A command is only retried if it wrote nothing to stdout, so the reader 
+ * never sees a partial result twice.
+ * 
+ * This function panics if the command returns a non-zero exit code.
+ */
+fn run_sqlite3(runner: &dyn CommandRunner, command: &mut Command, task: &str, input: Option<&str>, 
+               reader: &mut dyn FnMut(&mut dyn BufRead) -> io::Result<()>) -> io::Result<()> {
+    let mut attempt = 0;
+    loop {
+        let start = Instant::now();
+        let mut counts = ReadCounts::default();
+        let output = runner.run(command, input, &mut |r| {
+            reader(&mut CountingReader {inner: r, counts: &mut counts})
+        });
+        if TMSADM_ARGS.trace {
+            eprintln!("executed {} in {}ms", task, start.elapsed().as_millis());
+        }
+        let output = output?;
+        if output.status.success() {return Ok(());}
+
+        // Retry transient failures.
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if attempt < TMSADM_ARGS.retries && counts.bytes == 0 && is_transient(&stderr) {
+            attempt += 1;
+            let delay = 100u64 << (attempt - 1).min(16);
+            eprintln!("{} failed: {}; retrying in {}ms (retry {} of {})", task, 
+                      stderr.lines().next().unwrap_or_default(), delay, attempt, TMSADM_ARGS.retries);
+            thread::sleep(Duration::from_millis(delay));
+            continue;
+        }
+        command_failed(command, task, output.stderr, output.status);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// is_transient:
+// ---------------------------------------------------------------------------
+/** Return true if sqlite3's error message reports a locked or busy database,
+ * which may succeed if tried again.
+ */
+fn is_transient(stderr: &str) -> bool {
+    ["database is locked", "database table is locked", "database is busy"]
+        .iter().any(|m| stderr.contains(m))
+}
+
+// ---------------------------------------------------------------------------
+// forget_child:
+// ---------------------------------------------------------------------------
+/** Stop tracking a child process that has been waited on. */
+fn forget_child(pid: u32) {
+    CHILD_PIDS.lock().unwrap_or_else(|e| e.into_inner()).retain(|p| *p != pid);
+}
+
+// ---------------------------------------------------------------------------
+// kill_process:
+// ---------------------------------------------------------------------------
+/** Send SIGTERM to the process using the kill program, since this crate 
+ * doesn't allow unsafe code.
+ */
+fn kill_process(pid: u32) {
+    let _ = Command::new("kill").arg(pid.to_string())
+        .stdout(Stdio::null()).stderr(Stdio::null()).status();
+}
+
+// ---------------------------------------------------------------------------
+// install_interrupt_handler:
+// ---------------------------------------------------------------------------
+/** On SIGINT, terminate any running sqlite3 child, report that the operation
+ * was aborted and exit with EXIT_ABORTED.  A terminated sqlite3 never reaches
+ * COMMIT, so an open transaction is rolled back, and exiting releases the 
+ * lock file.
+ */
+fn install_interrupt_handler() {
+    let result = ctrlc::set_handler(|| {
+        let pids = CHILD_PIDS.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        for pid in pids {kill_process(pid);}
+        remove_key_file();
+        eprintln!("\naborted");
+        std::process::exit(EXIT_ABORTED);
+    });
+    if let Err(e) = result {
+        panic!("Unable to install interrupt handler: {}", e);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// stream_lines:
+// ---------------------------------------------------------------------------
+/** Copy the reader to the writer one line at a time, reusing a single line
+ * buffer so memory use doesn't grow with the size of the result set.  The
+ * number of json arrays seen, meaning lines that start with the opening 
+ * bracket of sqlite3's json array and its first row object, is returned.  
+ * Echoed SQL can't start that way, even if a line of it starts with a 
+ * bracket.
+ */
+fn stream_lines(mut reader: impl BufRead, out: &mut dyn Write) -> io::Result<usize> {
+    let mut line = Vec::new();
+    let mut json_rows = 0;
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {break;}
+        if line.starts_with(b"[{") {json_rows += 1;}
+        out.write_all(&line)?;
+    }
+    out.flush()?;
+    Ok(json_rows)
+}
+
+// ---------------------------------------------------------------------------
+// stream_json:
+// ---------------------------------------------------------------------------
+/** Stream sqlite3's json output, writing an empty array if the query 
+ * returned no rows.  Sqlite3 prints nothing at all in that case, though any
+ * echoed SQL statement still comes through.
+ */
+fn stream_json(reader: impl BufRead, out: &mut dyn Write) -> io::Result<()> {
+    if stream_lines(reader, out)? == 0 {
+        out.write_all(b"[]\n")?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// stream_json_rows:
+// ---------------------------------------------------------------------------
+/** Stream sqlite3's json output with each row object rewritten, with its keys
+ * sorted for --normalize-json and reserialized without whitespace for 
+ * --compact-json.  Sqlite3's layout of one row per line is kept, unless 
+ * --compact-json puts the whole array on one line.  Lines without a row, 
+ * such as echoed SQL, are copied as is, and an empty result is written as 
+ * an empty array.
+ */
+fn stream_json_rows(mut reader: impl BufRead, out: &mut dyn Write) -> io::Result<()> {
+    let mut rows = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {break;}
+        match json_row(&line) {
+            Some(row) => {
+                let row = if TMSADM_ARGS.normalize_json {normalize_row(row)} else {compact_row(row)};
+                out.write_all(if rows == 0 {b"["} else {json_separator().as_bytes()})?;
+                out.write_all(row.as_bytes())?;
+                rows += 1;
+            },
+            None => out.write_all(line.as_bytes())?,
+        }
+    }
+    out.write_all(if rows == 0 {b"[]\n"} else {b"]\n"})?;
+    out.flush()
+}
+
+// ---------------------------------------------------------------------------
+// compact_row:
+// ---------------------------------------------------------------------------
+/** Return the json row object reserialized without whitespace, or the row 
+ * unchanged if it doesn't parse.
+ */
+fn compact_row(row: &str) -> String {
+    match serde_json::from_str::<Value>(row) {
+        Ok(object) => object.to_string(),
+        Err(_) => row.to_string(),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// json_separator:
+// ---------------------------------------------------------------------------
+/** Return what separates the rows of a json array, which puts each row on its
+ * own line unless --compact-json is set.
+ */
+fn json_separator() -> &'static str {
+    if TMSADM_ARGS.compact_json {","} else {",\n"}
+}
+
+// ---------------------------------------------------------------------------
+// normalize_row:
+// ---------------------------------------------------------------------------
+/** Return the json row object with its keys in sorted order, or the row 
+ * unchanged if it doesn't parse.
+ */
+fn normalize_row(row: &str) -> String {
+    match serde_json::from_str::<Map<String, Value>>(row) {
+        Ok(object) => {
+            let sorted: BTreeMap<String, Value> = object.into_iter().collect();
+            serde_json::to_string(&sorted).unwrap_or_else(|_| row.to_string())
+        },
+        Err(_) => row.to_string(),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// stream_ndjson:
+// ---------------------------------------------------------------------------
+/** Convert sqlite3's json output to newline-delimited json by stripping the
+ * array punctuation from each row line, which leaves exactly one object per
+ * line.  Each line is flushed as it's written so the output works in a pipe.
+ * With --normalize-json, each object's keys are sorted.
+ */
+fn stream_ndjson(mut reader: impl BufRead, out: &mut dyn Write) -> io::Result<()> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {break;}
+        let row = match json_row(&line) {
+            Some(r) if TMSADM_ARGS.normalize_json => normalize_row(r),
+            Some(r) => r.to_string(),
+            None => continue,
+        };
+        out.write_all(row.as_bytes())?;
+        out.write_all(b"\n")?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// stream_yaml:
+// ---------------------------------------------------------------------------
+/** Convert sqlite3's json output to a yaml sequence of mappings, one per 
+ * row, writing each row as it's read.  An empty result is written as an 
+ * empty sequence.
+ */
+fn stream_yaml(mut reader: impl BufRead, out: &mut dyn Write) -> io::Result<()> {
+    let mut rows = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {break;}
+        let row = match json_row(&line) {
+            Some(r) => r,
+            None => continue,
+        };
+        let row: Map<String, Value> = serde_json::from_str(row)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let yaml = serde_yaml::to_string(&[row])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        out.write_all(yaml.as_bytes())?;
+        out.flush()?;
+        rows += 1;
+    }
+    if rows == 0 {out.write_all(b"[]\n")?;}
+    out.flush()
+}
+
+// ---------------------------------------------------------------------------
+// stream_authorized_keys:
+// ---------------------------------------------------------------------------
+/** Convert sqlite3's json output for pubkeys into OpenSSH authorized_keys 
+ * lines.  Each line contains the key type and base64 key body from the 
+ * public_key column followed by a tms_user_id@host comment.  Any comment
+ * stored with the key is replaced.
+ */
+fn stream_authorized_keys(mut reader: impl BufRead, out: &mut dyn Write) -> io::Result<()> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {break;}
+        let row = match json_row(&line) {
+            Some(r) => r,
+            None => continue,
+        };
+        let row: Map<String, Value> = serde_json::from_str(row)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let field = |name: &str| row.get(name).and_then(Value::as_str).unwrap_or_default();
+        let key: Vec<&str> = field("public_key").split_whitespace().take(2).collect();
+        if key.len() < 2 {
+            eprintln!("Skipping malformed public key in pubkey record {}.", 
+                      row.get("id").unwrap_or(&Value::Null));
+            continue;
+        }
+        writeln!(out, "{} {}@{}", key.join(" "), field("tms_user_id"), field("host"))?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// stream_template:
+// ---------------------------------------------------------------------------
+/** Convert sqlite3's json output into a line per row made from the --template,
+ * replacing each placeholder with the row's value for the column.  Strings 
+ * are written without quotes and nulls as nothing.
+ */
+fn stream_template(mut reader: impl BufRead, out: &mut dyn Write) -> io::Result<()> {
+    let parts = TMSADM_ARGS.template.as_deref().and_then(|t| template_parts(t).ok()).unwrap_or_default();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {break;}
+        let row = match json_row(&line) {
+            Some(r) => r,
+            None => continue,
+        };
+        let row: Map<String, Value> = serde_json::from_str(row)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        writeln!(out, "{}", fill_template(&parts, &row))?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// fill_template:
+// ---------------------------------------------------------------------------
+/** Return the template parts with each placeholder replaced by the row's 
+ * value for the column, where strings lose their quotes and nulls and 
+ * missing columns are empty.
+ */
+fn fill_template(parts: &[TemplatePart], row: &Map<String, Value>) -> String {
+    let mut text = String::new();
+    for part in parts {
+        match part {
+            TemplatePart::Text(t) => text += t,
+            TemplatePart::Column(c) => match row.get(c) {
+                Some(Value::String(v)) => text += v,
+                Some(Value::Null) | None => (),
+                Some(v) => text += &v.to_string(),
+            },
+        }
+    }
+    text
+}
+
+// ---------------------------------------------------------------------------
+// stream_fingerprints:
+// ---------------------------------------------------------------------------
+/** Stream sqlite3's json output for pubkeys, adding a fingerprint column to
+ * each row.  The array punctuation around each row is kept for the json 
+ * format and dropped for ndjson.  The first echo_lines lines are echoed SQL
+ * and are passed through as is; any other line that isn't a row is an error.
+ */
+fn stream_fingerprints(mut reader: impl BufRead, out: &mut dyn Write, echo_lines: usize) -> io::Result<()> {
+    let ndjson = TMSADM_ARGS.format == TmsFormat::ndjson;
+    let mut rows = 0;
+    let mut lines = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {break;}
+        lines += 1;
+        let row = match json_row(&line) {
+            Some(r) => r,
+            None if lines <= echo_lines => {
+                out.write_all(line.as_bytes())?;
+                continue;
+            },
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, 
+                format!("line {} of sqlite3's output isn't a json row", lines))),
+        };
+        let mut obj: Map<String, Value> = serde_json::from_str(row)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let key = obj.get("public_key").and_then(Value::as_str).unwrap_or_default();
+        let fingerprint = match PublicKey::from_openssh(key) {
+            Ok(k) => Value::String(k.fingerprint(HashAlg::Sha256).to_string()),
+            Err(_) => Value::Null,
+        };
+        obj.insert("fingerprint".to_string(), fingerprint);
+        let obj = Value::Object(obj).to_string();
+        rows += 1;
+        if ndjson {
+            writeln!(out, "{}", obj)?;
+        } else {
+            // The row is a slice of the line, so its offsets locate the punctuation.
+            let start = row.as_ptr() as usize - line.as_ptr() as usize;
+            let end = start + row.len();
+            write!(out, "{}{}{}", &line[..start], obj, &line[end..])?;
+        }
+        out.flush()?;
+    }
+    if rows == 0 && !ndjson {out.write_all(b"[]\n")?;}
+    out.flush()
+}
+
+// ---------------------------------------------------------------------------
+// stream_col_transforms:
+// ---------------------------------------------------------------------------
+/** Stream sqlite3's json output, applying the --col-transform transforms to
+ * each row's values and then the --sanitize rules.  Localtime conversions 
+ * have already been made by the query, so only the other transforms are 
+ * applied here.  As with 
+ * fingerprints, the array punctuation is kept for json and dropped for 
+ * ndjson, and with --normalize-json each row's keys are sorted.
+ * 
+ * The first echo_lines lines are echoed SQL and are copied as is.  Any other
+ * line that isn't a row is an error rather than being passed through, since 
+ * it would escape the transforms and could expose a sanitized column.
  */
-fn get_absolute_path(path: &str) -> String {
-    // Replace ~ and environment variable values if possible.
-    // On error, return the string version of the original path.
-    let s = match shellexpand::full(path) {
-        Ok(x) => x,
-        Err(_) => return path.to_owned(),
-    };
+fn stream_col_transforms(mut reader: impl BufRead, out: &mut dyn Write, echo_lines: usize) -> io::Result<()> {
+    let ndjson = TMSADM_ARGS.format == TmsFormat::ndjson;
+    let mut rows = 0;
+    let mut lines = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {break;}
+        lines += 1;
+        let row = match json_row(&line) {
+            Some(r) => r,
+            None if lines <= echo_lines => {
+                if !ndjson {out.write_all(line.as_bytes())?;}
+                continue;
+            },
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, 
+                format!("line {} of sqlite3's output isn't a json row", lines))),
+        };
+        let mut obj: Map<String, Value> = serde_json::from_str(row)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        for (column, transform) in &TMSADM_ARGS.col_transform {
+            if let Some(value) = obj.get_mut(column) {transform_value(value, transform);}
+        }
+        if TMSADM_ARGS.sanitize {
+            for (column, rule) in SANITIZE_RULES.iter() {
+                match rule {
+                    SanitizeRule::drop => {obj.shift_remove(column);},
+                    SanitizeRule::hash => if let Some(value) = obj.get_mut(column) {hash_value(value)},
+                }
+            }
+        }
+        let mut obj = Value::Object(obj).to_string();
+        if TMSADM_ARGS.normalize_json {obj = normalize_row(&obj);}
+        rows += 1;
+        if ndjson {
+            writeln!(out, "{}", obj)?;
+        } else {
+            // The row is a slice of the line, so its offsets locate the punctuation.
+            let start = row.as_ptr() as usize - line.as_ptr() as usize;
+            let end = start + row.len();
+            write!(out, "{}{}{}", &line[..start], obj, &line[end..])?;
+        }
+        out.flush()?;
+    }
+    if rows == 0 && !ndjson {out.write_all(b"[]\n")?;}
+    out.flush()
+}
 
-    // Convert to absolute path if necessary.
-    // Return original input on error.
-    let p = Path::new(s.deref());
-    let p1 = match p.absolutize() {
-        Ok(x) => x,
-        Err(_) => return path.to_owned(),
+// ---------------------------------------------------------------------------
+// transform_value:
+// ---------------------------------------------------------------------------
+/** Apply a --col-transform to a column's value.  Nulls are left alone, a 
+ * value that isn't a public key fingerprints to null and truncation applies
+ * to the value's text.
+ */
+fn transform_value(value: &mut Value, transform: &ColTransform) {
+    if value.is_null() {return;}
+    let text = match &*value {
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
     };
-    let p2 = match p1.to_str() {
-        Some(x) => x,
-        None => return path.to_owned(),
+    *value = match transform {
+        ColTransform::fingerprint => match PublicKey::from_openssh(&text) {
+            Ok(k) => Value::String(k.fingerprint(HashAlg::Sha256).to_string()),
+            Err(_) => Value::Null,
+        },
+        ColTransform::truncate(width) => Value::String(truncate_cell(&text, *width)),
+        ColTransform::redact => Value::String(REDACTED.to_string()),
+        ColTransform::localtime => return,
     };
-
-    p2.to_owned()
 }
 
 // ---------------------------------------------------------------------------
-// run_command:
+// hash_value:
 // ---------------------------------------------------------------------------
-/** Make an operating system call and return an Output object that contains
- * the result code and stdout/stderr as vectors.  If the command cannot be run
- * or if it runs and returns a non-zero exit code, this method writes the log 
- * before returning an error.  
- * 
- * The task parameter prefixes any error message logged or returned by this
- * function.
- * 
- * The only way Ok is returned is when the command has a zero exit code.
- */
-#[allow(clippy::needless_return)]
-fn run_command(mut command: Command, task: &str) {
-    // Capture all output.
-    //command.stdout(Stdio::piped());
-    //command.stderr(Stdio::piped());
- 
-    // Return an output object or error.
-    // Errors are logged before returning.
-    match command.execute_output() {
-        Ok(o) => {
-            // Check for success here.
-            if o.status.success() {}
-                else {
-                    let msg = task.to_string() + ": " + 
-                        &String::from_utf8(o.stderr)
-                        .unwrap_or(run_command_emsg(command, o.status));
-                    panic!("{}", msg);
-                };
-        },
-        Err(e) => {
-            let msg = task.to_string() + ": " + &e.to_string();
-            panic!("{}", msg);
-        },
+/** Replace a column's value with the SHA-256 hex digest of its text, so 
+ * that equal values still match once sanitized.  Nulls are left alone.
+ */
+fn hash_value(value: &mut Value) {
+    let text = match &*value {
+        Value::Null => return,
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
     };
+    let digest = Sha256::digest(text.as_bytes());
+    *value = Value::String(digest.iter().map(|b| format!("{:02x}", b)).collect());
+}
+
+// ---------------------------------------------------------------------------
+// stream_json_typed:
+// ---------------------------------------------------------------------------
+/** Convert sqlite3's json output into an object with a columns array, which
+ * gives the name and declared type of each column, and a rows array holding
+ * the records.  The rows are written as they're read.
+ */
+fn stream_json_typed(mut reader: impl BufRead, out: &mut dyn Write, 
+                     columns: &[(String, String)]) -> io::Result<()> {
+    let columns: Vec<Value> = columns.iter()
+        .map(|(name, ty)| serde_json::json!({"name": name, "type": ty})).collect();
+    write!(out, "{{\"columns\":{},\n\"rows\":[", Value::Array(columns))?;
+    let mut rows = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {break;}
+        let row = match json_row(&line) {
+            Some(r) => r,
+            None => continue,
+        };
+        write!(out, "{}{}", if rows == 0 {"\n"} else {",\n"}, row)?;
+        rows += 1;
+    }
+    out.write_all(b"]}\n")?;
+    out.flush()
+}
+
+// ---------------------------------------------------------------------------
+// stream_truncated_list:
+// ---------------------------------------------------------------------------
+/** Render sqlite3's json output in sqlite3's default list format, with 
+ * values separated by "|" or the --separator and NULLs shown as the 
+ * --null-token, truncating each cell to the --max-width.  The
+ * header line is taken from the first row's column names unless headers are
+ * turned off.  Lines that don't contain a row, such as echoed SQL, are 
+ * passed through.
+ */
+fn stream_truncated_list(mut reader: impl BufRead, out: &mut dyn Write) -> io::Result<()> {
+    let width = TMSADM_ARGS.max_width.unwrap_or(usize::MAX);
+    let sep = TMSADM_ARGS.separator.as_deref().unwrap_or("|");
+    let null = TMSADM_ARGS.null_token.as_deref().unwrap_or_default();
+    let mut header_done = TMSADM_ARGS.header_off || CONTINUED_PAGE.load(Ordering::Relaxed);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {break;}
+        let row = match json_row(&line) {
+            Some(r) => r,
+            None => {
+                out.write_all(line.as_bytes())?;
+                continue;
+            },
+        };
+        let row: Map<String, Value> = serde_json::from_str(row)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if !header_done {
+            let cols: Vec<String> = row.keys().map(|k| truncate_cell(k, width)).collect();
+            writeln!(out, "{}", cols.join(sep))?;
+            header_done = true;
+        }
+        let cells: Vec<String> = row.values().map(|v| {
+            let cell = match v {
+                Value::Null => null.to_string(),
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            truncate_cell(&cell, width)
+        }).collect();
+        writeln!(out, "{}", cells.join(sep))?;
+    }
+    out.flush()
+}
+
+// ---------------------------------------------------------------------------
+// truncate_cell:
+// ---------------------------------------------------------------------------
+/** Truncate the cell to at most width characters, ending in an ellipsis if
+ * anything was removed.
+ */
+fn truncate_cell(cell: &str, width: usize) -> String {
+    if cell.chars().count() <= width {return cell.to_string();}
+    if width == 0 {return String::new();}
+    let mut s: String = cell.chars().take(width - 1).collect();
+    s.push('…');
+    s
+}
+
+// ---------------------------------------------------------------------------
+// json_row:
+// ---------------------------------------------------------------------------
+/** Return the row object on a line of sqlite3's json output, if any.  Sqlite3
+ * writes each row object on its own line, with the array's opening bracket
+ * before the first row, a comma after each row and the closing bracket after
+ * the last.  Other lines, such as echoed SQL, don't contain a row.
+ */
+fn json_row(line: &str) -> Option<&str> {
+    let mut row = line.trim();
+    row = row.strip_prefix('[').unwrap_or(row);
+    if !row.starts_with('{') {return None;}
+    row = row.strip_suffix(']').unwrap_or(row);
+    row = row.strip_suffix(',').unwrap_or(row);
+    Some(row)
+}
+
+// ---------------------------------------------------------------------------
+// format_command:
+// ---------------------------------------------------------------------------
+/** Return the command's program and arguments as a single line that can be
+ * copied and pasted into a POSIX shell.  Arguments containing anything other
+ * than a conservative set of characters are single quoted.
+ */
+fn format_command(command: &Command) -> String {
+    let mut parts = vec![shell_quote(&command.get_program().to_string_lossy())];
+    for arg in command.get_args() {
+        parts.push(shell_quote(&arg.to_string_lossy()));
+    }
+    parts.join(" ")
+}
+
+// ---------------------------------------------------------------------------
+// shell_quote:
+// ---------------------------------------------------------------------------
+/** Single quote the string for the shell if necessary. */
+fn shell_quote(s: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !s.is_empty() && s.chars().all(safe) {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// command_failed:
+// ---------------------------------------------------------------------------
+/** Panic with the stderr of a command that returned a non-zero exit code, 
+ * prefixed by the task.  A locked database usually just means that the TMS
+ * Server is writing, so that error gets a hint on how to wait it out.
+ */
+fn command_failed(command: &Command, task: &str, stderr: Vec<u8>, status: ExitStatus) -> ! {
+    let mut msg = task.to_string() + ": " + 
+        &String::from_utf8(stderr).unwrap_or(run_command_emsg(command, status));
+    if msg.contains("database is locked") {
+        msg += "\nThe database is locked by another connection, most likely the TMS Server \
+                writing to it. Try again or use --busy-timeout to wait for the lock.";
+    }
+    SQLITE_FAILED.store(true, Ordering::Relaxed);
+    panic!("{}", msg);
 }
 
 // ---------------------------------------------------------------------------
 // run_command_emsg:
 // ---------------------------------------------------------------------------
 /** Return a message for commands that return non-zero exit codes. */
-fn run_command_emsg(command: Command, status: ExitStatus) -> String {
+fn run_command_emsg(command: &Command, status: ExitStatus) -> String {
     "Unknown error condition returned by command: ".to_owned() + 
     command.get_program().to_str().unwrap_or("unknown") +
     " with exit status: " + &status.to_string()
-}
\ No newline at end of file
+}
+// ***************************************************************************
+//                                  Tests
+// ***************************************************************************
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    // The arguments that TMSADM_ARGS is parsed from in tests.  Deleted records
+    // are included so that building a WHERE clause doesn't read the database,
+    // and the limit is the --all-pages page size.
+    pub const TEST_ARGS: &[&str] = &["tmsadm", "-o", "LIST", "-r", "pubkey", "--include-deleted", 
+                                     "--retries", "2", "--user", "O'Brien", "--host", "h;1", 
+                                     "--tenant", "it's", "--limit", "3"];
+
+    // Held by the tests that count rows or page, since ROW_COUNT and 
+    // CONTINUED_PAGE are shared by all threads.
+    static ROW_COUNT_LOCK: Mutex<()> = Mutex::new(());
+
+    // -----------------------------------------------------------------------
+    // FakeRunner:
+    // -----------------------------------------------------------------------
+    // A command's stdout, stderr and exit code, or None if it can't be started.
+    type Response = Option<(&'static str, &'static str, i32)>;
+
+    /** A command runner that records the commands it's given and answers each
+     * with the next of its responses.
+     */
+    struct FakeRunner {
+        responses: Mutex<Vec<Response>>,
+        commands: Mutex<Vec<String>>,
+    }
+
+    impl CommandRunner for FakeRunner {
+        fn run(&self, command: &mut Command, _input: Option<&str>,
+               reader: &mut dyn FnMut(&mut dyn BufRead) -> io::Result<()>) -> io::Result<CommandOutput> {
+            self.commands.lock().unwrap().push(format_command(command));
+            match self.responses.lock().unwrap().remove(0) {
+                None => Err(io::Error::new(io::ErrorKind::NotFound, "No such file or directory")),
+                Some((stdout, stderr, code)) => {
+                    reader(&mut stdout.as_bytes())?;
+                    Ok(CommandOutput {status: ExitStatus::from_raw(code << 8), stderr: stderr.as_bytes().to_vec()})
+                },
+            }
+        }
+    }
+
+    /** Return a fake runner with the responses, which lives for the rest of 
+     * the tests so that it can be installed as a thread's runner.
+     */
+    fn fake_runner(responses: Vec<Response>) -> &'static FakeRunner {
+        Box::leak(Box::new(FakeRunner {responses: Mutex::new(responses), commands: Mutex::new(Vec::new())}))
+    }
+
+    /** Run an sqlite3 command with the runner, returning its stdout. */
+    fn run_fake(runner: &FakeRunner, task: &str) -> io::Result<Vec<u8>> {
+        let mut stdout = Vec::new();
+        run_sqlite3(runner, &mut Command::new(SQLITE3), task, None, 
+                    &mut |r| r.read_to_end(&mut stdout).map(|_| ()))?;
+        Ok(stdout)
+    }
+
+    // -----------------------------------------------------------------------
+    // SyntheticRunner:
+    // -----------------------------------------------------------------------
+    /** A command runner whose command writes the json output of a query over
+     * a table of the given number of rows, each over 1KB, generating the rows
+     * as they're read.
+     */
+    struct SyntheticRunner {
+        rows: usize,
+    }
+
+    struct SyntheticRows {
+        rows: usize,
+        next: usize,
+        line: Vec<u8>,
+        pos: usize,
+    }
+
+    impl Read for SyntheticRows {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.pos == self.line.len() {
+                if self.next == self.rows {return Ok(0);}
+                let open = if self.next == 0 {"["} else {""};
+                let close = if self.next + 1 == self.rows {"]"} else {","};
+                self.line = format!("{}{{\"id\":{},\"public_key\":\"{}\"}}{}\n", open, self.next, 
+                                    "A".repeat(1000), close).into_bytes();
+                self.pos = 0;
+                self.next += 1;
+            }
+            let n = buf.len().min(self.line.len() - self.pos);
+            buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    impl CommandRunner for SyntheticRunner {
+        fn run(&self, _command: &mut Command, _input: Option<&str>,
+               reader: &mut dyn FnMut(&mut dyn BufRead) -> io::Result<()>) -> io::Result<CommandOutput> {
+            let rows = SyntheticRows {rows: self.rows, next: 0, line: Vec::new(), pos: 0};
+            reader(&mut BufReader::new(rows))?;
+            Ok(CommandOutput {status: ExitStatus::from_raw(0), stderr: Vec::new()})
+        }
+    }
+
+    /** A writer that only counts the bytes written to it. */
+    struct CountingSink(usize);
+
+    impl Write for CountingSink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /** Return the most memory the process has used so far, in bytes. */
+    fn peak_memory() -> usize {
+        let status = fs::read_to_string("/proc/self/status").unwrap();
+        let line = status.lines().find(|l| l.starts_with("VmHWM:")).unwrap();
+        line.split_whitespace().nth(1).unwrap().parse::<usize>().unwrap() * 1024
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn large_listing_streams_in_bounded_memory() {
+        const ROWS: usize = 128 * 1024;
+        COMMAND_RUNNER.with(|r| *r.borrow_mut() = &SyntheticRunner {rows: ROWS});
+        let _counting = ROW_COUNT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let counted = ROW_COUNT.load(Ordering::Relaxed);
+        let before = peak_memory();
+        let mut out = CountingSink(0);
+        run_command_to(Command::new(SQLITE3), "LIST test", true, &mut out);
+        assert!(out.0 > 128 * 1024 * 1024, "only {} bytes were written", out.0);
+        let growth = peak_memory().saturating_sub(before);
+        assert!(growth < 32 * 1024 * 1024, "peak memory grew by {} bytes", growth);
+        assert_eq!(ROW_COUNT.load(Ordering::Relaxed) - counted, ROWS as u64);
+    }
+
+    #[test]
+    fn sql_quote_doubles_single_quotes() {
+        assert_eq!(sql_quote("bud"), "'bud'");
+        assert_eq!(sql_quote("O'Brien"), "'O''Brien'");
+        assert_eq!(sql_quote("'"), "''''");
+        assert_eq!(sql_quote("a;b"), "'a;b'");
+    }
+
+    #[test]
+    fn shortcut_filters_are_quoted() {
+        let wh = make_where_clause();
+        assert_eq!(wh, "WHERE tms_user_id = 'O''Brien' AND host = 'h;1' AND tenant = 'it''s'");
+        check_sqlwhere(&wh);
+    }
+
+    #[test]
+    fn sqlwhere_allows_quoted_semicolons_and_quotes() {
+        check_sqlwhere("WHERE host = 'a;b'");
+        check_sqlwhere("WHERE tms_user_id = 'O''Brien'");
+        check_sqlwhere("WHERE \"odd;name\" = 1");
+        check_sqlwhere("WHERE host = 'say \"hi\"'");
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot contain a semicolon outside of quotes")]
+    fn sqlwhere_rejects_embedded_semicolon() {
+        check_sqlwhere("WHERE id = 1; DROP TABLE pubkeys");
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot contain a semicolon outside of quotes")]
+    fn sqlwhere_rejects_trailing_semicolon() {
+        check_sqlwhere("WHERE id = 1;");
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot contain a semicolon outside of quotes")]
+    fn sqlwhere_rejects_semicolon_after_quotes() {
+        check_sqlwhere("WHERE tms_user_id = 'O''Brien';");
+    }
+
+    #[test]
+    #[should_panic(expected = "has unbalanced quotes")]
+    fn sqlwhere_rejects_unclosed_string() {
+        check_sqlwhere("WHERE tms_user_id = 'bud");
+    }
+
+    #[test]
+    #[should_panic(expected = "has unbalanced quotes")]
+    fn sqlwhere_rejects_unclosed_escaped_string() {
+        check_sqlwhere("WHERE tms_user_id = 'O''Brien");
+    }
+
+    #[test]
+    #[should_panic(expected = "has unbalanced quotes")]
+    fn sqlwhere_rejects_unclosed_identifier() {
+        check_sqlwhere("WHERE \"host = 'h'");
+    }
+
+    /** Return what stream_json writes for sqlite3's output. */
+    fn json_stream(output: &str) -> String {
+        let mut out = Vec::new();
+        stream_json(output.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn empty_json_result_is_an_empty_array() {
+        assert_eq!(json_stream(""), "[]\n");
+    }
+
+    #[test]
+    fn empty_json_result_follows_echoed_sql() {
+        let echo = "SELECT * FROM pubkeys WHERE id = 1;\n";
+        assert_eq!(json_stream(echo), format!("{}[]\n", echo));
+        let echo = "SELECT * FROM pubkeys WHERE\n[id] IN (1, 2);\n";
+        assert_eq!(json_stream(echo), format!("{}[]\n", echo));
+    }
+
+    #[test]
+    fn json_rows_are_copied_as_is() {
+        let rows = "[{\"id\":1},\n{\"id\":2}]\n";
+        assert_eq!(json_stream(rows), rows);
+        let echoed = format!("SELECT * FROM pubkeys;\n{}", rows);
+        assert_eq!(json_stream(&echoed), echoed);
+    }
+
+    /** Return what stream_col_transforms writes for sqlite3's output, or the error. */
+    fn col_transform_stream(output: &str, echo_lines: usize) -> io::Result<String> {
+        let mut out = Vec::new();
+        stream_col_transforms(output.as_bytes(), &mut out, echo_lines)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn col_transforms_refuse_list_mode_lines() {
+        let listed = "1|client1|SECRET1\n";
+        let e = col_transform_stream(listed, 0).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(!e.to_string().contains("SECRET1"));
+    }
+
+    #[test]
+    fn col_transforms_copy_echoed_sql() {
+        let echoed = "SELECT * FROM clients;\n[{\"id\":1}]\n";
+        assert_eq!(col_transform_stream(echoed, 1).unwrap(), echoed);
+        assert!(col_transform_stream(echoed, 0).is_err());
+        assert_eq!(col_transform_stream("", 0).unwrap(), "[]\n");
+    }
+
+    #[test]
+    fn fingerprints_refuse_list_mode_lines() {
+        let mut out = Vec::new();
+        let e = stream_fingerprints("1|ssh-ed25519 AAAA|bud\n".as_bytes(), &mut out, 0).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn fingerprints_are_null_for_unparsable_keys() {
+        let mut out = Vec::new();
+        stream_fingerprints("[{\"id\":1,\"public_key\":\"junk\"}]\n".as_bytes(), &mut out, 0).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "[{\"id\":1,\"public_key\":\"junk\",\"fingerprint\":null}]\n");
+    }
+
+    #[test]
+    fn thread_runner_runs_commands() {
+        let runner = fake_runner(vec![Some(("42\n", "", 0))]);
+        COMMAND_RUNNER.with(|r| *r.borrow_mut() = runner);
+        assert_eq!(run_capture("SELECT 42", "COUNT test"), "42\n");
+        assert!(runner.commands.lock().unwrap()[0].ends_with(" 'SELECT 42'"));
+    }
+
+    #[test]
+    #[should_panic(expected = "LIST test: Error: no such table: pubkeys")]
+    fn nonzero_exit_fails_with_stderr() {
+        let runner = fake_runner(vec![Some(("", "Error: no such table: pubkeys\n", 1))]);
+        let _ = run_fake(runner, "LIST test");
+    }
+
+    #[test]
+    fn spawn_failure_is_returned() {
+        let runner = fake_runner(vec![None]);
+        let e = run_fake(runner, "LIST test").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        assert_eq!(runner.commands.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn process_runner_returns_spawn_failure() {
+        let result = ProcessRunner.run(&mut Command::new("/nonexistent/sqlite3"), None, &mut |_| Ok(()));
+        assert_eq!(result.err().map(|e| e.kind()), Some(io::ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn transient_failure_is_retried() {
+        let runner = fake_runner(vec![Some(("", "Error: database is locked\n", 5)), Some(("1\n", "", 0))]);
+        assert_eq!(run_fake(runner, "COUNT test").unwrap(), b"1\n");
+        assert_eq!(runner.commands.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "LIST test: Error: no such column: x")]
+    fn other_failures_are_not_retried() {
+        let runner = fake_runner(vec![Some(("", "Error: no such column: x\n", 1)), Some(("", "", 0))]);
+        let _ = run_fake(runner, "LIST test");
+    }
+
+    #[test]
+    #[should_panic(expected = "Try again or use --busy-timeout to wait for the lock.")]
+    fn locked_database_gets_a_hint() {
+        let locked = Some(("", "Error: database is locked\n", 5));
+        let runner = fake_runner(vec![locked, locked, locked]);
+        let _ = run_fake(runner, "DELETE test");
+    }
+
+    #[test]
+    #[should_panic(expected = "Batch commands cannot read stdin, see line 2 of")]
+    fn batch_commands_cannot_read_stdin() {
+        let path = std::env::temp_dir().join(format!("tmsadm-test-batch-{}", std::process::id()));
+        fs::write(&path, "list pubkey\nimport pubkey --input -\n").unwrap();
+        process_batch(&path.to_string_lossy(), None);
+    }
+
+    #[test]
+    fn other_files_are_not_an_inherited_lock() {
+        let path = std::env::temp_dir().join(format!("tmsadm-test-lock-{}", std::process::id()));
+        fs::write(&path, "").unwrap();
+        assert!(inherited_lock(&path.to_string_lossy()).is_none());
+        fs::remove_file(&path).unwrap();
+    }
+
+    /** Return the json object as a map. */
+    fn json_map(json: &str) -> Map<String, Value> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    /** Return the column names. */
+    fn names(columns: &[&str]) -> Vec<String> {
+        columns.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn inserts_only_ignore_unique_conflicts() {
+        let row = json_map(r#"{"id":1,"host":"O'Brien","key_bits":256,"ok":true,"gone":null}"#);
+        let columns = names(&["id", "host", "key_bits", "ok", "gone"]);
+        assert_eq!(make_insert_sql("pubkeys", &row, &columns, &[]).unwrap(), 
+                   "INSERT INTO pubkeys (id, host, key_bits, ok, gone) VALUES (1, 'O''Brien', 256, 1, NULL) ON CONFLICT DO NOTHING;\n");
+    }
+
+    #[test]
+    fn inserts_need_required_columns() {
+        let (columns, required) = (names(&["id", "public_key"]), names(&["public_key"]));
+        let missing = json_map(r#"{"id":1}"#);
+        assert_eq!(make_insert_sql("pubkeys", &missing, &columns, &required).unwrap_err(), 
+                   "column public_key is required");
+        let null = json_map(r#"{"id":1,"public_key":null}"#);
+        assert_eq!(make_insert_sql("pubkeys", &null, &columns, &required).unwrap_err(), 
+                   "column public_key is required");
+    }
+
+    #[test]
+    fn inserts_need_scalars_and_identifiers() {
+        let columns = names(&["id"]);
+        let nested = json_map(r#"{"id":[1]}"#);
+        assert_eq!(make_insert_sql("pubkeys", &nested, &columns, &[]).unwrap_err(), 
+                   "column id must have a scalar value");
+        let bad = json_map(r#"{"id; DROP":1}"#);
+        assert_eq!(make_insert_sql("pubkeys", &bad, &columns, &[]).unwrap_err(), "invalid column name: id; DROP");
+    }
+
+    #[test]
+    fn inserts_need_known_columns() {
+        let row = json_map(r#"{"id":1,"bogus":1}"#);
+        assert_eq!(make_insert_sql("pubkeys", &row, &names(&["id"]), &[]).unwrap_err(), 
+                   "table pubkeys has no column named bogus");
+    }
+
+    #[test]
+    fn pages_start_after_the_last_id() {
+        let runner = fake_runner(vec![Some(("3\n", "", 0)), Some(("[{\"id\":1},\n{\"id\":2},\n{\"id\":3}]\n", "", 0)),
+                                      Some(("5\n", "", 0)), Some(("[{\"id\":5}]\n", "", 0))]);
+        COMMAND_RUNNER.with(|r| *r.borrow_mut() = runner);
+        let _counting = ROW_COUNT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut out = Vec::new();
+        run_pages(&TmsResource::pubkey, "LIST test", &mut out);
+        assert_eq!(String::from_utf8(out).unwrap(), "[{\"id\":1},\n{\"id\":2},\n{\"id\":3},\n{\"id\":5}]\n");
+        let commands = runner.commands.lock().unwrap();
+        assert_eq!(commands.len(), 4);
+        assert!(commands[0].contains(" ORDER BY id LIMIT 3)"));
+        assert!(commands[1].contains(" AND id <= 3 ORDER BY id'"));
+        assert!(commands[2].contains(" AND id > 3 ORDER BY id LIMIT 3)"));
+        assert!(commands[3].contains(" AND id > 3 AND id <= 5 ORDER BY id'"));
+        assert!(!commands.iter().any(|c| c.contains("COUNT(*)") || c.contains("OFFSET")));
+    }
+
+    #[test]
+    fn pages_stop_when_no_ids_are_left() {
+        let runner = fake_runner(vec![Some(("3\n", "", 0)), Some(("[{\"id\":1},\n{\"id\":2},\n{\"id\":3}]\n", "", 0)),
+                                      Some(("\n", "", 0))]);
+        COMMAND_RUNNER.with(|r| *r.borrow_mut() = runner);
+        let _counting = ROW_COUNT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut out = Vec::new();
+        run_pages(&TmsResource::pubkey, "LIST test", &mut out);
+        assert_eq!(String::from_utf8(out).unwrap(), "[{\"id\":1},\n{\"id\":2},\n{\"id\":3}]\n");
+        assert_eq!(runner.commands.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn unarchived_deletes_use_the_condition() {
+        assert_eq!(archived_delete_sql("delegations", "tms_user_id = 'bud'"), 
+                   "DELETE FROM delegations WHERE tms_user_id = 'bud'");
+    }
+
+    #[test]
+    fn sql_tokens_split_quotes_numbers_and_symbols() {
+        use SqlToken::*;
+        let tokens: Vec<SqlToken> = sql_tokens("WHERE \"a\"\"b\" <= 'O''Brien;' AND x!=-1e5 'open")
+            .into_iter().map(|(_, t)| t).collect();
+        assert_eq!(tokens, vec![Word("WHERE".into()), Quoted {name: "a\"b".into(), closed: true}, 
+            Symbol("<=".into()), Literal {value: "O'Brien;".into(), closed: true}, Word("AND".into()), 
+            Word("x".into()), Symbol("!=".into()), Symbol("-".into()), Number("1e5".into()), 
+            Literal {value: "open".into(), closed: false}]);
+        let starts: Vec<usize> = sql_tokens("a = 'é' b").into_iter().map(|(s, _)| s).collect();
+        assert_eq!(starts, vec![0, 2, 4, 8]);
+    }
+
+    #[test]
+    fn strict_sql_allows_simple_conditions() {
+        let columns = vec!["id".to_string(), "host".to_string()];
+        check_strict_sql("WHERE id IN (1, -2) AND \"host\" LIKE 'a(b)--;' OR id <> 3", &columns);
+        check_strict_sql("WHERE NOT host IS NULL AND id BETWEEN 1 AND 3", &columns);
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't allow lower in the --sqlwhere clause")]
+    fn strict_sql_rejects_function_calls() {
+        check_strict_sql("WHERE lower (host) = 'h'", &["host".to_string()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't allow \"secret\" in the --sqlwhere clause")]
+    fn strict_sql_rejects_quoted_non_columns() {
+        check_strict_sql("WHERE \"secret\" = 1", &["host".to_string()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't allow -- in the --sqlwhere clause")]
+    fn strict_sql_rejects_comments() {
+        check_strict_sql("WHERE host = 'h' -- and more", &["host".to_string()]);
+    }
+
+    #[test]
+    fn sqlwhere_names_skip_literals_keywords_and_qualified_names() {
+        let clause = "WHERE hots = 'tenant' AND lower(\"Tenant\") IN (SELECT id FROM clients \
+                      WHERE clients.id = 1e3) OR x.y IS NOT NULL";
+        assert_eq!(sqlwhere_names(clause), vec!["hots", "Tenant", "id"]);
+    }
+
+    #[test]
+    fn db_timezones_are_utc_local_or_offsets() {
+        assert_eq!(parse_db_timezone("UTC"), Ok("utc".to_string()));
+        assert_eq!(parse_db_timezone("Local"), Ok("local".to_string()));
+        assert_eq!(parse_db_timezone("+05:30"), Ok("+05:30".to_string()));
+        assert_eq!(parse_db_timezone("-23:59"), Ok("-23:59".to_string()));
+        for tz in ["-24:00", "+05:60", "05:00", "+5:00", "+05:00:00", "America/Chicago"] {
+            assert!(parse_db_timezone(tz).is_err(), "{}", tz);
+        }
+    }
+
+    #[test]
+    fn iso8601_dates_and_timestamps_are_validated() {
+        for ts in ["2026-01-31", "2026-01-31T12:30", "2026-01-31 12:30:59", "2026-01-31T12:30:00.125Z", 
+                   "2026-01-31T23:59:60+05:30", "2026-01-31T00:00-00:00"] {
+            assert_eq!(parse_iso8601(ts), Ok(ts.to_string()));
+        }
+        for ts in ["2026-13-01", "2026-01-32", "2026-1-31", "26-01-31", "2026-01-31T24:00", "2026-01-31T12", 
+                   "2026-01-31T12:30+5", "2026-01-31T12:30.", "2026-01-31X12:30", "2026-01-31T12:30Zjunk"] {
+            assert!(parse_iso8601(ts).is_err(), "{}", ts);
+        }
+    }
+
+    #[test]
+    fn templates_split_into_text_and_columns() {
+        use TemplatePart::*;
+        assert_eq!(template_parts("{{id}} {id}:{ host }}}"), 
+                   Ok(vec![Text("{id} ".into()), Column("id".into()), Text(":".into()), Column("host".into()), 
+                           Text("}".into())]));
+        assert_eq!(template_parts(""), Ok(vec![]));
+        assert!(template_parts("{id").unwrap_err().starts_with("unclosed placeholder"));
+        assert!(template_parts("{ }").unwrap_err().starts_with("empty placeholder"));
+        assert!(template_parts("a}b").unwrap_err().starts_with("unmatched }"));
+    }
+
+    #[test]
+    fn templates_fill_in_row_values() {
+        let parts = template_parts("{id} {host}:{port}{missing}").unwrap();
+        let row = json_map("{\"id\":1,\"host\":\"h\",\"port\":null}");
+        assert_eq!(fill_template(&parts, &row), "1 h:");
+    }
+
+    #[test]
+    fn levenshtein_counts_edits() {
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("host", "hots"), 2);
+        assert_eq!(levenshtein("tenant", "tenant"), 0);
+    }
+
+    #[test]
+    fn uri_file_paths_are_decoded() {
+        assert_eq!(file_path("file:/opt/tms/my%20tms.db?mode=ro#frag"), "/opt/tms/my tms.db");
+        assert_eq!(file_path("file://localhost/opt/tms/tms.db"), "/opt/tms/tms.db");
+        assert_eq!(file_path("file:/opt/100%zz.db"), "/opt/100%zz.db");
+        assert_eq!(file_path("/opt/tms/a%20b.db"), "/opt/tms/a%20b.db");
+    }
+
+    #[test]
+    fn utc_timestamps_are_civil_dates() {
+        assert_eq!(format_utc(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_utc(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_utc(1767225599), "2025-12-31T23:59:59Z");
+        let now = utc_timestamp();
+        assert_eq!(now.len(), 20);
+        assert!(parse_iso8601(&now).is_ok());
+    }
+
+    #[test]
+    fn pragmas_are_validated() {
+        assert_eq!(parse_pragma(" PAGE_SIZE = 4096"), Ok(("page_size".to_string(), "4096".to_string())));
+        assert_eq!(parse_pragma("journal_mode=wal"), Ok(("journal_mode".to_string(), "WAL".to_string())));
+        assert_eq!(parse_pragma("user_version=-3"), Ok(("user_version".to_string(), "-3".to_string())));
+        assert_eq!(parse_pragma("page_size=1000"), Err("invalid value for page_size: 1000".to_string()));
+        assert!(parse_pragma("cache_size=1").unwrap_err().starts_with("unsupported pragma cache_size"));
+        assert!(parse_pragma("user_version").unwrap_err().starts_with("expected name=value"));
+    }
+
+    #[test]
+    fn json_rows_are_found_on_their_lines() {
+        assert_eq!(json_row("[{\"id\":1},\n"), Some("{\"id\":1}"));
+        assert_eq!(json_row("{\"id\":2},\n"), Some("{\"id\":2}"));
+        assert_eq!(json_row("{\"id\":3}]\n"), Some("{\"id\":3}"));
+        assert_eq!(json_row("[{\"id\":4}]"), Some("{\"id\":4}"));
+        assert_eq!(json_row("SELECT * FROM pubkeys;\n"), None);
+        assert_eq!(json_row("[]\n"), None);
+    }
+
+    /** sqlite3's json output for two rows after an echoed statement. */
+    const TWO_ROWS: &str = "SELECT * FROM pubkeys;\n[{\"id\": 1,\"host\":\"h\"},\n{\"id\":2,\"host\":null}]\n";
+
+    /** Return what the formatter writes for sqlite3's output. */
+    fn formatted(format: fn(&[u8], &mut dyn Write) -> io::Result<()>, output: &str) -> String {
+        let mut out = Vec::new();
+        format(output.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn lines_are_copied_and_arrays_counted() {
+        let mut out = Vec::new();
+        assert_eq!(stream_lines(TWO_ROWS.as_bytes(), &mut out).unwrap(), 1);
+        assert_eq!(String::from_utf8(out).unwrap(), TWO_ROWS);
+        assert_eq!(stream_lines("[\n{\"id\":1}\n".as_bytes(), &mut Vec::new()).unwrap(), 0);
+    }
+
+    #[test]
+    fn json_rows_are_compacted() {
+        assert_eq!(formatted(|r, o| stream_json_rows(r, o), TWO_ROWS), 
+                   "SELECT * FROM pubkeys;\n[{\"id\":1,\"host\":\"h\"},\n{\"id\":2,\"host\":null}]\n");
+        assert_eq!(formatted(|r, o| stream_json_rows(r, o), ""), "[]\n");
+    }
+
+    #[test]
+    fn ndjson_is_one_row_per_line() {
+        assert_eq!(formatted(|r, o| stream_ndjson(r, o), TWO_ROWS), 
+                   "{\"id\": 1,\"host\":\"h\"}\n{\"id\":2,\"host\":null}\n");
+        assert_eq!(formatted(|r, o| stream_ndjson(r, o), ""), "");
+    }
+
+    #[test]
+    fn yaml_is_a_sequence_of_mappings() {
+        assert_eq!(formatted(|r, o| stream_yaml(r, o), TWO_ROWS), "- id: 1\n  host: h\n- id: 2\n  host: null\n");
+        assert_eq!(formatted(|r, o| stream_yaml(r, o), ""), "[]\n");
+        let mut out = Vec::new();
+        assert!(stream_yaml("[{\"id\":\n".as_bytes(), &mut out).is_err());
+    }
+
+    #[test]
+    fn authorized_keys_get_user_and_host_comments() {
+        let output = "[{\"id\":1,\"public_key\":\"ssh-ed25519 AAAAC3 old comment\",\"tms_user_id\":\"bud\",\"host\":\"h\"},\n\
+                      {\"id\":2,\"public_key\":\"garbage\",\"tms_user_id\":\"bud\",\"host\":\"h\"}]\n";
+        assert_eq!(formatted(|r, o| stream_authorized_keys(r, o), output), "ssh-ed25519 AAAAC3 bud@h\n");
+    }
+
+    #[test]
+    fn typed_json_lists_columns_then_rows() {
+        let columns = vec![("id".to_string(), "INTEGER".to_string()), ("host".to_string(), "TEXT".to_string())];
+        let mut out = Vec::new();
+        stream_json_typed(TWO_ROWS.as_bytes(), &mut out, &columns).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), 
+                   "{\"columns\":[{\"name\":\"id\",\"type\":\"INTEGER\"},{\"name\":\"host\",\"type\":\"TEXT\"}],\n\
+                    \"rows\":[\n{\"id\": 1,\"host\":\"h\"},\n{\"id\":2,\"host\":null}]}\n");
+        let mut out = Vec::new();
+        stream_json_typed("".as_bytes(), &mut out, &[]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "{\"columns\":[],\n\"rows\":[]}\n");
+    }
+
+    #[test]
+    fn list_format_has_a_header_and_cells() {
+        let _paging = ROW_COUNT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        assert_eq!(formatted(|r, o| stream_truncated_list(r, o), TWO_ROWS), 
+                   "SELECT * FROM pubkeys;\nid|host\n1|h\n2|\n");
+    }
+
+    #[test]
+    fn cells_are_truncated_with_an_ellipsis() {
+        assert_eq!(truncate_cell("abcdef", 4), "abc\u{2026}");
+        assert_eq!(truncate_cell("ab", 4), "ab");
+        assert_eq!(truncate_cell("ab", 0), "");
+        assert_eq!(truncate_cell("éèêë", 3), "éè\u{2026}");
+    }
+
+    #[test]
+    fn thousands_are_grouped() {
+        assert_eq!(group_thousands(0), "0");
+        assert_eq!(group_thousands(999), "999");
+        assert_eq!(group_thousands(1000), "1,000");
+        assert_eq!(group_thousands(1234567), "1,234,567");
+    }
+
+    #[test]
+    fn previews_sample_the_given_selection() {
+        let runner = fake_runner(vec![Some(("[{\"id\":4}]\n", "", 0))]);
+        COMMAND_RUNNER.with(|r| *r.borrow_mut() = runner);
+        preview_selected("SELECT * FROM clients WHERE enabled != 0 ORDER BY id", 1);
+        let commands = runner.commands.lock().unwrap();
+        assert_eq!(commands.len(), 1);
+        assert!(commands[0].contains(&format!("SELECT * FROM (SELECT * FROM clients WHERE enabled != 0 ORDER BY id) \
+                                                LIMIT {}", TMSADM_ARGS.preview_rows)));
+    }
+}